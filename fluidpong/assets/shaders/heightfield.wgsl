@@ -0,0 +1,63 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+@group(2) @binding(0)
+var<uniform> screen_size: vec2<f32>;
+@group(2) @binding(1)
+var<uniform> grid_size: vec2<f32>;
+@group(2) @binding(2)
+var<uniform> cells: array<vec4<f32>, 4608>;
+// array strides must be multiple of 16.
+// cell.x : height above rest depth
+// cell.y : vx
+// cell.z : vy
+// cell.w : --
+const DEEP_COLOR: vec3<f32> = vec3<f32>(0.02, 0.12, 0.35);
+const SHALLOW_COLOR: vec3<f32> = vec3<f32>(0.10, 0.45, 0.70);
+const FOAM_COLOR: vec3<f32> = vec3<f32>(0.85, 0.92, 1.0);
+const LIGHT_DIR: vec3<f32> = vec3<f32>(-0.4, 0.5, 0.77);
+const NORMAL_STRENGTH: f32 = 4.0;
+const MAX_HEIGHT: f32 = 5.0;
+const MAX_SPEED: f32 = 200.0;
+const OPACITY: f32 = 0.8;
+
+fn height_at(cell: vec2<f32>) -> f32 {
+    var c: vec2<f32> = clamp(cell - vec2<f32>(0.5), vec2<f32>(0.0), grid_size - vec2<f32>(1.0));
+    var gx: u32 = u32(grid_size.x);
+    var gy: u32 = u32(grid_size.y);
+    var ix: u32 = u32(c.x);
+    var iy: u32 = u32(c.y);
+    var ix1: u32 = min(ix + 1, gx - 1);
+    var iy1: u32 = min(iy + 1, gy - 1);
+    var f: vec2<f32> = fract(c);
+
+    var h0: f32 = mix(cells[iy * gx + ix].x, cells[iy * gx + ix1].x, f.x);
+    var h1: f32 = mix(cells[iy1 * gx + ix].x, cells[iy1 * gx + ix1].x, f.x);
+    return mix(h0, h1, f.y);
+}
+
+@fragment
+fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
+    var pos: vec2<f32> = mesh.world_position.xy;
+    var cell: vec2<f32> = (pos + screen_size / 2.0) / screen_size * grid_size;
+
+    var h: f32 = height_at(cell);
+    var dhdx: f32 = (height_at(cell + vec2<f32>(1.0, 0.0)) - height_at(cell - vec2<f32>(1.0, 0.0))) / 2.0;
+    var dhdy: f32 = (height_at(cell + vec2<f32>(0.0, 1.0)) - height_at(cell - vec2<f32>(0.0, 1.0))) / 2.0;
+    var normal: vec3<f32> = normalize(vec3<f32>(-dhdx * NORMAL_STRENGTH, -dhdy * NORMAL_STRENGTH, 1.0));
+
+    var light: vec3<f32> = normalize(LIGHT_DIR);
+    var diffuse: f32 = max(dot(normal, light), 0.0);
+    var half_dir: vec3<f32> = normalize(light + vec3<f32>(0.0, 0.0, 1.0));
+    var specular: f32 = pow(max(dot(normal, half_dir), 0.0), 64.0);
+
+    var depth: f32 = clamp(h / MAX_HEIGHT * 0.5 + 0.5, 0.0, 1.0);
+    var base: vec3<f32> = mix(DEEP_COLOR, SHALLOW_COLOR, depth);
+
+    var ix: u32 = u32(clamp(cell.x, 0.0, grid_size.x - 1.0));
+    var iy: u32 = u32(clamp(cell.y, 0.0, grid_size.y - 1.0));
+    var c: vec4<f32> = cells[iy * u32(grid_size.x) + ix];
+    var foam: f32 = clamp(length(vec2<f32>(c.y, c.z)) / MAX_SPEED, 0.0, 1.0) * 0.5;
+
+    var color: vec3<f32> = mix(base * (0.4 + 0.6 * diffuse), FOAM_COLOR, foam) + vec3<f32>(specular);
+    return vec4<f32>(color, OPACITY);
+}
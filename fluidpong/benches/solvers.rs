@@ -0,0 +1,158 @@
+//! Benchmarks for the solvers' hot paths, run with `cargo bench`.
+//!
+//! The SPH benchmarks start from a fluid that a paddle has stirred for a
+//! second, so that particles are no longer on a grid.
+
+use bevy::math::Vec2;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use fluidpong::backend::{FluidBackend, FIXED_TIMESTEP};
+use fluidpong::ns::math::{fluid_step, GridSize};
+use fluidpong::simui::FluidSimVars;
+use fluidpong::sph::particle::Particle;
+use fluidpong::sph::spatial_grid::SpatialGrid2D;
+use fluidpong::{ns, sph};
+
+/// The defaults of a backend's simulation variables.
+fn simvars<B: FluidBackend>() -> FluidSimVars {
+    FluidSimVars::new(
+        B::simvars().into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+    )
+}
+
+fn ns_step(fluid: &mut ns::fluid::Fluid, simvars: &FluidSimVars, iter: u32) {
+    fluid_step(fluid, simvars.get("viscosity"), simvars.get("diffusion"), simvars.get("dt"), iter);
+}
+
+/// Points spread over the arena, to query or sample at.
+fn probes() -> Vec<Vec2> {
+    (0..64)
+        .map(|i| Vec2::new((i % 8) as f32 * 70.0 - 245.0, (i / 8) as f32 * 35.0 - 122.5))
+        .collect()
+}
+
+fn ns_fluid(size: GridSize) -> ns::fluid::Fluid {
+    let simvars = simvars::<ns::fluid::Fluid>();
+    let mut fluid = ns::fluid::Fluid::with_size(size);
+    for frame in 0..60 {
+        let paddle = Vec2::new(-270.0, (frame as f32 * 0.1).sin() * 100.0);
+        fluid.add_density(paddle, 10.0);
+        fluid.add_velocity(paddle, Vec2::new(10000.0, 0.0));
+        ns_step(&mut fluid, &simvars, simvars.get("iter") as u32);
+    }
+    fluid
+}
+
+fn sph_step(fluid: &mut sph::fluid::Fluid, simvars: &FluidSimVars) {
+    fluid.compute_density_pressure(simvars.get("gas_const"), simvars.get("rest_dens"));
+    fluid.compute_forces(simvars.get("visc_const"), 0.0);
+    fluid.integrate(FIXED_TIMESTEP, simvars.get("bound_damping"));
+}
+
+/// Returns the particles of a stirred fluid, with their densities and forces
+/// computed.
+fn sph_particles() -> Vec<Particle> {
+    let simvars = simvars::<sph::fluid::Fluid>();
+    let mut fluid =
+        sph::fluid::Fluid::new(simvars.get("kernel_radius"), simvars.get("particle_mass"));
+    for frame in 0..60 {
+        let paddle = Vec2::new(-270.0, (frame as f32 * 0.1).sin() * 100.0);
+        fluid.add_external_force(paddle, Vec2::new(1e5, 0.0), 30.0);
+        sph_step(&mut fluid, &simvars);
+    }
+    fluid.compute_density_pressure(simvars.get("gas_const"), simvars.get("rest_dens"));
+    fluid.compute_forces(simvars.get("visc_const"), 0.0);
+    fluid.particles().iter().cloned().collect()
+}
+
+fn sph_fluid(particles: &[Particle]) -> sph::fluid::Fluid {
+    let simvars = simvars::<sph::fluid::Fluid>();
+    let mut fluid =
+        sph::fluid::Fluid::new(simvars.get("kernel_radius"), simvars.get("particle_mass"));
+    fluid.set_particles(particles.iter().cloned());
+    fluid
+}
+
+fn ns_benches(c: &mut Criterion) {
+    let simvars = simvars::<ns::fluid::Fluid>();
+    let iter = simvars.get("iter") as u32;
+    let mut group = c.benchmark_group("ns::fluid_step");
+    // Half, the same as and double the grid the game plays on, each way.
+    for size in [GridSize::new(48, 36), GridSize::DEFAULT, GridSize::new(192, 144)] {
+        let id = BenchmarkId::from_parameter(format!("{}x{}", size.x, size.y));
+        group.bench_with_input(id, &size, |b, &size| {
+            let mut fluid = ns_fluid(size);
+            b.iter(|| ns_step(&mut fluid, &simvars, iter));
+        });
+    }
+    group.finish();
+}
+
+fn spatial_grid_benches(c: &mut Criterion) {
+    let particles = sph_particles();
+    let mut grid = SpatialGrid2D::new(simvars::<sph::fluid::Fluid>().get("kernel_radius"));
+    for particle in &particles {
+        grid.insert(particle.clone());
+    }
+    let probes = probes();
+
+    c.bench_function("SpatialGrid2D::query", |b| {
+        b.iter(|| {
+            for &probe in &probes {
+                black_box(grid.query(black_box(probe)));
+            }
+        })
+    });
+    c.bench_function("SpatialGrid2D::recompute", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut grid = grid.clone();
+                for particle in grid.iter_mut() {
+                    particle.position += particle.velocity * FIXED_TIMESTEP;
+                }
+                grid
+            },
+            |grid| grid.recompute(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn sph_benches(c: &mut Criterion) {
+    let simvars = simvars::<sph::fluid::Fluid>();
+    let (gas_const, rest_dens) = (simvars.get("gas_const"), simvars.get("rest_dens"));
+    let visc_const = simvars.get("visc_const");
+    let bound_damping = simvars.get("bound_damping");
+    let particles = sph_particles();
+    let probes = probes();
+
+    c.bench_function("sph::compute_density_pressure", |b| {
+        let mut fluid = sph_fluid(&particles);
+        b.iter(|| fluid.compute_density_pressure(gas_const, rest_dens))
+    });
+    c.bench_function("sph::compute_forces", |b| {
+        let mut fluid = sph_fluid(&particles);
+        b.iter(|| fluid.compute_forces(visc_const, 0.0))
+    });
+    c.bench_function("sph::integrate", |b| {
+        b.iter_batched_ref(
+            || sph_fluid(&particles),
+            |fluid| fluid.integrate(FIXED_TIMESTEP, bound_damping),
+            BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("sph::get_balls", |b| {
+        let fluid = sph_fluid(&particles);
+        b.iter(|| black_box(fluid.get_balls()))
+    });
+    c.bench_function("sph::get_force_at", |b| {
+        let fluid = sph_fluid(&particles);
+        b.iter(|| {
+            for &probe in &probes {
+                black_box(fluid.get_force_at(black_box(probe), Vec2::new(3.0, 1.0)));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, ns_benches, spatial_grid_benches, sph_benches);
+criterion_main!(benches);
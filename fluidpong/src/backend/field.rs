@@ -0,0 +1,62 @@
+use bevy::math::Vec2;
+
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+pub const FIELD_X: usize = 64;
+pub const FIELD_Y: usize = 32;
+pub const CELL_SIZE: f32 = GAME_WIDTH / FIELD_X as f32;
+
+/// A velocity field in pixels per second, sampled at the cell centers of a
+/// coarse grid over the arena.
+///
+/// This is the common ground between backends: when swapping, the old fluid is
+/// sampled into a field and the new one seeded from it.
+#[derive(Clone, Debug)]
+pub struct VelocityField {
+    velocities: Vec<Vec2>,
+}
+
+impl VelocityField {
+    /// Samples `velocity_at` at the center of every cell.
+    pub fn from_fn(velocity_at: impl Fn(Vec2) -> Vec2) -> Self {
+        let velocities = (0..FIELD_X * FIELD_Y).map(|c| velocity_at(cell_center(c))).collect();
+        Self { velocities }
+    }
+
+    /// Returns the velocity of every cell, row by row from the bottom left.
+    pub fn velocities(&self) -> &[Vec2] {
+        &self.velocities
+    }
+
+    /// Returns the velocity at the given position, bilinearly interpolated
+    /// between cell centers.
+    pub fn sample(&self, position: Vec2) -> Vec2 {
+        let local = (position + Vec2::new(GAME_WIDTH, GAME_HEIGHT) / 2.0) / CELL_SIZE - 0.5;
+        let local = local.clamp(Vec2::ZERO, Vec2::new(FIELD_X as f32 - 1.0, FIELD_Y as f32 - 1.0));
+        let (i, j) = (local.x as usize, local.y as usize);
+        let (i1, j1) = ((i + 1).min(FIELD_X - 1), (j + 1).min(FIELD_Y - 1));
+        let (s, t) = (local.x.fract(), local.y.fract());
+        let v = |i: usize, j: usize| self.velocities[i + j * FIELD_X];
+        (v(i, j) * (1.0 - s) + v(i1, j) * s) * (1.0 - t) + (v(i, j1) * (1.0 - s) + v(i1, j1) * s) * t
+    }
+
+    /// Returns the vorticity of every cell, from central differences.
+    pub fn curl(&self) -> Vec<(Vec2, f32)> {
+        let v = |i: usize, j: usize| self.velocities[i + j * FIELD_X];
+        (0..FIELD_X * FIELD_Y)
+            .map(|c| {
+                let (i, j) = (c % FIELD_X, c / FIELD_X);
+                let (il, ir) = (i.saturating_sub(1), (i + 1).min(FIELD_X - 1));
+                let (jd, ju) = (j.saturating_sub(1), (j + 1).min(FIELD_Y - 1));
+                let dvdx = (v(ir, j).y - v(il, j).y) / ((ir - il) as f32 * CELL_SIZE);
+                let dudy = (v(i, ju).x - v(i, jd).x) / ((ju - jd) as f32 * CELL_SIZE);
+                (cell_center(c), dvdx - dudy)
+            })
+            .collect()
+    }
+}
+
+fn cell_center(c: usize) -> Vec2 {
+    let cell = Vec2::new((c % FIELD_X) as f32, (c / FIELD_X) as f32);
+    (cell + 0.5) * CELL_SIZE - Vec2::new(GAME_WIDTH, GAME_HEIGHT) / 2.0
+}
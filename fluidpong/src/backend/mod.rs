@@ -0,0 +1,524 @@
+pub mod field;
+pub mod registry;
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use bevy::input::mouse::MouseMotion;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy::sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle};
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::HashMap;
+use bevy::window::PrimaryWindow;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::field::VelocityField;
+use crate::export::FluidExport;
+use crate::pong::pongfluid::{PongFluid, PongFluidPlugin, PongFluidSet};
+use crate::pong::state::GameState;
+use crate::raster::SoftwareMaterial;
+use crate::simui::{self, FluidSimVars, SimVarEdit};
+use crate::snapshot::{self, FluidState, SnapshotError};
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+/// A fluid simulation that can be played on.
+///
+/// On top of the coupling to the ball and paddles in `PongFluid`, a backend
+/// knows how to create, step, reset, sample and render itself. The generic
+/// `FluidPlugin` does the rest, so a new fluid only needs to implement this
+/// trait and be listed in the registry.
+pub trait FluidBackend: PongFluid + Component + Sized {
+    /// The name of the backend, as given to `--fluid`.
+    const NAME: &'static str;
+
+    /// The material the fluid is rendered with, on the GPU or in software.
+    type Material: Material2d<Data = ()> + SoftwareMaterial;
+
+    /// Returns the simulation variables and their default values.
+    fn simvars() -> Vec<(&'static str, f32)>;
+
+    /// Returns the simulation variables shown in the simulation UI. These
+    /// replace the defaults once the UI is initialized.
+    fn debug_simvars() -> Vec<(&'static str, f32)> {
+        Self::simvars()
+    }
+
+    fn new(simvars: &FluidSimVars) -> Self;
+
+    fn reset(&mut self, simvars: &FluidSimVars);
+
+    /// Advances the simulation by one frame of `dt` seconds.
+    fn step(&mut self, dt: f32, simvars: &FluidSimVars);
+
+    /// Returns the velocity of the fluid at the given point in pixels per
+    /// second.
+    fn sample_velocity(&self, position: Vec2) -> Vec2;
+
+    /// Stirs the fluid with the mouse. `drag` is how far the cursor moved this
+    /// frame in world space, and `pressed` whether the left button is held.
+    fn interact(&mut self, position: Vec2, drag: Vec2, pressed: bool, simvars: &FluidSimVars);
+
+    /// Sets the velocity of the fluid from a field sampled from another
+    /// backend, so that the flow carries over when swapping. Backends that
+    /// can't hold an arbitrary velocity field start at rest instead.
+    fn seed_velocity(&mut self, _field: &VelocityField) {}
+
+    /// Returns the state of the simulation, for snapshots.
+    fn save_state(&self) -> FluidState;
+
+    /// Restores the simulation from the state in a snapshot. The fluid has
+    /// just been reset with the simulation variables of the snapshot.
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError>;
+
+    /// Returns the fluid laid out for exporting to other tools. Backends
+    /// without a layout of their own export their sampled velocity field.
+    fn export(&self) -> FluidExport {
+        FluidExport::from_field(&VelocityField::from_fn(|position| self.sample_velocity(position)))
+    }
+
+    /// Returns a material showing the current state of the fluid.
+    fn material(&self) -> Self::Material;
+
+    /// Updates the material with the current state of the fluid.
+    fn update_material(&self, material: &mut Self::Material, simvars: &FluidSimVars);
+
+    /// Draws debug gizmos, such as particles.
+    fn draw_gizmos(&self, _gizmos: &mut Gizmos, _simvars: &FluidSimVars) {}
+
+    /// Adds anything else the backend needs to the app. Not called in
+    /// headless mode, so it is the place for extra views of the fluid.
+    fn build(_app: &mut App) {}
+}
+
+/// The time step of the game and the fluids in `FixedUpdate`, one frame at 60
+/// frames per second. Deterministic mode also advances time by it every
+/// frame, so that every frame is exactly one step.
+pub const FIXED_TIMESTEP: f32 = 1.0 / 60.0;
+
+/// The name of the backend that is being played on. Changing it swaps the
+/// fluid at the start of the next frame.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActiveFluid(pub &'static str);
+
+/// Where the mouse is stirring the fluid in debug mode, in world space. `drag`
+/// is how far the cursor moved this frame, and `pressed` whether the left
+/// button is held.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct FluidPointer {
+    pub position: Option<Vec2>,
+    pub drag: Vec2,
+    pub pressed: bool,
+}
+
+/// How the fluids are run.
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct FluidOptions {
+    /// Adds mouse stirring, gizmos, resets and the snapshot keys, and starts
+    /// fluids on the simulation variables of the simulation UI.
+    pub debug: bool,
+    /// Leaves out everything that needs a window or a GPU.
+    pub headless: bool,
+}
+
+/// Simulation variables that replace the defaults of every fluid when it is
+/// spawned, such as those given to `--set`.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct SimVarOverrides(pub Vec<(String, f32)>);
+
+/// While this resource exists, every fluid sends a `FluidCapture` after it
+/// steps, for tools that work with any backend.
+#[derive(Resource)]
+pub struct CaptureFluid;
+
+/// A copy of the fluid taken after this frame's step.
+#[derive(Event)]
+pub struct FluidCapture {
+    pub fluid: &'static str,
+    pub simvars: FluidSimVars,
+    pub state: FluidState,
+    pub velocity: VelocityField,
+    pub export: FluidExport,
+    pub material: Box<dyn SoftwareMaterial>,
+}
+
+/// What a swapped-out fluid leaves for the one replacing it.
+#[derive(Resource, Default)]
+struct CarryOver {
+    velocity: Option<VelocityField>,
+    debug: bool,
+}
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum FluidSwapSet {
+    Despawn,
+    Spawn,
+}
+
+/// Registers every backend in the registry and starts on `initial`. The
+/// fluid can be swapped with Tab, or by setting `ActiveFluid`.
+pub struct FluidBackendsPlugin {
+    pub initial: &'static str,
+    pub options: FluidOptions,
+}
+
+impl Plugin for FluidBackendsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ActiveFluid(self.initial))
+            .insert_resource(self.options)
+            .init_resource::<SimVarOverrides>()
+            .init_resource::<CarryOver>()
+            .init_resource::<FluidPointer>()
+            .add_event::<SimVarEdit>()
+            .add_event::<FluidCapture>()
+            .insert_resource(Time::<Fixed>::from_duration(Duration::from_secs_f32(FIXED_TIMESTEP)))
+            // The fixed steps run before `Update`, so they never see a fluid
+            // mid-swap, and a fluid spawned this frame is stepped from the
+            // next one.
+            .configure_sets(
+                Update,
+                (FluidSwapSet::Despawn, FluidSwapSet::Spawn)
+                    .chain()
+                    .run_if(resource_changed::<ActiveFluid>),
+            )
+            .add_systems(Update, cycle_fluid.before(FluidSwapSet::Despawn))
+            .add_systems(Update, simui::apply_simvar_edits.after(FluidSwapSet::Spawn));
+        if self.options.debug && !self.options.headless {
+            app.add_systems(PreUpdate, read_pointer.after(InputSystem))
+                .add_systems(Update, draw_pointer);
+        }
+        for backend in registry::BACKENDS {
+            (backend.add_plugin)(app, self.options);
+        }
+    }
+}
+
+/// Makes runs reproducible: time advances by `FIXED_TIMESTEP` every frame
+/// instead of by the frame time, so the same inputs produce bit-identical
+/// fluid and ball state on every run and machine.
+///
+/// The rest is up to the backends, which keep their particles in a stable
+/// order and only parallelize work whose result doesn't depend on how it is
+/// split between threads, such as updating each particle on its own. Sums
+/// over many particles or cells run in order on one thread.
+pub struct DeterministicPlugin;
+
+impl Plugin for DeterministicPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            FIXED_TIMESTEP,
+        )));
+    }
+}
+
+pub(crate) fn read_pointer(
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mb: Res<ButtonInput<MouseButton>>,
+    mut motion_er: EventReader<MouseMotion>,
+    mut pointer: ResMut<FluidPointer>,
+) {
+    let drag = motion_er.read().map(|motion| Vec2::new(1., -1.) * motion.delta).sum::<Vec2>();
+    let (camera, camera_transform) = camera_query.single();
+
+    let window: &Window = window_query.single();
+    let position = (window.cursor_position())
+        .and_then(|cursor_position| camera.viewport_to_world_2d(camera_transform, cursor_position));
+    *pointer = FluidPointer { position, drag, pressed: mb.pressed(MouseButton::Left) };
+}
+
+fn draw_pointer(pointer: Res<FluidPointer>, mut gizmos: Gizmos) {
+    if let (Some(position), true) = (pointer.position, pointer.pressed) {
+        gizmos.circle_2d(position, 10., Color::WHITE);
+    }
+}
+
+fn cycle_fluid(keyboard_input: Res<ButtonInput<KeyCode>>, mut active: ResMut<ActiveFluid>) {
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        active.0 = registry::next(active.0).name;
+        println!("fluid: {}", active.0);
+    }
+}
+
+/// Runs a fluid backend and couples it to the pong game.
+pub struct FluidPlugin<B> {
+    pub options: FluidOptions,
+    backend: PhantomData<B>,
+}
+
+impl<B> FluidPlugin<B> {
+    pub fn new(options: FluidOptions) -> Self {
+        Self { options, backend: PhantomData }
+    }
+}
+
+impl<B: FluidBackend> Plugin for FluidPlugin<B> {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(PongFluidPlugin::<B>::default())
+            .add_systems(Update, despawn_fluid::<B>.in_set(FluidSwapSet::Despawn))
+            .add_systems(Update, spawn_fluid::<B>.in_set(FluidSwapSet::Spawn))
+            .add_systems(
+                FixedUpdate,
+                update_fluid::<B>.after(PongFluidSet).run_if(not(in_state(GameState::Paused))),
+            )
+            .add_systems(
+                Update,
+                (
+                    snapshot::apply_snapshot::<B>
+                        .after(FluidSwapSet::Spawn)
+                        .before(capture_fluid::<B>),
+                    capture_fluid::<B>
+                        .after(simui::apply_simvar_edits)
+                        .run_if(resource_exists::<CaptureFluid>),
+                ),
+            );
+        if self.options.debug {
+            // Ordered before the capture so that replays apply inputs in the
+            // same order as they were recorded.
+            app.add_systems(
+                Update,
+                (
+                    update_interactive::<B>
+                        .after(FluidSwapSet::Spawn)
+                        .before(capture_fluid::<B>),
+                    update_debug::<B>
+                        .after(simui::apply_simvar_edits)
+                        .before(capture_fluid::<B>),
+                    snapshot::save_snapshot::<B>,
+                ),
+            );
+        }
+        if self.options.headless {
+            return;
+        }
+        // Several backends can share one material.
+        if !app.is_plugin_added::<Material2dPlugin<B::Material>>() {
+            app.add_plugins(Material2dPlugin::<B::Material>::default());
+        }
+        app.add_systems(Update, update_material::<B>.after(capture_fluid::<B>));
+        if self.options.debug {
+            app.add_systems(Update, draw_gizmos::<B>);
+        }
+        B::build(app);
+    }
+}
+
+fn despawn_fluid<B: FluidBackend>(
+    mut commands: Commands,
+    active: Res<ActiveFluid>,
+    mut carry_over: ResMut<CarryOver>,
+    query: Query<(Entity, &B, &FluidSimVars)>,
+) {
+    if active.0 == B::NAME {
+        return;
+    }
+    for (entity, fluid, simvars) in query.iter() {
+        *carry_over = CarryOver {
+            velocity: Some(VelocityField::from_fn(|position| fluid.sample_velocity(position))),
+            debug: simvars.debug,
+        };
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_fluid<B: FluidBackend>(
+    mut commands: Commands,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<B::Material>>>,
+    options: Res<FluidOptions>,
+    overrides: Res<SimVarOverrides>,
+    active: Res<ActiveFluid>,
+    carry_over: Res<CarryOver>,
+    query: Query<(), With<B>>,
+) {
+    if active.0 != B::NAME || !query.is_empty() {
+        return;
+    }
+    let mut simvars = FluidSimVars::new(HashMap::from_iter(
+        B::simvars().into_iter().map(|(name, value)| (name.to_string(), value)),
+    ));
+    if options.debug {
+        // What the simulation UI would set, so that headless runs of debug
+        // replays match the recorded game.
+        for (name, value) in B::debug_simvars() {
+            simvars.set(name.to_string(), value);
+        }
+    }
+    for (name, value) in &overrides.0 {
+        simvars.set(name.clone(), *value);
+    }
+    simvars.debug = carry_over.debug;
+    let mut fluid = B::new(&simvars);
+    if let Some(field) = &carry_over.velocity {
+        fluid.seed_velocity(field);
+    }
+    let render = match (meshes, materials) {
+        (Some(mut meshes), Some(mut materials)) => Some(MaterialMesh2dBundle {
+            mesh: Mesh2dHandle(meshes.add(Rectangle::new(GAME_WIDTH, GAME_HEIGHT))),
+            material: materials.add(fluid.material()),
+            transform: Transform::from_translation(Vec3::ZERO),
+            ..default()
+        }),
+        _ => None,
+    };
+
+    let mut entity = commands.spawn((fluid, simvars));
+    if let Some(render) = render {
+        entity.insert(render);
+    }
+}
+
+fn update_fluid<B: FluidBackend>(time: Res<Time>, mut query: Query<(&mut B, &FluidSimVars)>) {
+    if let Ok((mut fluid, simvars)) = query.get_single_mut() {
+        fluid.step(time.delta_seconds(), simvars);
+    }
+}
+
+fn capture_fluid<B: FluidBackend>(
+    query: Query<(&B, &FluidSimVars)>,
+    mut captures: EventWriter<FluidCapture>,
+) {
+    if let Ok((fluid, simvars)) = query.get_single() {
+        captures.send(FluidCapture {
+            fluid: B::NAME,
+            simvars: simvars.clone(),
+            state: fluid.save_state(),
+            velocity: VelocityField::from_fn(|position| fluid.sample_velocity(position)),
+            export: fluid.export(),
+            material: Box::new(fluid.material()),
+        });
+    }
+}
+
+fn update_material<B: FluidBackend>(
+    query: Query<(&B, &FluidSimVars, &Handle<B::Material>)>,
+    mut materials: ResMut<Assets<B::Material>>,
+) {
+    if let Ok((fluid, simvars, handle)) = query.get_single() {
+        if let Some(material) = materials.get_mut(handle) {
+            fluid.update_material(material, simvars);
+        }
+    }
+}
+
+fn update_interactive<B: FluidBackend>(
+    pointer: Res<FluidPointer>,
+    mut query: Query<(&mut B, &FluidSimVars)>,
+) {
+    let Ok((mut fluid, simvars)) = query.get_single_mut() else {
+        return;
+    };
+    if let Some(position) = pointer.position {
+        fluid.interact(position, pointer.drag, pointer.pressed, simvars);
+    }
+}
+
+fn draw_gizmos<B: FluidBackend>(mut gizmos: Gizmos, query: Query<(&B, &FluidSimVars)>) {
+    for (fluid, simvars) in query.iter() {
+        fluid.draw_gizmos(&mut gizmos, simvars);
+    }
+}
+
+fn update_debug<B: FluidBackend>(mut query: Query<(&mut B, &mut FluidSimVars)>) {
+    if let Ok((mut fluid, mut simvars)) = query.get_single_mut() {
+        if simvars.do_reset {
+            fluid.reset(&simvars);
+            simvars.do_reset = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether a paddle at rest in front of an emitting one changes the flow.
+    fn paddle_at_rest_changes_fluid<B: FluidBackend>() -> bool {
+        let simvars = FluidSimVars::new(HashMap::from_iter(
+            B::simvars().into_iter().map(|(name, value)| (name.to_string(), value)),
+        ));
+        let mut fluids = [<B as FluidBackend>::new(&simvars), <B as FluidBackend>::new(&simvars)];
+        for _ in 0..20 {
+            for fluid in &mut fluids {
+                fluid.apply_emit_force(Vec2::new(-270.0, 0.0), Vec2::new(1.0, 0.0));
+            }
+            fluids[1].apply_paddle_force(Vec2::new(-200.0, 0.0), Vec2::ZERO);
+            for fluid in &mut fluids {
+                FluidBackend::step(fluid, FIXED_TIMESTEP, &simvars);
+            }
+        }
+        let flow = |fluid: &B| -> Vec<Vec2> {
+            (-8..=8)
+                .flat_map(|i| (-4..=4).map(move |j| Vec2::new(i as f32, j as f32) * 35.0))
+                .map(|position| fluid.sample_velocity(position))
+                .collect()
+        };
+        flow(&fluids[0]) != flow(&fluids[1])
+    }
+
+    #[test]
+    fn paddles_at_rest_only_block_the_fluid() {
+        // LBM and FLIP treat the paddles as solids, which the fluid has to flow
+        // around whether they move or not. The others only push with them.
+        assert!(paddle_at_rest_changes_fluid::<crate::lbm::fluid::Fluid>());
+        assert!(paddle_at_rest_changes_fluid::<crate::flip::fluid::Fluid>());
+        assert!(!paddle_at_rest_changes_fluid::<crate::sph::fluid::Fluid>());
+        assert!(!paddle_at_rest_changes_fluid::<crate::ns::fluid::Fluid>());
+        assert!(!paddle_at_rest_changes_fluid::<crate::pbf::fluid::Fluid>());
+        assert!(!paddle_at_rest_changes_fluid::<crate::swe::fluid::Fluid>());
+        assert!(!paddle_at_rest_changes_fluid::<crate::vortex::fluid::Fluid>());
+    }
+
+    /// Plays a fixed sequence of inputs against a fluid and a bouncing ball,
+    /// and returns a hash of the final fluid and ball state.
+    fn run_scenario<B: FluidBackend>() -> u64 {
+        let simvars = FluidSimVars::new(HashMap::from_iter(
+            B::simvars().into_iter().map(|(name, value)| (name.to_string(), value)),
+        ));
+        let mut fluid = <B as FluidBackend>::new(&simvars);
+        let (mut ball, mut ball_velocity) = (Vec2::ZERO, Vec2::new(3.0, 3.0));
+        for frame in 0..30 {
+            let paddle = Vec2::new(-270.0, (frame as f32 * 0.2).sin() * 100.0);
+            fluid.apply_paddle_force(paddle, Vec2::new(0.0, 6.0));
+            if frame % 10 < 5 {
+                fluid.apply_emit_force(paddle, Vec2::new(1.0, 0.0));
+            }
+            fluid.interact(Vec2::new(50.0, 20.0), Vec2::new(4.0, -2.0), frame % 7 == 0, &simvars);
+            ball_velocity += fluid.get_fluid_force_at(ball, ball_velocity);
+            fluid.apply_ball_force(ball, ball_velocity);
+            FluidBackend::step(&mut fluid, FIXED_TIMESTEP, &simvars);
+            ball += ball_velocity;
+            if ball.x.abs() > GAME_WIDTH / 2.0 {
+                ball_velocity.x = -ball_velocity.x;
+            }
+            if ball.y.abs() > GAME_HEIGHT / 2.0 {
+                ball_velocity.y = -ball_velocity.y;
+            }
+        }
+        fluid.save_state().with_vec2s("ball", [ball, ball_velocity]).checksum()
+    }
+
+    /// Runs the scenario twice on several threads and once on a single one.
+    fn assert_deterministic<B: FluidBackend>() {
+        let hashes: Vec<u64> = [4, 4, 1]
+            .into_iter()
+            .map(|threads| {
+                let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+                pool.install(run_scenario::<B>)
+            })
+            .collect();
+        assert!(hashes.windows(2).all(|w| w[0] == w[1]), "{} diverged: {hashes:x?}", B::NAME);
+    }
+
+    #[test]
+    fn same_inputs_give_same_state() {
+        assert_deterministic::<crate::sph::fluid::Fluid>();
+        assert_deterministic::<crate::ns::fluid::Fluid>();
+        assert_deterministic::<crate::pbf::fluid::Fluid>();
+        assert_deterministic::<crate::lbm::fluid::Fluid>();
+        assert_deterministic::<crate::flip::fluid::Fluid>();
+        assert_deterministic::<crate::swe::fluid::Fluid>();
+        assert_deterministic::<crate::vortex::fluid::Fluid>();
+    }
+}
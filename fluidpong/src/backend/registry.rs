@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+
+use crate::backend::{FluidBackend, FluidOptions, FluidPlugin};
+
+/// The backend used when `--fluid` names none of the registered ones.
+pub const DEFAULT_BACKEND: &str = "ns";
+
+/// Every fluid that can be played on.
+pub const BACKENDS: &[Backend] = &[
+    Backend::of::<crate::sph::fluid::Fluid>(),
+    Backend::of::<crate::ns::fluid::Fluid>(),
+    Backend::of::<crate::pbf::fluid::Fluid>(),
+    Backend::of::<crate::lbm::fluid::Fluid>(),
+    Backend::of::<crate::flip::fluid::Fluid>(),
+    Backend::of::<crate::swe::fluid::Fluid>(),
+    Backend::of::<crate::vortex::fluid::Fluid>(),
+];
+
+/// A registered backend, with its type erased.
+pub struct Backend {
+    pub name: &'static str,
+    pub add_plugin: fn(&mut App, FluidOptions),
+    pub debug_simvars: fn() -> Vec<(&'static str, f32)>,
+}
+
+impl Backend {
+    pub const fn of<B: FluidBackend>() -> Self {
+        Self { name: B::NAME, add_plugin: add_plugin::<B>, debug_simvars: B::debug_simvars }
+    }
+}
+
+fn add_plugin<B: FluidBackend>(app: &mut App, options: FluidOptions) {
+    app.add_plugins(FluidPlugin::<B>::new(options));
+}
+
+/// Returns the backend with the given name, if there is one.
+pub fn get(name: &str) -> Option<&'static Backend> {
+    BACKENDS.iter().find(|backend| backend.name == name)
+}
+
+/// Returns the backend with the given name, or the default one.
+pub fn find(name: &str) -> &'static Backend {
+    get(name).or_else(|| get(DEFAULT_BACKEND)).expect("the default backend is registered")
+}
+
+/// Returns the backend after the one with the given name, wrapping around.
+pub fn next(name: &str) -> &'static Backend {
+    let index = BACKENDS.iter().position(|backend| backend.name == name).unwrap_or(0);
+    &BACKENDS[(index + 1) % BACKENDS.len()]
+}
@@ -0,0 +1,33 @@
+//! CSV with a row per cell center or particle: its position, then every
+//! field, with vectors split into `_x` and `_y` columns.
+
+use std::io::{self, Write};
+
+use super::{ExportValues, FluidExport};
+
+pub fn write(writer: &mut impl Write, export: &FluidExport) -> io::Result<()> {
+    let mut header = vec!["x".to_string(), "y".to_string()];
+    for field in &export.fields {
+        match &field.values {
+            ExportValues::Scalars(_) => header.push(field.name.to_string()),
+            ExportValues::Vectors(_) => {
+                header.push(format!("{}_x", field.name));
+                header.push(format!("{}_y", field.name));
+            }
+        }
+    }
+    writeln!(writer, "{}", header.join(","))?;
+    for (i, position) in export.positions().into_iter().enumerate() {
+        write!(writer, "{},{}", position.x, position.y)?;
+        for field in &export.fields {
+            match &field.values {
+                ExportValues::Scalars(values) => write!(writer, ",{}", values[i])?,
+                ExportValues::Vectors(values) => {
+                    write!(writer, ",{},{}", values[i].x, values[i].y)?
+                }
+            }
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
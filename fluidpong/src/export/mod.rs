@@ -0,0 +1,201 @@
+mod csv;
+mod vtk;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::core::FrameCount;
+use bevy::prelude::*;
+
+use crate::backend::field::{VelocityField, FIELD_X, FIELD_Y};
+use crate::backend::{CaptureFluid, FluidCapture};
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+/// A fluid laid out for other tools, such as ParaView or plotting scripts:
+/// named fields on the cells of a grid over the arena, or on particles.
+/// Positions are in world space and velocities in pixels per second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FluidExport {
+    pub points: ExportPoints,
+    pub fields: Vec<ExportField>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportPoints {
+    /// The centers of a grid of cells covering the arena, row by row from the
+    /// bottom left.
+    Grid(UVec2),
+    Particles(Vec<Vec2>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportField {
+    pub name: &'static str,
+    pub values: ExportValues,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportValues {
+    Scalars(Vec<f32>),
+    Vectors(Vec<Vec2>),
+}
+
+impl FluidExport {
+    pub fn grid(size: UVec2) -> Self {
+        Self { points: ExportPoints::Grid(size), fields: Vec::new() }
+    }
+
+    pub fn particles(positions: impl IntoIterator<Item = Vec2>) -> Self {
+        Self {
+            points: ExportPoints::Particles(positions.into_iter().collect()),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Lays out the common velocity field, for backends without a layout of
+    /// their own.
+    pub fn from_field(field: &VelocityField) -> Self {
+        Self::grid(UVec2::new(FIELD_X as u32, FIELD_Y as u32))
+            .with_vectors("velocity", field.velocities().iter().copied())
+    }
+
+    pub fn with_scalars(
+        mut self,
+        name: &'static str,
+        values: impl IntoIterator<Item = f32>,
+    ) -> Self {
+        let values = ExportValues::Scalars(values.into_iter().collect());
+        self.fields.push(ExportField { name, values });
+        self
+    }
+
+    pub fn with_vectors(
+        mut self,
+        name: &'static str,
+        values: impl IntoIterator<Item = Vec2>,
+    ) -> Self {
+        let values = ExportValues::Vectors(values.into_iter().collect());
+        self.fields.push(ExportField { name, values });
+        self
+    }
+
+    /// Returns the number of points, which every field has a value for.
+    pub fn len(&self) -> usize {
+        match &self.points {
+            ExportPoints::Grid(size) => (size.x * size.y) as usize,
+            ExportPoints::Particles(positions) => positions.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the position of every point.
+    pub fn positions(&self) -> Vec<Vec2> {
+        match &self.points {
+            ExportPoints::Grid(size) => {
+                let (origin, spacing) = grid_layout(*size);
+                (0..size.y)
+                    .flat_map(|j| (0..size.x).map(move |i| UVec2::new(i, j)))
+                    .map(|cell| origin + cell.as_vec2() * spacing)
+                    .collect()
+            }
+            ExportPoints::Particles(positions) => positions.clone(),
+        }
+    }
+}
+
+/// Returns the center of the bottom left cell of a grid covering the arena,
+/// and the size of its cells.
+fn grid_layout(size: UVec2) -> (Vec2, Vec2) {
+    let spacing = Vec2::new(GAME_WIDTH, GAME_HEIGHT) / size.as_vec2();
+    ((spacing - Vec2::new(GAME_WIDTH, GAME_HEIGHT)) / 2.0, spacing)
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Legacy VTK: image data for grids and polydata for particles.
+    #[default]
+    Vtk,
+    /// One row per cell or particle.
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Vtk => "vtk",
+            ExportFormat::Csv => "csv",
+        }
+    }
+
+    pub fn write(
+        self,
+        writer: &mut impl Write,
+        export: &FluidExport,
+        title: &str,
+    ) -> io::Result<()> {
+        match self {
+            ExportFormat::Vtk => vtk::write(writer, export, title),
+            ExportFormat::Csv => csv::write(writer, export),
+        }
+    }
+
+    pub fn save(self, path: &Path, export: &FluidExport, title: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, export, title)?;
+        writer.flush()
+    }
+}
+
+/// Writes the fluid to `<fluid>-<frame>.vtk` or `.csv` in `dir` every `every`
+/// frames.
+pub struct ExportPlugin {
+    pub dir: PathBuf,
+    pub every: u32,
+    pub format: ExportFormat,
+}
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        if let Err(err) = std::fs::create_dir_all(&self.dir) {
+            eprintln!("failed to create {}: {err}", self.dir.display());
+            std::process::exit(1);
+        }
+        app.insert_resource(CaptureFluid)
+            .insert_resource(Exporter {
+                dir: self.dir.clone(),
+                every: self.every.max(1),
+                format: self.format,
+            })
+            .add_systems(PostUpdate, write_exports);
+    }
+}
+
+#[derive(Resource)]
+struct Exporter {
+    dir: PathBuf,
+    every: u32,
+    format: ExportFormat,
+}
+
+fn write_exports(
+    frame: Res<FrameCount>,
+    exporter: Res<Exporter>,
+    mut captures: EventReader<FluidCapture>,
+) {
+    if !frame.0.is_multiple_of(exporter.every) {
+        captures.clear();
+        return;
+    }
+    for capture in captures.read() {
+        let name = format!("{}-{:06}.{}", capture.fluid, frame.0, exporter.format.extension());
+        let path = exporter.dir.join(name);
+        let title = format!("fluidpong {} frame {}", capture.fluid, frame.0);
+        if let Err(err) = exporter.format.save(&path, &capture.export, &title) {
+            println!("failed to export to {}: {err}", path.display());
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Legacy ASCII VTK files, which ParaView and VisIt open directly. Grids are
+//! written as `STRUCTURED_POINTS` with a point at every cell center, and
+//! particles as `POLYDATA` with a vertex per particle.
+
+use std::io::{self, Write};
+
+use super::{grid_layout, ExportPoints, ExportValues, FluidExport};
+
+pub fn write(writer: &mut impl Write, export: &FluidExport, title: &str) -> io::Result<()> {
+    writeln!(writer, "# vtk DataFile Version 3.0")?;
+    writeln!(writer, "{title}")?;
+    writeln!(writer, "ASCII")?;
+    let len = export.len();
+    match &export.points {
+        ExportPoints::Grid(size) => {
+            let (origin, spacing) = grid_layout(*size);
+            writeln!(writer, "DATASET STRUCTURED_POINTS")?;
+            writeln!(writer, "DIMENSIONS {} {} 1", size.x, size.y)?;
+            writeln!(writer, "ORIGIN {} {} 0", origin.x, origin.y)?;
+            writeln!(writer, "SPACING {} {} 1", spacing.x, spacing.y)?;
+        }
+        ExportPoints::Particles(positions) => {
+            writeln!(writer, "DATASET POLYDATA")?;
+            writeln!(writer, "POINTS {len} float")?;
+            for position in positions {
+                writeln!(writer, "{} {} 0", position.x, position.y)?;
+            }
+            writeln!(writer, "VERTICES {len} {}", len * 2)?;
+            for i in 0..len {
+                writeln!(writer, "1 {i}")?;
+            }
+        }
+    }
+    writeln!(writer, "POINT_DATA {len}")?;
+    for field in &export.fields {
+        match &field.values {
+            ExportValues::Scalars(values) => {
+                writeln!(writer, "SCALARS {} float 1", field.name)?;
+                writeln!(writer, "LOOKUP_TABLE default")?;
+                for value in values {
+                    writeln!(writer, "{value}")?;
+                }
+            }
+            ExportValues::Vectors(values) => {
+                writeln!(writer, "VECTORS {} float", field.name)?;
+                for value in values {
+                    writeln!(writer, "{} {} 0", value.x, value.y)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
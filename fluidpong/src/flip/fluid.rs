@@ -0,0 +1,588 @@
+use bevy::ecs::component::Component;
+use bevy::math::{Vec2, Vec4};
+use rayon::prelude::*;
+
+use crate::flip::particle::Particle;
+use crate::pong::level;
+use crate::sph::spatial_grid::SpatialGrid2D;
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+pub const GRID_X: usize = 64;
+pub const GRID_Y: usize = 32;
+pub const NUM_CELLS: usize = GRID_X * GRID_Y;
+pub const WIDTH: f32 = GAME_WIDTH;
+pub const HEIGHT: f32 = GAME_HEIGHT;
+pub const CELL_SIZE: f32 = WIDTH / GRID_X as f32;
+pub const NUM_PARTICLES: usize = crate::sph::fluid::NUM_PARTICLES;
+
+const PARTICLE_RADIUS: f32 = 0.3 * CELL_SIZE;
+const PARTICLE_SPACING: f32 = 0.7 * CELL_SIZE;
+const OVER_RELAXATION: f32 = 1.9;
+const CELL_VELOCITY_SCALE: f32 = 20.0;
+
+/// What occupies a grid cell during the pressure solve.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellType {
+    Fluid,
+    Air,
+    Solid,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Shape {
+    /// An axis-aligned box with the given half extents.
+    Box(Vec2),
+    /// A circle with the given radius.
+    Circle(f32),
+}
+
+/// A solid that moves through the fluid, such as a paddle or the ball.
+#[derive(Clone, Copy, Debug)]
+pub struct Obstacle {
+    pub center: Vec2,
+    pub shape: Shape,
+    pub velocity: Vec2,
+}
+
+impl Obstacle {
+    fn contains(&self, point: Vec2) -> bool {
+        self.push_out(point, 0.0).is_some()
+    }
+
+    /// Returns the closest point at least `margin` outside the obstacle, or
+    /// `None` if the point is already outside.
+    fn push_out(&self, point: Vec2, margin: f32) -> Option<Vec2> {
+        let d = point - self.center;
+        match self.shape {
+            Shape::Box(half_extents) => {
+                let overlap = half_extents + margin - d.abs();
+                if overlap.x <= 0.0 || overlap.y <= 0.0 {
+                    None
+                } else if overlap.x < overlap.y {
+                    Some(point + Vec2::new(overlap.x.copysign(d.x), 0.0))
+                } else {
+                    Some(point + Vec2::new(0.0, overlap.y.copysign(d.y)))
+                }
+            }
+            Shape::Circle(radius) => {
+                let length = d.length();
+                if length >= radius + margin {
+                    None
+                } else {
+                    let normal = if length > 0.0 { d / length } else { Vec2::Y };
+                    Some(self.center + normal * (radius + margin))
+                }
+            }
+        }
+    }
+}
+
+/// A FLIP/APIC liquid with a free surface.
+///
+/// Particles carry velocity and are transferred to a staggered (MAC) grid
+/// every step, where gravity and obstacles are applied and the velocity field
+/// is made divergence free. The grid velocities are then transferred back,
+/// either as a blend of the FLIP update and the PIC velocity, or with affine
+/// (APIC) transfers. Cells that contain no particles are air, so the liquid
+/// keeps a free surface and sloshes under gravity.
+#[derive(Component)]
+pub struct Fluid {
+    particles: Vec<Particle>,
+    u: Vec<f32>,
+    v: Vec<f32>,
+    u_prev: Vec<f32>,
+    v_prev: Vec<f32>,
+    cell_type: Vec<CellType>,
+    solid_velocity: Vec<Vec2>,
+    particle_density: Vec<f32>,
+    rest_density: f32,
+    obstacles: Vec<Obstacle>,
+    /// The level's obstacles, which stand still.
+    solids: Vec<level::Obstacle>,
+}
+
+impl Default for Fluid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fluid {
+    pub fn new() -> Fluid {
+        Fluid {
+            particles: spawn_particles(),
+            u: vec![0.0; (GRID_X + 1) * GRID_Y],
+            v: vec![0.0; GRID_X * (GRID_Y + 1)],
+            u_prev: vec![0.0; (GRID_X + 1) * GRID_Y],
+            v_prev: vec![0.0; GRID_X * (GRID_Y + 1)],
+            cell_type: vec![CellType::Air; NUM_CELLS],
+            solid_velocity: vec![Vec2::ZERO; NUM_CELLS],
+            particle_density: vec![0.0; NUM_CELLS],
+            rest_density: 0.0,
+            obstacles: Vec::new(),
+            solids: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Fluid::new();
+    }
+
+    /// Advances the simulation by one time step.
+    pub fn step(
+        &mut self,
+        dt: f32,
+        gravity: f32,
+        flip_ratio: f32,
+        apic: bool,
+        iterations: u32,
+        drift_stiffness: f32,
+    ) {
+        self.integrate_particles(dt, gravity);
+        self.separate_particles();
+        self.transfer_to_grid(apic);
+        self.solve_incompressibility(iterations, drift_stiffness);
+        self.transfer_to_particles(flip_ratio, apic);
+    }
+
+    /// Applies gravity and moves the particles, keeping them inside the arena
+    /// and out of obstacles.
+    pub fn integrate_particles(&mut self, dt: f32, gravity: f32) {
+        let obstacles = &self.obstacles;
+        let solids = &self.solids;
+        let min = Vec2::new(-WIDTH, -HEIGHT) / 2.0 + CELL_SIZE + PARTICLE_RADIUS;
+        let max = Vec2::new(WIDTH, HEIGHT) / 2.0 - CELL_SIZE - PARTICLE_RADIUS;
+        self.particles.par_iter_mut().for_each(|p| {
+            p.velocity.y -= gravity * dt;
+            p.position += p.velocity * dt;
+
+            for obstacle in obstacles {
+                if let Some(position) = obstacle.push_out(p.position, PARTICLE_RADIUS) {
+                    p.position = position;
+                    p.velocity = obstacle.velocity;
+                }
+            }
+            for solid in solids {
+                if let Some((position, normal)) = solid.push_out(p.position, PARTICLE_RADIUS) {
+                    p.position = position;
+                    p.velocity -= normal * p.velocity.dot(normal).min(0.0);
+                }
+            }
+            if p.position.x < min.x || p.position.x > max.x {
+                p.position.x = p.position.x.clamp(min.x, max.x);
+                p.velocity.x = 0.0;
+            }
+            if p.position.y < min.y || p.position.y > max.y {
+                p.position.y = p.position.y.clamp(min.y, max.y);
+                p.velocity.y = 0.0;
+            }
+        });
+    }
+
+    /// Pushes overlapping particles apart so that they do not clump in cells
+    /// the pressure solve cannot resolve.
+    pub fn separate_particles(&mut self) {
+        let mut grid = SpatialGrid2D::new(2.0 * PARTICLE_RADIUS);
+        for particle in &self.particles {
+            grid.insert(particle.clone());
+        }
+        self.particles.par_iter_mut().for_each(|pi| {
+            let delta: Vec2 = (grid.query(pi.position).into_iter())
+                .map(|pj| pi.position - pj.position)
+                .filter(|d| *d != Vec2::ZERO)
+                .map(|d| d.normalize() * 0.5 * (2.0 * PARTICLE_RADIUS - d.length()))
+                .sum();
+            pi.position += delta;
+        });
+    }
+
+    /// Classifies the cells and splats particle velocities onto the grid.
+    pub fn transfer_to_grid(&mut self, apic: bool) {
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                let c = cell_index(i, j);
+                let center = cell_center(i, j);
+                let boundary = i == 0 || j == 0 || i == GRID_X - 1 || j == GRID_Y - 1;
+                self.solid_velocity[c] = Vec2::ZERO;
+                self.cell_type[c] = if boundary { CellType::Solid } else { CellType::Air };
+                for obstacle in self.obstacles.iter().filter(|o| o.contains(center)) {
+                    self.cell_type[c] = CellType::Solid;
+                    self.solid_velocity[c] = obstacle.velocity;
+                }
+                if self.solids.iter().any(|solid| solid.contains(center)) {
+                    self.cell_type[c] = CellType::Solid;
+                    self.solid_velocity[c] = Vec2::ZERO;
+                }
+            }
+        }
+
+        self.u.fill(0.0);
+        self.v.fill(0.0);
+        self.particle_density.fill(0.0);
+        let mut u_weight = vec![0.0; self.u.len()];
+        let mut v_weight = vec![0.0; self.v.len()];
+
+        for p in &self.particles {
+            let local = to_local(p.position);
+            let c = cell_at(local);
+            if self.cell_type[c] == CellType::Air {
+                self.cell_type[c] = CellType::Fluid;
+            }
+
+            for (idx, w, _) in stencil(local, GRID_X + 1, GRID_Y, U_OFFSET) {
+                let affine = if apic { p.affine_x.dot(face_position(idx, GRID_X + 1, U_OFFSET) - local) } else { 0.0 };
+                self.u[idx] += w * (p.velocity.x + affine);
+                u_weight[idx] += w;
+            }
+            for (idx, w, _) in stencil(local, GRID_X, GRID_Y + 1, V_OFFSET) {
+                let affine = if apic { p.affine_y.dot(face_position(idx, GRID_X, V_OFFSET) - local) } else { 0.0 };
+                self.v[idx] += w * (p.velocity.y + affine);
+                v_weight[idx] += w;
+            }
+            for (idx, w, _) in stencil(local, GRID_X, GRID_Y, CENTER_OFFSET) {
+                self.particle_density[idx] += w;
+            }
+        }
+
+        for (u, w) in self.u.iter_mut().zip(u_weight) {
+            if w > 0.0 {
+                *u /= w;
+            }
+        }
+        for (v, w) in self.v.iter_mut().zip(v_weight) {
+            if w > 0.0 {
+                *v /= w;
+            }
+        }
+        self.u_prev.copy_from_slice(&self.u);
+        self.v_prev.copy_from_slice(&self.v);
+
+        if self.rest_density == 0.0 {
+            let fluid_cells = (0..NUM_CELLS).filter(|&c| self.cell_type[c] == CellType::Fluid);
+            let (sum, count) =
+                fluid_cells.fold((0.0, 0), |(sum, count), c| (sum + self.particle_density[c], count + 1));
+            if count > 0 {
+                self.rest_density = sum / count as f32;
+            }
+        }
+
+        // Faces touching a solid move with it.
+        for j in 0..GRID_Y {
+            for i in 0..=GRID_X {
+                if let Some(velocity) = self.solid_between((i as i32 - 1, j as i32), (i as i32, j as i32)) {
+                    self.u[i + j * (GRID_X + 1)] = velocity.x;
+                }
+            }
+        }
+        for j in 0..=GRID_Y {
+            for i in 0..GRID_X {
+                if let Some(velocity) = self.solid_between((i as i32, j as i32 - 1), (i as i32, j as i32)) {
+                    self.v[i + j * GRID_X] = velocity.y;
+                }
+            }
+        }
+    }
+
+    /// Projects the grid velocity onto a divergence-free field with
+    /// Gauss-Seidel iterations. Cells holding more particles than at rest get
+    /// extra outflow proportional to `drift_stiffness`, which stops the volume
+    /// from slowly collapsing.
+    pub fn solve_incompressibility(&mut self, iterations: u32, drift_stiffness: f32) {
+        for _ in 0..iterations {
+            for j in 1..GRID_Y - 1 {
+                for i in 1..GRID_X - 1 {
+                    let c = cell_index(i, j);
+                    if self.cell_type[c] != CellType::Fluid {
+                        continue;
+                    }
+                    let open = |i: usize, j: usize| {
+                        if self.cell_type[cell_index(i, j)] == CellType::Solid { 0.0 } else { 1.0 }
+                    };
+                    let (sx0, sx1, sy0, sy1) = (open(i - 1, j), open(i + 1, j), open(i, j - 1), open(i, j + 1));
+                    let s = sx0 + sx1 + sy0 + sy1;
+                    if s == 0.0 {
+                        continue;
+                    }
+
+                    let (u0, u1) = (i + j * (GRID_X + 1), i + 1 + j * (GRID_X + 1));
+                    let (v0, v1) = (i + j * GRID_X, i + (j + 1) * GRID_X);
+                    let mut div = self.u[u1] - self.u[u0] + self.v[v1] - self.v[v0];
+                    if self.rest_density > 0.0 {
+                        let compression = self.particle_density[c] - self.rest_density;
+                        if compression > 0.0 {
+                            div -= drift_stiffness * compression;
+                        }
+                    }
+
+                    let p = -div / s * OVER_RELAXATION;
+                    self.u[u0] -= sx0 * p;
+                    self.u[u1] += sx1 * p;
+                    self.v[v0] -= sy0 * p;
+                    self.v[v1] += sy1 * p;
+                }
+            }
+        }
+    }
+
+    /// Transfers the grid velocities back to the particles. With `apic`, the
+    /// particles take the grid velocity and its local gradient; otherwise they
+    /// blend the FLIP update and the PIC velocity by `flip_ratio`.
+    pub fn transfer_to_particles(&mut self, flip_ratio: f32, apic: bool) {
+        let fluid = &*self;
+        let samples: Vec<_> = (self.particles.par_iter())
+            .map(|p| {
+                let local = to_local(p.position);
+                (fluid.sample_face(local, true), fluid.sample_face(local, false))
+            })
+            .collect();
+
+        self.particles.par_iter_mut().zip(samples).for_each(|(p, (x, y))| {
+            if let Some((pic, delta, grad)) = x {
+                if apic {
+                    p.velocity.x = pic;
+                    p.affine_x = grad;
+                } else {
+                    p.velocity.x = (1.0 - flip_ratio) * pic + flip_ratio * (p.velocity.x + delta);
+                }
+            }
+            if let Some((pic, delta, grad)) = y {
+                if apic {
+                    p.velocity.y = pic;
+                    p.affine_y = grad;
+                } else {
+                    p.velocity.y = (1.0 - flip_ratio) * pic + flip_ratio * (p.velocity.y + delta);
+                }
+            }
+        });
+    }
+
+    /// Interpolates one velocity component at a local position, using only
+    /// faces that border fluid or solid cells. Returns the interpolated
+    /// velocity, its change over the pressure solve, and its gradient.
+    fn sample_face(&self, local: Vec2, horizontal: bool) -> Option<(f32, f32, Vec2)> {
+        let (nx, ny, offset, field, prev) = if horizontal {
+            (GRID_X + 1, GRID_Y, U_OFFSET, &self.u, &self.u_prev)
+        } else {
+            (GRID_X, GRID_Y + 1, V_OFFSET, &self.v, &self.v_prev)
+        };
+
+        let mut total = 0.0;
+        let (mut pic, mut delta, mut grad) = (0.0, 0.0, Vec2::ZERO);
+        for (idx, w, dw) in stencil(local, nx, ny, offset) {
+            let (i, j) = ((idx % nx) as i32, (idx / nx) as i32);
+            let neighbors = if horizontal { [(i - 1, j), (i, j)] } else { [(i, j - 1), (i, j)] };
+            let valid = neighbors.iter().any(|&(ci, cj)| {
+                ci < 0
+                    || cj < 0
+                    || ci >= GRID_X as i32
+                    || cj >= GRID_Y as i32
+                    || self.cell_type[cell_index(ci as usize, cj as usize)] != CellType::Air
+            });
+            if valid {
+                total += w;
+                pic += w * field[idx];
+                delta += w * (field[idx] - prev[idx]);
+                grad += dw * field[idx];
+            }
+        }
+        if total > 0.0 {
+            Some((pic / total, delta / total, grad))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the velocity of the solid between two cells, if either of them
+    /// is solid. Cells outside the grid count as stationary walls.
+    fn solid_between(&self, a: (i32, i32), b: (i32, i32)) -> Option<Vec2> {
+        let mut velocity = None;
+        for (i, j) in [a, b] {
+            if i < 0 || j < 0 || i >= GRID_X as i32 || j >= GRID_Y as i32 {
+                velocity = Some(Vec2::ZERO);
+            } else {
+                let c = cell_index(i as usize, j as usize);
+                if self.cell_type[c] == CellType::Solid {
+                    velocity = Some(velocity.unwrap_or(self.solid_velocity[c]));
+                }
+            }
+        }
+        velocity
+    }
+
+    /// Adds a solid obstacle for the next step.
+    pub fn add_obstacle(&mut self, obstacle: Obstacle) {
+        self.obstacles.push(obstacle);
+    }
+
+    /// Adds one of the level's obstacles for the next step.
+    pub fn add_solid(&mut self, obstacle: level::Obstacle) {
+        self.solids.push(obstacle);
+    }
+
+    /// Adds velocity to the particles within the given radius, falling off
+    /// linearly from the center.
+    pub fn add_impulse(&mut self, center: Vec2, radius: f32, velocity: Vec2) {
+        self.particles.par_iter_mut().for_each(|p| {
+            let distance = p.position.distance(center);
+            if distance < radius {
+                p.velocity += velocity * (1.0 - distance / radius);
+            }
+        });
+    }
+
+    /// Removes the obstacles added since the last call.
+    pub fn clear_frame_inputs(&mut self) {
+        self.obstacles.clear();
+        self.solids.clear();
+    }
+
+    /// Returns the grid velocity at the given position, or `None` if it lies
+    /// in the air.
+    pub fn get_velocity_at(&self, position: Vec2) -> Option<Vec2> {
+        let local = to_local(position);
+        if self.cell_type[cell_at(local)] == CellType::Air {
+            return None;
+        }
+        let x = self.sample_face(local, true).map(|s| s.0).unwrap_or(0.0);
+        let y = self.sample_face(local, false).map(|s| s.0).unwrap_or(0.0);
+        Some(Vec2::new(x, y))
+    }
+
+    /// Returns the type of the cell at the given position.
+    pub fn get_cell_type_at(&self, position: Vec2) -> CellType {
+        self.cell_type[cell_at(to_local(position))]
+    }
+
+    /// Sets the velocity of every particle from a velocity field, dropping
+    /// their affine velocities.
+    pub fn set_velocities(&mut self, velocity_at: impl Fn(Vec2) -> Vec2) {
+        for particle in self.particles.iter_mut() {
+            particle.velocity = velocity_at(particle.position);
+            particle.affine_x = Vec2::ZERO;
+            particle.affine_y = Vec2::ZERO;
+        }
+    }
+
+    /// Moves every particle from where it is to where `to` puts it.
+    pub fn move_particles(&mut self, to: impl Fn(Vec2) -> Vec2) {
+        for particle in self.particles.iter_mut() {
+            particle.position = to(particle.position);
+        }
+    }
+
+    /// Returns a reference to the particles in the fluid.
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Replaces the particles in the fluid, along with the rest density they
+    /// were measured to have. A rest density of zero is measured again on the
+    /// next step.
+    pub fn set_particles(&mut self, particles: Vec<Particle>, rest_density: f32) {
+        self.particles = particles;
+        self.rest_density = rest_density;
+    }
+
+    /// Returns the particle density of a cell in the initial block of fluid,
+    /// or zero before the first step.
+    pub fn rest_density(&self) -> f32 {
+        self.rest_density
+    }
+
+    /// Returns metaball information for the shader
+    pub fn get_balls(&self) -> [Vec4; NUM_PARTICLES] {
+        let mut balls = [Vec4::ZERO; NUM_PARTICLES];
+        for (ball, particle) in balls.iter_mut().zip(self.particles.iter()) {
+            let c = cell_at(to_local(particle.position));
+            let density = if self.rest_density > 0.0 {
+                self.particle_density[c] / self.rest_density
+            } else {
+                1.0
+            };
+            *ball = Vec4::new(
+                particle.position.x,
+                particle.position.y,
+                density,
+                particle.velocity.length(),
+            );
+        }
+        balls
+    }
+
+    /// Returns the cell buffer for the grid debug view, padded to the size of
+    /// `ns::FluidGridMaterial`. Velocity goes in `x`/`y`, and `z` encodes the
+    /// cell type as opacity.
+    pub fn get_cells(&self) -> [Vec4; crate::ns::fluid::NUM_CELLS] {
+        let mut cells = [Vec4::ZERO; crate::ns::fluid::NUM_CELLS];
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                let c = cell_index(i, j);
+                let u = 0.5 * (self.u[i + j * (GRID_X + 1)] + self.u[i + 1 + j * (GRID_X + 1)]);
+                let v = 0.5 * (self.v[i + j * GRID_X] + self.v[i + (j + 1) * GRID_X]);
+                let opacity = match self.cell_type[c] {
+                    CellType::Fluid => 0.5,
+                    CellType::Solid => 0.7,
+                    CellType::Air => 0.0,
+                };
+                cells[c] = Vec4::new(u * CELL_VELOCITY_SCALE, v * CELL_VELOCITY_SCALE, opacity, 0.0);
+            }
+        }
+        cells
+    }
+}
+
+const U_OFFSET: Vec2 = Vec2::new(0.0, 0.5);
+const V_OFFSET: Vec2 = Vec2::new(0.5, 0.0);
+const CENTER_OFFSET: Vec2 = Vec2::new(0.5, 0.5);
+
+/// Fills the bottom of the arena with particles.
+fn spawn_particles() -> Vec<Particle> {
+    let min = Vec2::new(-WIDTH, -HEIGHT) / 2.0 + CELL_SIZE + PARTICLE_RADIUS;
+    let per_row = ((WIDTH - 2.0 * (CELL_SIZE + PARTICLE_RADIUS)) / PARTICLE_SPACING) as usize + 1;
+    (0..NUM_PARTICLES)
+        .map(|n| {
+            let (i, j) = (n % per_row, n / per_row);
+            Particle::new(min + Vec2::new(i as f32, j as f32) * PARTICLE_SPACING)
+        })
+        .collect()
+}
+
+fn to_local(position: Vec2) -> Vec2 {
+    position + Vec2::new(WIDTH, HEIGHT) / 2.0
+}
+
+fn cell_index(i: usize, j: usize) -> usize {
+    i + j * GRID_X
+}
+
+fn cell_center(i: usize, j: usize) -> Vec2 {
+    (Vec2::new(i as f32, j as f32) + 0.5) * CELL_SIZE - Vec2::new(WIDTH, HEIGHT) / 2.0
+}
+
+fn cell_at(local: Vec2) -> usize {
+    let i = ((local.x / CELL_SIZE) as i32).clamp(0, GRID_X as i32 - 1) as usize;
+    let j = ((local.y / CELL_SIZE) as i32).clamp(0, GRID_Y as i32 - 1) as usize;
+    cell_index(i, j)
+}
+
+fn face_position(idx: usize, nx: usize, offset: Vec2) -> Vec2 {
+    (Vec2::new((idx % nx) as f32, (idx / nx) as f32) + offset) * CELL_SIZE
+}
+
+/// Returns the four samples surrounding a local position on a grid of `nx` by
+/// `ny` samples offset by `offset` cells, with their bilinear weights and the
+/// gradients of those weights.
+fn stencil(local: Vec2, nx: usize, ny: usize, offset: Vec2) -> [(usize, f32, Vec2); 4] {
+    let g = local / CELL_SIZE - offset;
+    let i0 = (g.x.floor() as i32).clamp(0, nx as i32 - 2) as usize;
+    let j0 = (g.y.floor() as i32).clamp(0, ny as i32 - 2) as usize;
+    let fx = (g.x - i0 as f32).clamp(0.0, 1.0);
+    let fy = (g.y - j0 as f32).clamp(0.0, 1.0);
+    let idx = |i: usize, j: usize| i + j * nx;
+    [
+        (idx(i0, j0), (1.0 - fx) * (1.0 - fy), Vec2::new(fy - 1.0, fx - 1.0) / CELL_SIZE),
+        (idx(i0 + 1, j0), fx * (1.0 - fy), Vec2::new(1.0 - fy, -fx) / CELL_SIZE),
+        (idx(i0, j0 + 1), (1.0 - fx) * fy, Vec2::new(-fy, 1.0 - fx) / CELL_SIZE),
+        (idx(i0 + 1, j0 + 1), fx * fy, Vec2::new(fy, fx) / CELL_SIZE),
+    ]
+}
@@ -0,0 +1,175 @@
+pub mod fluid;
+pub mod particle;
+mod pongfluid;
+
+use bevy::prelude::*;
+use bevy::sprite::{Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle};
+
+use crate::backend::field::VelocityField;
+use crate::backend::FluidBackend;
+use crate::flip::particle::Particle;
+use crate::ns::FluidGridMaterial;
+use crate::simui::FluidSimVars;
+use crate::snapshot::{FluidState, SnapshotError};
+use crate::sph::MetaballMaterial;
+
+impl FluidBackend for fluid::Fluid {
+    const NAME: &'static str = "flip";
+    type Material = MetaballMaterial;
+
+    fn simvars() -> Vec<(&'static str, f32)> {
+        vec![
+            ("gravity", 500.0),
+            ("flip_ratio", 0.95),
+            ("apic", 0.0),
+            ("iterations", 40.0),
+            ("drift_stiffness", 50.0),
+            ("substeps", 2.0),
+        ]
+    }
+
+    fn debug_simvars() -> Vec<(&'static str, f32)> {
+        let mut simvars = Self::simvars();
+        simvars.push(("interact_force", 1.0));
+        simvars
+    }
+
+    fn new(_simvars: &FluidSimVars) -> Self {
+        Self::new()
+    }
+
+    fn reset(&mut self, _simvars: &FluidSimVars) {
+        self.reset();
+    }
+
+    fn step(&mut self, dt: f32, simvars: &FluidSimVars) {
+        let substeps = (simvars.get("substeps") as u32).max(1);
+        let dt = dt / substeps as f32;
+        for _ in 0..substeps {
+            self.step(
+                dt,
+                simvars.get("gravity"),
+                simvars.get("flip_ratio"),
+                simvars.get("apic") != 0.0,
+                simvars.get("iterations") as u32,
+                simvars.get("drift_stiffness"),
+            );
+        }
+        self.clear_frame_inputs();
+    }
+
+    fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        self.get_velocity_at(position).unwrap_or(Vec2::ZERO)
+    }
+
+    fn interact(&mut self, position: Vec2, drag: Vec2, pressed: bool, simvars: &FluidSimVars) {
+        if pressed {
+            self.add_impulse(position, 20.0, drag * simvars.get("interact_force"));
+        }
+    }
+
+    fn seed_velocity(&mut self, field: &VelocityField) {
+        self.set_velocities(|position| field.sample(position));
+    }
+
+    fn save_state(&self) -> FluidState {
+        let particles = self.particles();
+        FluidState::default()
+            .with("rest_density", [self.rest_density()])
+            .with_vec2s("position", particles.iter().map(|particle| particle.position))
+            .with_vec2s("velocity", particles.iter().map(|particle| particle.velocity))
+            .with_vec2s("affine_x", particles.iter().map(|particle| particle.affine_x))
+            .with_vec2s("affine_y", particles.iter().map(|particle| particle.affine_y))
+    }
+
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError> {
+        let len = state.get("position")?.len() / 2;
+        let position = state.get_vec2s("position", len)?;
+        let velocity = state.get_vec2s("velocity", len)?;
+        let affine_x = state.get_vec2s("affine_x", len)?;
+        let affine_y = state.get_vec2s("affine_y", len)?;
+        let particles = (0..len)
+            .map(|i| Particle {
+                position: position[i],
+                velocity: velocity[i],
+                affine_x: affine_x[i],
+                affine_y: affine_y[i],
+            })
+            .collect();
+        self.set_particles(particles, state.get_len("rest_density", 1)?[0]);
+        Ok(())
+    }
+
+    fn material(&self) -> MetaballMaterial {
+        MetaballMaterial { color: Color::BLUE, balls: self.get_balls() }
+    }
+
+    fn update_material(&self, material: &mut MetaballMaterial, _simvars: &FluidSimVars) {
+        material.balls = self.get_balls();
+    }
+
+    fn draw_gizmos(&self, gizmos: &mut Gizmos, simvars: &FluidSimVars) {
+        if simvars.debug {
+            for particle in self.particles() {
+                gizmos.circle_2d(particle.position, 2.0, Color::rgba(0.6, 0.8, 1.0, 0.3));
+            }
+        }
+    }
+
+    fn build(app: &mut App) {
+        if !app.is_plugin_added::<Material2dPlugin<FluidGridMaterial>>() {
+            app.add_plugins(Material2dPlugin::<FluidGridMaterial>::default());
+        }
+        app.add_systems(Update, (spawn_grid_view, update_grid_view));
+    }
+}
+
+/// Marks the grid debug view, shown in place of the metaballs when the
+/// simulation UI's debug toggle is on.
+#[derive(Component)]
+struct GridView;
+
+fn spawn_grid_view(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<FluidGridMaterial>>,
+    query: Query<(Entity, &fluid::Fluid), Added<fluid::Fluid>>,
+) {
+    for (entity, fluid) in query.iter() {
+        let grid_view = commands
+            .spawn((
+                GridView,
+                MaterialMesh2dBundle {
+                    mesh: Mesh2dHandle(meshes.add(Rectangle::new(fluid::WIDTH, fluid::HEIGHT))),
+                    material: materials.add(FluidGridMaterial {
+                        screen_size: Vec2::new(fluid::WIDTH, fluid::HEIGHT),
+                        grid_size: Vec2::new(fluid::GRID_X as f32, fluid::GRID_Y as f32),
+                        cells: fluid.get_cells(),
+                    }),
+                    transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.5)),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+            ))
+            .id();
+        commands.entity(entity).add_child(grid_view);
+    }
+}
+
+fn update_grid_view(
+    fluid_query: Query<(&fluid::Fluid, &FluidSimVars)>,
+    mut grid_query: Query<(&mut Visibility, &Handle<FluidGridMaterial>), With<GridView>>,
+    mut materials: ResMut<Assets<FluidGridMaterial>>,
+) {
+    let (Ok((fluid, simvars)), Ok((mut visibility, handle))) =
+        (fluid_query.get_single(), grid_query.get_single_mut())
+    else {
+        return;
+    };
+    *visibility = if simvars.debug { Visibility::Visible } else { Visibility::Hidden };
+    if simvars.debug {
+        if let Some(material) = materials.get_mut(handle) {
+            material.cells = fluid.get_cells();
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use bevy::math::Vec2;
+
+use crate::sph::spatial_grid::Position;
+
+/// A particle in the FLIP simulation.
+///
+/// Particles carry the fluid's velocity between steps, and the grid is only
+/// used to make the velocity field incompressible. With APIC transfers each
+/// particle also carries the local velocity gradient, one row per velocity
+/// component.
+#[derive(Debug, Clone)]
+pub struct Particle {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub affine_x: Vec2,
+    pub affine_y: Vec2,
+}
+
+impl Particle {
+    /// Creates a new particle at rest at the given position.
+    pub fn new(position: Vec2) -> Self {
+        Self { position, velocity: Vec2::ZERO, affine_x: Vec2::ZERO, affine_y: Vec2::ZERO }
+    }
+}
+
+impl Position for Particle {
+    fn position(&self) -> Vec2 {
+        self.position
+    }
+}
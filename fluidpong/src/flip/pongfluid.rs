@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+
+use crate::backend::FIXED_TIMESTEP;
+use crate::flip::fluid::{Obstacle, Shape};
+use crate::pong::level;
+use crate::pong::pongfluid::{pack_into_wall, PongFluid};
+
+const PADDLE_HALF_EXTENTS: Vec2 = Vec2::new(5.0, 25.0);
+const BALL_RADIUS: f32 = 5.0;
+const EMIT_VELOCITY: f32 = 40.0;
+const EMIT_RADIUS: f32 = 30.0;
+const FLUID_ON_BALL_VELOCITY: f32 = 0.002;
+const FLUID_ON_BALL_DRAG: f32 = 0.01;
+
+impl PongFluid for crate::flip::fluid::Fluid {
+    fn apply_emit_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_impulse(position, EMIT_RADIUS, velocity * EMIT_VELOCITY);
+    }
+    fn apply_paddle_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_obstacle(Obstacle {
+            center: position,
+            shape: Shape::Box(PADDLE_HALF_EXTENTS),
+            velocity: velocity / FIXED_TIMESTEP,
+        });
+    }
+    fn apply_ball_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_obstacle(Obstacle {
+            center: position,
+            shape: Shape::Circle(BALL_RADIUS),
+            velocity: velocity / FIXED_TIMESTEP,
+        });
+    }
+    fn apply_obstacles(&mut self, obstacles: &[level::Obstacle]) {
+        for obstacle in obstacles {
+            self.add_solid(obstacle.clone());
+        }
+    }
+    // The drift correction pushes the packed particles apart again, so the
+    // band bursts back out.
+    fn apply_wall(&mut self, position: Vec2, direction: Vec2) {
+        self.move_particles(|point| pack_into_wall(point, position, direction));
+    }
+    fn get_fluid_force_at(&self, position: Vec2, velocity: Vec2) -> Vec2 {
+        // Only the liquid pushes the ball; it flies freely through the air.
+        match self.get_velocity_at(position) {
+            Some(flow) => flow * FLUID_ON_BALL_VELOCITY - velocity * FLUID_ON_BALL_DRAG,
+            None => Vec2::ZERO,
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use bevy::app::AppExit;
+use bevy::core::FrameCount;
+use bevy::prelude::*;
+
+use crate::backend::{CaptureFluid, FluidCapture, SimVarOverrides};
+use crate::pong::powerup::ExtraBall;
+use crate::pong::{Ball, Paddle, Position, Score, Shape, Velocity};
+use crate::raster::Frame;
+use crate::snapshot::Snapshot;
+
+/// The metrics file, in the output directory.
+pub const METRICS_FILE: &str = "metrics.csv";
+
+/// How many frames a headless run lasts when neither `--steps` nor a replay
+/// says otherwise.
+pub const DEFAULT_STEPS: u32 = 600;
+
+/// Runs the game for a fixed number of frames without a window or GPU, for
+/// CI machines, long runs and parameter sweeps.
+///
+/// Every frame adds a row of ball, score and flow metrics to `metrics.csv` in
+/// `out_dir`, and the fluid is saved to `snapshot-<frame>.fpsnap` there every
+/// `snapshot_every` frames and after the last one. With `render_every`, the
+/// fluid, ball and paddles are drawn on the CPU to `frame-<frame>.png`. Inputs
+/// come from a replay, which can be recorded or written by hand.
+pub struct HeadlessPlugin {
+    pub steps: u32,
+    pub out_dir: PathBuf,
+    /// 0 to only save the last frame.
+    pub snapshot_every: u32,
+    /// 0 to draw no frames.
+    pub render_every: u32,
+    /// Replaces the defaults of the fluids' simulation variables.
+    pub simvars: Vec<(String, f32)>,
+}
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        let metrics = std::fs::create_dir_all(&self.out_dir)
+            .and_then(|()| Metrics::create(&self.out_dir.join(METRICS_FILE)))
+            .unwrap_or_else(|err| {
+                eprintln!("failed to create {}: {err}", self.out_dir.display());
+                std::process::exit(1);
+            });
+        app.insert_resource(CaptureFluid)
+            .insert_resource(SimVarOverrides(self.simvars.clone()))
+            .insert_resource(metrics)
+            .insert_resource(Run {
+                steps: self.steps,
+                out_dir: self.out_dir.clone(),
+                snapshot_every: self.snapshot_every,
+                render_every: self.render_every,
+            })
+            .add_systems(
+                PostUpdate,
+                (write_metrics, write_snapshots, write_frames, stop_after_steps).chain(),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct Run {
+    steps: u32,
+    out_dir: PathBuf,
+    snapshot_every: u32,
+    render_every: u32,
+}
+
+impl Run {
+    fn is_last(&self, frame: u32) -> bool {
+        frame + 1 >= self.steps
+    }
+}
+
+#[derive(Resource)]
+struct Metrics {
+    writer: BufWriter<File>,
+    last_frame: Instant,
+}
+
+impl Metrics {
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "frame,fluid,ball_x,ball_y,ball_vx,ball_vy,balls,score1,score2,\
+             mean_speed,max_speed,kinetic_energy,enstrophy,frame_ms"
+        )?;
+        Ok(Self { writer, last_frame: Instant::now() })
+    }
+}
+
+/// Summarizes the flow sampled on the velocity field: speeds in pixels per
+/// second, and kinetic energy and enstrophy per unit mass, averaged over the
+/// cells.
+fn flow_metrics(capture: &FluidCapture) -> [f32; 4] {
+    let velocities = capture.velocity.velocities();
+    let n = velocities.len() as f32;
+    let mean_speed = velocities.iter().map(|v| v.length()).sum::<f32>() / n;
+    let max_speed = velocities.iter().map(|v| v.length()).fold(0.0, f32::max);
+    let kinetic_energy = velocities.iter().map(|v| 0.5 * v.length_squared()).sum::<f32>() / n;
+    let curl = capture.velocity.curl();
+    let enstrophy = curl.iter().map(|(_, w)| 0.5 * w * w).sum::<f32>() / curl.len() as f32;
+    [mean_speed, max_speed, kinetic_energy, enstrophy]
+}
+
+#[allow(clippy::type_complexity)]
+fn write_metrics(
+    frame: Res<FrameCount>,
+    score: Res<Score>,
+    ball: Query<(&Position, &Velocity), (With<Ball>, Without<ExtraBall>)>,
+    balls: Query<(), With<Ball>>,
+    mut captures: EventReader<FluidCapture>,
+    mut metrics: ResMut<Metrics>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // The columns for the ball are the served one's.
+    let (ball_position, ball_velocity) =
+        ball.get_single().map(|(position, velocity)| (position.0, velocity.0)).unwrap_or_default();
+    let balls = balls.iter().count();
+    for capture in captures.read() {
+        let [mean_speed, max_speed, kinetic_energy, enstrophy] = flow_metrics(capture);
+        let frame_ms = metrics.last_frame.elapsed().as_secs_f32() * 1000.0;
+        metrics.last_frame = Instant::now();
+        let written = writeln!(
+            metrics.writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            frame.0,
+            capture.fluid,
+            ball_position.x,
+            ball_position.y,
+            ball_velocity.x,
+            ball_velocity.y,
+            balls,
+            score.player1,
+            score.player2,
+            mean_speed,
+            max_speed,
+            kinetic_energy,
+            enstrophy,
+            frame_ms,
+        );
+        if let Err(err) = written {
+            eprintln!("failed to write metrics: {err}");
+            exit.send(AppExit);
+        }
+    }
+}
+
+fn write_snapshots(
+    frame: Res<FrameCount>,
+    run: Res<Run>,
+    mut captures: EventReader<FluidCapture>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let due = run.snapshot_every > 0 && frame.0.is_multiple_of(run.snapshot_every);
+    if !due && !run.is_last(frame.0) {
+        captures.clear();
+        return;
+    }
+    for capture in captures.read() {
+        let path = run.out_dir.join(format!("snapshot-{:06}.fpsnap", frame.0));
+        let snapshot = Snapshot::new(capture.fluid, &capture.simvars, capture.state.clone());
+        if let Err(err) = snapshot.save(&path) {
+            eprintln!("failed to save snapshot to {}: {err}", path.display());
+            exit.send(AppExit);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn write_frames(
+    frame: Res<FrameCount>,
+    run: Res<Run>,
+    shapes: Query<(&Position, &Shape, Has<Ball>), Or<(With<Ball>, With<Paddle>)>>,
+    mut captures: EventReader<FluidCapture>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if run.render_every == 0 || !frame.0.is_multiple_of(run.render_every) {
+        captures.clear();
+        return;
+    }
+    for capture in captures.read() {
+        let mut image = Frame::new();
+        capture.material.draw(&mut image);
+        for (position, shape, is_ball) in &shapes {
+            if is_ball {
+                image.fill_circle(position.0, shape.0.x, Vec4::ONE);
+            } else {
+                image.fill_rect(position.0, shape.0, Vec4::ONE);
+            }
+        }
+        let path = run.out_dir.join(format!("frame-{:06}.png", frame.0));
+        if let Err(err) = image.save_png(&path) {
+            eprintln!("failed to save frame to {}: {err}", path.display());
+            exit.send(AppExit);
+        }
+    }
+}
+
+fn stop_after_steps(
+    frame: Res<FrameCount>,
+    run: Res<Run>,
+    mut metrics: ResMut<Metrics>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if !run.is_last(frame.0) {
+        return;
+    }
+    if let Err(err) = metrics.writer.flush() {
+        eprintln!("failed to write metrics: {err}");
+    }
+    println!("ran {} frames, wrote {}", run.steps, run.out_dir.display());
+    exit.send(AppExit);
+}
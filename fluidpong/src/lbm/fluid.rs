@@ -0,0 +1,313 @@
+use bevy::ecs::component::Component;
+use bevy::math::{Vec2, Vec4};
+use rayon::prelude::*;
+
+use crate::ns::fluid::{GRID_X, GRID_Y, HEIGHT, NUM_CELLS, WIDTH};
+use crate::pong::level;
+
+/// Lattice velocities of the D2Q9 model.
+const C: [(i32, i32); 9] =
+    [(0, 0), (1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+/// Quadrature weights of the D2Q9 model.
+const W: [f32; 9] = [
+    4.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 36.0,
+    1.0 / 36.0,
+    1.0 / 36.0,
+    1.0 / 36.0,
+];
+
+/// Index of the lattice velocity pointing the opposite way.
+const OPPOSITE: [usize; 9] = [0, 3, 4, 1, 2, 7, 8, 5, 6];
+
+/// Lattice velocities above this are clamped when imposed on the fluid, since
+/// the method breaks down as the flow approaches the lattice speed of sound.
+pub const MAX_LATTICE_VELOCITY: f32 = 0.1;
+
+const CELL_VELOCITY_SCALE: f32 = 100000.0;
+const CELL_VORTICITY_SCALE: f32 = 20.0;
+const CELL_BASE_OPACITY: f32 = 0.1;
+
+/// A cell that the fluid cannot enter. Populations streaming into it are
+/// bounced back, picking up momentum from the cell's velocity.
+#[derive(Clone, Copy, Debug)]
+pub struct Obstacle {
+    pub velocity: Vec2,
+}
+
+/// A Lattice Boltzmann fluid on a D2Q9 lattice with BGK collision.
+///
+/// The lattice shares its dimensions with the Navier-Stokes grid so that it can
+/// be rendered by the same cell buffer. The arena edges are stationary
+/// bounce-back walls, and paddles and the ball are added each frame as moving
+/// obstacles.
+#[derive(Component)]
+pub struct Fluid {
+    f: Vec<f32>,
+    f_tmp: Vec<f32>,
+    force: Vec<Vec2>,
+    obstacles: Vec<Option<Obstacle>>,
+}
+
+impl Default for Fluid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fluid {
+    pub fn new() -> Fluid {
+        let mut fluid = Fluid {
+            f: vec![0.0; NUM_CELLS * 9],
+            f_tmp: vec![0.0; NUM_CELLS * 9],
+            force: vec![Vec2::ZERO; NUM_CELLS],
+            obstacles: vec![None; NUM_CELLS],
+        };
+        fluid.reset();
+        fluid
+    }
+
+    /// Sets the whole lattice to fluid at rest with unit density.
+    pub fn reset(&mut self) {
+        for cell in self.f.chunks_mut(9) {
+            cell.copy_from_slice(&equilibrium(1.0, Vec2::ZERO));
+        }
+        self.force.fill(Vec2::ZERO);
+        self.obstacles.fill(None);
+    }
+
+    /// Runs one collide-and-stream step with relaxation time `tau`.
+    pub fn step(&mut self, tau: f32) {
+        let omega = 1.0 / tau;
+
+        // Collide: relax towards equilibrium, with body forces applied by
+        // shifting the equilibrium velocity.
+        (self.f.par_chunks_mut(9).zip(self.force.par_iter()).zip(self.obstacles.par_iter()))
+            .for_each(|((cell, force), obstacle)| {
+                if let Some(obstacle) = obstacle {
+                    // Keep solid cells at equilibrium so that they hold sane
+                    // values once the obstacle moves off them.
+                    cell.copy_from_slice(&equilibrium(1.0, obstacle.velocity));
+                    return;
+                }
+                let (rho, u) = moments(cell);
+                let feq = equilibrium(rho, u + *force * tau / rho);
+                for i in 0..9 {
+                    cell[i] += omega * (feq[i] - cell[i]);
+                }
+            });
+
+        // Stream: pull populations from upwind neighbors, bouncing back from
+        // walls and obstacles.
+        let f = &self.f;
+        let obstacles = &self.obstacles;
+        self.f_tmp.par_chunks_mut(9).enumerate().for_each(|(idx, cell)| {
+            if obstacles[idx].is_some() {
+                cell.copy_from_slice(&f[idx * 9..idx * 9 + 9]);
+                return;
+            }
+            let x = (idx % GRID_X as usize) as i32;
+            let y = (idx / GRID_X as usize) as i32;
+            let rho = moments(&f[idx * 9..idx * 9 + 9]).0;
+            for i in 0..9 {
+                let sx = x - C[i].0;
+                let sy = y - C[i].1;
+                let source = cell_index(sx, sy);
+                match source.map(|s| (s, obstacles[s])) {
+                    Some((s, None)) => cell[i] = f[s * 9 + i],
+                    Some((_, Some(obstacle))) => {
+                        let cu = C[i].0 as f32 * obstacle.velocity.x
+                            + C[i].1 as f32 * obstacle.velocity.y;
+                        cell[i] = f[idx * 9 + OPPOSITE[i]] + 6.0 * W[i] * rho * cu;
+                    }
+                    None => cell[i] = f[idx * 9 + OPPOSITE[i]],
+                }
+            }
+        });
+        std::mem::swap(&mut self.f, &mut self.f_tmp);
+    }
+
+    /// Sets every cell to equilibrium at unit density with the velocity given
+    /// by a velocity field in lattice units.
+    pub fn set_velocities(&mut self, velocity_at: impl Fn(Vec2) -> Vec2) {
+        for (idx, cell) in self.f.chunks_mut(9).enumerate() {
+            let center = Vec2::new((idx % GRID_X as usize) as f32, (idx / GRID_X as usize) as f32);
+            let position = (center + 0.5) * cell_size() - Vec2::new(WIDTH, HEIGHT) / 2.0;
+            let velocity = velocity_at(position).clamp_length_max(MAX_LATTICE_VELOCITY);
+            cell.copy_from_slice(&equilibrium(1.0, velocity));
+        }
+    }
+
+    /// Returns the particle distributions, nine per cell.
+    pub fn populations(&self) -> &[f32] {
+        &self.f
+    }
+
+    /// Sets the particle distributions, nine per cell.
+    pub fn set_populations(&mut self, f: &[f32]) {
+        self.f.copy_from_slice(f);
+    }
+
+    /// Removes the forces and moving obstacles added since the last call.
+    pub fn clear_frame_inputs(&mut self) {
+        self.force.fill(Vec2::ZERO);
+        self.obstacles.fill(None);
+    }
+
+    /// Marks every cell whose center lies within the given axis-aligned box as
+    /// an obstacle moving with the given lattice velocity.
+    pub fn add_box_obstacle(&mut self, center: Vec2, half_extents: Vec2, velocity: Vec2) {
+        let velocity = velocity.clamp_length_max(MAX_LATTICE_VELOCITY);
+        self.for_cells_in(center, half_extents, |fluid, idx, offset| {
+            if offset.x.abs() <= half_extents.x && offset.y.abs() <= half_extents.y {
+                fluid.obstacles[idx] = Some(Obstacle { velocity });
+            }
+        });
+    }
+
+    /// Marks every cell whose center lies within the given circle as an
+    /// obstacle moving with the given lattice velocity.
+    pub fn add_circle_obstacle(&mut self, center: Vec2, radius: f32, velocity: Vec2) {
+        let velocity = velocity.clamp_length_max(MAX_LATTICE_VELOCITY);
+        self.for_cells_in(center, Vec2::splat(radius), |fluid, idx, offset| {
+            if offset.length() <= radius {
+                fluid.obstacles[idx] = Some(Obstacle { velocity });
+            }
+        });
+    }
+
+    /// Marks every cell whose center lies inside a level's obstacle as a
+    /// stationary obstacle.
+    pub fn add_level_obstacle(&mut self, obstacle: &level::Obstacle) {
+        let (min, max) = obstacle.bounds();
+        let center = (min + max) / 2.0;
+        self.for_cells_in(center, (max - min) / 2.0, |fluid, idx, offset| {
+            if obstacle.contains(center + offset) {
+                fluid.obstacles[idx] = Some(Obstacle { velocity: Vec2::ZERO });
+            }
+        });
+    }
+
+    /// Adds a body force, in lattice units, to every cell within the given
+    /// radius.
+    pub fn add_force(&mut self, center: Vec2, radius: f32, force: Vec2) {
+        self.for_cells_in(center, Vec2::splat(radius), |fluid, idx, offset| {
+            if offset.length() <= radius {
+                fluid.force[idx] += force;
+            }
+        });
+    }
+
+    fn for_cells_in(
+        &mut self,
+        center: Vec2,
+        half_extents: Vec2,
+        mut f: impl FnMut(&mut Self, usize, Vec2),
+    ) {
+        let cell_size = cell_size();
+        let min = ((center - half_extents + Vec2::new(WIDTH, HEIGHT) / 2.0) / cell_size).floor();
+        let max = ((center + half_extents + Vec2::new(WIDTH, HEIGHT) / 2.0) / cell_size).ceil();
+        for y in (min.y as i32).max(0)..=(max.y as i32).min(GRID_Y as i32 - 1) {
+            for x in (min.x as i32).max(0)..=(max.x as i32).min(GRID_X as i32 - 1) {
+                if let Some(idx) = cell_index(x, y) {
+                    f(self, idx, grid_to_screen(x, y) - center);
+                }
+            }
+        }
+    }
+
+    /// Returns the density and lattice velocity at the given position.
+    pub fn get_moments_at(&self, position: Vec2) -> (f32, Vec2) {
+        match screen_to_grid(position) {
+            Some(idx) if self.obstacles[idx].is_none() => moments(&self.f[idx * 9..idx * 9 + 9]),
+            _ => (1.0, Vec2::ZERO),
+        }
+    }
+
+    /// Returns the lattice velocity at the given position.
+    pub fn get_velocity_at(&self, position: Vec2) -> Vec2 {
+        self.get_moments_at(position).1
+    }
+
+    /// Returns the total mass on the lattice, excluding obstacle cells.
+    pub fn total_mass(&self) -> f32 {
+        (self.f.chunks(9).zip(self.obstacles.iter()))
+            .filter(|(_, obstacle)| obstacle.is_none())
+            .map(|(cell, _)| cell.iter().sum::<f32>())
+            .sum()
+    }
+
+    /// Returns the cell buffer for `ns::FluidGridMaterial`. Velocity goes in
+    /// `x`/`y`, and `z` holds an opacity that highlights vorticity so that
+    /// vortex shedding is visible.
+    pub fn get_cells(&self) -> [Vec4; NUM_CELLS] {
+        let velocities: Vec<Vec2> = (self.f.chunks(9).zip(self.obstacles.iter()))
+            .map(|(cell, obstacle)| match obstacle {
+                Some(_) => Vec2::ZERO,
+                None => moments(cell).1,
+            })
+            .collect();
+
+        let mut cells = [Vec4::ZERO; NUM_CELLS];
+        for y in 0..GRID_Y as i32 {
+            for x in 0..GRID_X as i32 {
+                let idx = cell_index(x, y).unwrap();
+                let at = |dx: i32, dy: i32| {
+                    cell_index(x + dx, y + dy).map(|i| velocities[i]).unwrap_or(Vec2::ZERO)
+                };
+                let curl = (at(1, 0).y - at(-1, 0).y - at(0, 1).x + at(0, -1).x) / 2.0;
+                let u = velocities[idx] * CELL_VELOCITY_SCALE;
+                cells[idx] =
+                    Vec4::new(u.x, u.y, CELL_BASE_OPACITY + curl.abs() * CELL_VORTICITY_SCALE, 0.0);
+            }
+        }
+        cells
+    }
+}
+
+/// Returns the size of a lattice cell in world units.
+pub fn cell_size() -> Vec2 {
+    Vec2::new(WIDTH / GRID_X as f32, HEIGHT / GRID_Y as f32)
+}
+
+fn cell_index(x: i32, y: i32) -> Option<usize> {
+    if x < 0 || y < 0 || x >= GRID_X as i32 || y >= GRID_Y as i32 {
+        None
+    } else {
+        Some((x + y * GRID_X as i32) as usize)
+    }
+}
+
+fn screen_to_grid(position: Vec2) -> Option<usize> {
+    let grid = ((position + Vec2::new(WIDTH, HEIGHT) / 2.0) / cell_size()).floor();
+    cell_index(grid.x as i32, grid.y as i32)
+}
+
+fn grid_to_screen(x: i32, y: i32) -> Vec2 {
+    (Vec2::new(x as f32, y as f32) + 0.5) * cell_size() - Vec2::new(WIDTH, HEIGHT) / 2.0
+}
+
+fn moments(cell: &[f32]) -> (f32, Vec2) {
+    let mut rho = 0.0;
+    let mut momentum = Vec2::ZERO;
+    for i in 0..9 {
+        rho += cell[i];
+        momentum += cell[i] * Vec2::new(C[i].0 as f32, C[i].1 as f32);
+    }
+    (rho, momentum / rho)
+}
+
+fn equilibrium(rho: f32, u: Vec2) -> [f32; 9] {
+    let uu = u.length_squared();
+    let mut feq = [0.0; 9];
+    for i in 0..9 {
+        let cu = C[i].0 as f32 * u.x + C[i].1 as f32 * u.y;
+        feq[i] = W[i] * rho * (1.0 + 3.0 * cu + 4.5 * cu * cu - 1.5 * uu);
+    }
+    feq
+}
@@ -0,0 +1,81 @@
+pub mod fluid;
+mod pongfluid;
+
+use bevy::prelude::*;
+
+use crate::{
+    backend::{field::VelocityField, FluidBackend, FIXED_TIMESTEP},
+    lbm::fluid::Fluid,
+    ns::{
+        fluid::{GRID_X, GRID_Y, HEIGHT, NUM_CELLS, WIDTH},
+        FluidGridMaterial,
+    },
+    simui::FluidSimVars,
+    snapshot::{FluidState, SnapshotError},
+};
+
+impl FluidBackend for Fluid {
+    const NAME: &'static str = "lbm";
+    type Material = FluidGridMaterial;
+
+    fn simvars() -> Vec<(&'static str, f32)> {
+        vec![("tau", 0.6), ("steps", 4.0), ("interact_force", 0.0001)]
+    }
+
+    fn new(_simvars: &FluidSimVars) -> Self {
+        Self::new()
+    }
+
+    fn reset(&mut self, _simvars: &FluidSimVars) {
+        self.reset();
+    }
+
+    // The lattice runs `steps` steps per `FIXED_TIMESTEP`, whatever `dt` is:
+    // the game always steps at that rate, and lattice velocities are turned
+    // into pixels per second through it in `sample_velocity`.
+    fn step(&mut self, _dt: f32, simvars: &FluidSimVars) {
+        // Relaxation times at or below 0.5 give a negative viscosity.
+        let tau = simvars.get("tau").max(0.51);
+        for _ in 0..simvars.get("steps") as u32 {
+            self.step(tau);
+        }
+        self.clear_frame_inputs();
+    }
+
+    fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        pongfluid::from_lattice(self.get_velocity_at(position)) / FIXED_TIMESTEP
+    }
+
+    fn interact(&mut self, position: Vec2, drag: Vec2, pressed: bool, simvars: &FluidSimVars) {
+        if pressed {
+            self.add_force(position, 10.0, drag * simvars.get("interact_force"));
+        }
+    }
+
+    fn seed_velocity(&mut self, field: &VelocityField) {
+        self.set_velocities(|position| {
+            pongfluid::to_lattice(field.sample(position) * FIXED_TIMESTEP)
+        });
+    }
+
+    fn save_state(&self) -> FluidState {
+        FluidState::default().with("f", self.populations().iter().copied())
+    }
+
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError> {
+        self.set_populations(state.get_len("f", NUM_CELLS * 9)?);
+        Ok(())
+    }
+
+    fn material(&self) -> FluidGridMaterial {
+        FluidGridMaterial {
+            screen_size: Vec2::new(WIDTH, HEIGHT),
+            grid_size: Vec2::new(GRID_X as f32, GRID_Y as f32),
+            cells: self.get_cells(),
+        }
+    }
+
+    fn update_material(&self, material: &mut FluidGridMaterial, _simvars: &FluidSimVars) {
+        material.cells = self.get_cells();
+    }
+}
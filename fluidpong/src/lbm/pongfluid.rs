@@ -0,0 +1,50 @@
+use bevy::prelude::*;
+
+use crate::lbm::fluid::cell_size;
+use crate::pong::level::Obstacle;
+use crate::pong::pongfluid::PongFluid;
+
+/// Lattice steps taken per fixed step; pong velocities are in pixels per
+/// fixed step.
+const STEPS_PER_FRAME: f32 = 4.0;
+const PADDLE_HALF_EXTENTS: Vec2 = Vec2::new(5.0, 25.0);
+const BALL_RADIUS: f32 = 5.0;
+const EMIT_FORCE: f32 = 0.0005;
+const EMIT_RADIUS: f32 = 30.0;
+const FLUID_ON_BALL_VELOCITY: f32 = 0.01;
+const FLUID_ON_BALL_DRAG: f32 = 0.001;
+
+pub(super) fn to_lattice(velocity: Vec2) -> Vec2 {
+    velocity / cell_size() / STEPS_PER_FRAME
+}
+
+pub(super) fn from_lattice(velocity: Vec2) -> Vec2 {
+    velocity * cell_size() * STEPS_PER_FRAME
+}
+
+// The default wall suits the lattice: it is weakly compressible, so driving
+// the fluid together from both sides packs it into a denser band.
+impl PongFluid for crate::lbm::fluid::Fluid {
+    fn apply_emit_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_force(position, EMIT_RADIUS, velocity * EMIT_FORCE);
+    }
+    fn apply_paddle_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_box_obstacle(position, PADDLE_HALF_EXTENTS, to_lattice(velocity));
+    }
+    fn apply_ball_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_circle_obstacle(position, BALL_RADIUS, to_lattice(velocity));
+    }
+    fn apply_obstacles(&mut self, obstacles: &[Obstacle]) {
+        for obstacle in obstacles {
+            self.add_level_obstacle(obstacle);
+        }
+    }
+    fn get_fluid_force_at(&self, position: Vec2, velocity: Vec2) -> Vec2 {
+        // The ball itself is an obstacle, so sample the flow just around it.
+        let reach = BALL_RADIUS + cell_size().max_element();
+        let offsets = [Vec2::X, Vec2::Y, Vec2::NEG_X, Vec2::NEG_Y];
+        let flow = offsets.iter().map(|o| self.get_velocity_at(position + *o * reach)).sum::<Vec2>()
+            / offsets.len() as f32;
+        from_lattice(flow) * FLUID_ON_BALL_VELOCITY - velocity * FLUID_ON_BALL_DRAG
+    }
+}
@@ -0,0 +1,21 @@
+pub mod backend;
+pub mod export;
+pub mod headless;
+pub mod sph;
+pub mod lbm;
+pub mod net;
+pub mod pong;
+pub mod ns;
+pub mod pbf;
+pub mod raster;
+pub mod replay;
+pub mod flip;
+pub mod swe;
+pub mod vortex;
+pub mod simui;
+pub mod snapshot;
+
+pub const SCREEN_WIDTH: f32 = 640.0;
+pub const SCREEN_HEIGHT: f32 = 480.0;
+pub const GAME_WIDTH: f32 = SCREEN_WIDTH;
+pub const GAME_HEIGHT: f32 = SCREEN_HEIGHT - 160.0;
@@ -1,50 +1,300 @@
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::input::InputPlugin;
 use bevy::prelude::*;
 use clap::Parser;
-
-pub mod sph;
-pub mod pong;
-pub mod ns;
-pub mod simui;
-
-const SCREEN_WIDTH: f32 = 640.0;
-const SCREEN_HEIGHT: f32 = 480.0;
-const GAME_WIDTH: f32 = SCREEN_WIDTH;
-const GAME_HEIGHT: f32 = SCREEN_HEIGHT - 160.0;
+use fluidpong::{
+    backend, export, headless, net, pong, replay, simui, snapshot, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
+    #[arg(short, long, default_value = backend::registry::DEFAULT_BACKEND)]
     fluid: String,
 
     #[arg(short, long, default_value_t = false)]
     debug: bool,
+
+    /// Steps the simulation by a fixed time step, so that runs are
+    /// reproducible.
+    #[arg(long, default_value_t = false)]
+    deterministic: bool,
+
+    /// Starts from a snapshot, on the fluid it was taken from.
+    #[arg(long)]
+    load_snapshot: Option<PathBuf>,
+
+    /// Records every input to a replay file, written on exit.
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Plays back a replay file, on the fluid and in the mode it was recorded.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Runs without a window or GPU, writing metrics and snapshots to
+    /// `--out`. Implies `--deterministic`.
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+
+    /// How many frames to run in headless mode. Defaults to the length of the
+    /// replay, if any.
+    #[arg(long, requires = "headless")]
+    steps: Option<u32>,
+
+    /// Where headless mode writes its output.
+    #[arg(long, requires = "headless", default_value = "sim-out")]
+    out: PathBuf,
+
+    /// Saves a snapshot every this many frames in headless mode, besides the
+    /// one after the last frame.
+    #[arg(long, requires = "headless", default_value_t = 0)]
+    snapshot_every: u32,
+
+    /// Draws a PNG frame every this many frames in headless mode.
+    #[arg(long, requires = "headless", default_value_t = 0)]
+    render_every: u32,
+
+    /// Sets a simulation variable in headless mode, as `name=value`. Can be
+    /// given more than once.
+    #[arg(
+        long = "set",
+        requires = "headless",
+        value_name = "NAME=VALUE",
+        value_parser = parse_simvar
+    )]
+    simvars: Vec<(String, f32)>,
+
+    /// Exports the fluid to this directory, for ParaView or plotting
+    /// scripts.
+    #[arg(long)]
+    export: Option<PathBuf>,
+
+    /// Exports every this many frames.
+    #[arg(long, requires = "export", default_value_t = 1)]
+    export_every: u32,
+
+    #[arg(long, requires = "export", value_enum, default_value_t)]
+    export_format: export::ExportFormat,
+
+    /// Lets the computer play the left paddle.
+    #[arg(long, value_enum, value_name = "DIFFICULTY")]
+    ai1: Option<pong::ai::Difficulty>,
+
+    /// Lets the computer play the right paddle.
+    #[arg(long, value_enum, value_name = "DIFFICULTY")]
+    ai2: Option<pong::ai::Difficulty>,
+
+    /// The points needed to win a match.
+    #[arg(long, default_value_t = 11)]
+    win_score: u32,
+
+    /// Makes the winner need a lead of two points.
+    #[arg(long, default_value_t = false)]
+    win_by_two: bool,
+
+    /// Reads the key and gamepad bindings from this file, writing the
+    /// defaults to it first if it doesn't exist.
+    #[arg(long)]
+    controls: Option<PathBuf>,
+
+    /// Plays in the level in this RON file, with its obstacles, fans, vents
+    /// and goal size, instead of the empty arena.
+    #[arg(long, conflicts_with_all = ["replay", "connect"])]
+    level: Option<PathBuf>,
+
+    /// Hosts a network game on this UDP port, playing the left paddle. The
+    /// other side joins with `--connect`. Implies `--deterministic`.
+    #[arg(
+        long,
+        value_name = "PORT",
+        conflicts_with_all = ["replay", "record", "ai1", "ai2", "debug", "load_snapshot"]
+    )]
+    host: Option<u16>,
+
+    /// Joins a network game hosted at this address, playing the right paddle
+    /// on the host's fluid and rules. Implies `--deterministic`.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        conflicts_with_all = ["host", "replay", "record", "ai1", "ai2", "debug", "load_snapshot"]
+    )]
+    connect: Option<String>,
+
+    /// Compares the game with the other side's every this many frames in a
+    /// hosted network game, stopping if they differ.
+    #[arg(long, requires = "host", default_value_t = 60)]
+    check_every: u32,
+}
+
+fn parse_simvar(arg: &str) -> Result<(String, f32), String> {
+    let (name, value) = arg.split_once('=').ok_or("expected `name=value`")?;
+    let value = value.parse().map_err(|err| format!("invalid value `{value}`: {err}"))?;
+    Ok((name.to_string(), value))
 }
 
 fn main() {
     let args = Args::parse();
+    let snapshot = args.load_snapshot.as_ref().map(|path| {
+        snapshot::Snapshot::load(path)
+            .and_then(|snapshot| Ok((snapshot.backend()?, snapshot)))
+            .unwrap_or_else(|err| {
+                eprintln!("failed to load snapshot from {}: {err}", path.display());
+                std::process::exit(1);
+            })
+    });
+    let replay = args.replay.as_ref().map(|path| {
+        replay::Replay::load(path).unwrap_or_else(|err| {
+            eprintln!("failed to load replay from {}: {err}", path.display());
+            std::process::exit(1);
+        })
+    });
+    let level = args.level.as_ref().map(|path| {
+        pong::level::Level::load(path).unwrap_or_else(|err| {
+            eprintln!("failed to load level from {}: {err}", path.display());
+            std::process::exit(1);
+        })
+    });
+    let headless = args.headless;
+    // Nobody can press start in a headless run.
+    let first_state =
+        if headless { pong::state::GameState::Serve } else { pong::state::GameState::Title };
+    let session = if let Some(port) = args.host {
+        println!("waiting for the other side on port {port}");
+        let settings = net::MatchSettings {
+            fluid: backend::registry::find(&args.fluid).name.to_string(),
+            rules: pong::state::MatchRules {
+                win_score: args.win_score,
+                win_by_two: args.win_by_two,
+            },
+            start: first_state,
+            check_every: args.check_every,
+            level: level.clone().unwrap_or_default(),
+        };
+        Some(net::Session::host(port, settings))
+    } else {
+        args.connect.as_ref().map(net::Session::join)
+    };
+    let session = session.map(|session| {
+        session.unwrap_or_else(|err| {
+            eprintln!("failed to start the network game: {err}");
+            std::process::exit(1);
+        })
+    });
+    let initial = match (&snapshot, &replay, &session) {
+        (Some((backend, _)), _, _) => backend.name,
+        (None, Some(replay), _) => backend::registry::find(&replay.fluid).name,
+        (None, None, Some(session)) => backend::registry::find(&session.settings.fluid).name,
+        (None, None, None) => backend::registry::find(&args.fluid).name,
+    };
+    let debug = args.debug || replay.as_ref().is_some_and(|replay| replay.debug);
+    let ai = match &replay {
+        Some(replay) => replay.ai,
+        None => pong::ai::AiPlayers { player1: args.ai1, player2: args.ai2 },
+    };
+    let (start, rules) = match (&replay, &session) {
+        (Some(replay), _) => (replay.start, replay.rules),
+        (None, Some(session)) => (session.settings.start, session.settings.rules),
+        (None, None) => (
+            first_state,
+            pong::state::MatchRules { win_score: args.win_score, win_by_two: args.win_by_two },
+        ),
+    };
+    let controls = match (&replay, &args.controls) {
+        (Some(replay), _) => replay.controls.clone(),
+        (None, Some(path)) => {
+            pong::controls::Controls::load_or_create(path).unwrap_or_else(|err| {
+                eprintln!("failed to load controls from {}: {err}", path.display());
+                std::process::exit(1);
+            })
+        }
+        (None, None) => pong::controls::Controls::default(),
+    };
+    let level = match (&replay, &session) {
+        (Some(replay), _) => replay.level.clone(),
+        (None, Some(session)) => session.settings.level.clone(),
+        (None, None) => level.unwrap_or_default(),
+    };
 
     let mut app = App::new();
-    app.add_systems(Startup, (spawn_camera, resize_window));
-    app.add_plugins(DefaultPlugins
-        .set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "FluidPong".to_string(),
-                ..Default::default()
-            }),
-            ..Default::default()
-        }));
-    app.insert_resource(ClearColor(Color::BLACK));
-    if args.fluid == "sph" {
-        app.add_plugins(sph::FluidPlugin {debug: args.debug});
+    if headless {
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::ZERO)),
+            InputPlugin,
+        ));
+        app.add_plugins(headless::HeadlessPlugin {
+            steps: (args.steps)
+                .or(replay.as_ref().map(|replay| replay.frames))
+                .unwrap_or(headless::DEFAULT_STEPS),
+            out_dir: args.out,
+            snapshot_every: args.snapshot_every,
+            render_every: args.render_every,
+            simvars: args.simvars,
+        });
     } else {
-        app.add_plugins(ns::FluidPlugin {debug: args.debug});
+        app.add_systems(Startup, (spawn_camera, resize_window));
+        app.add_plugins(DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    title: "FluidPong".to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+        app.insert_resource(ClearColor(Color::BLACK));
+    }
+    let online = session.is_some();
+    if args.deterministic || headless || args.record.is_some() || replay.is_some() || online {
+        app.add_plugins(backend::DeterministicPlugin);
+    }
+    app.add_plugins(backend::FluidBackendsPlugin {
+        initial,
+        options: backend::FluidOptions { debug, headless },
+    });
+    app.add_plugins(snapshot::SnapshotPlugin {
+        load: snapshot.map(|(_, snapshot)| snapshot),
+        debug,
+    });
+    if let Some(path) = args.record {
+        app.add_plugins(replay::ReplayPlugin::Record {
+            path,
+            fluid: initial,
+            debug,
+            ai,
+            start,
+            rules,
+            controls: controls.clone(),
+            level: level.clone(),
+        });
     }
-    app.add_plugins(pong::PongPlugin);
-    if args.debug {
-        app.add_plugins(simui::SimUIPlugin {fluid_type: args.fluid});
+    if let Some(replay) = replay {
+        app.add_plugins(replay::ReplayPlugin::Play(replay));
+    }
+    if let Some(dir) = args.export {
+        app.add_plugins(export::ExportPlugin {
+            dir,
+            every: args.export_every,
+            format: args.export_format,
+        });
+    }
+    let mut net_failed = None;
+    if let Some(session) = session {
+        let net = net::NetPlugin::new(session);
+        net_failed = Some(net.failed());
+        app.add_plugins(net);
+    }
+    app.add_plugins(pong::PongPlugin { headless, ai, rules, start, controls, online, level });
+    if debug && !headless {
+        app.add_plugins(simui::SimUIPlugin);
     }
     app.run();
+    if net_failed.is_some_and(|failed| failed.load(Ordering::Relaxed)) {
+        std::process::exit(1);
+    }
 }
 
 fn spawn_camera(mut commands: Commands) {
@@ -0,0 +1,677 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bevy::app::AppExit;
+use bevy::input::gamepad::{GamepadAxis, GamepadButton};
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{CaptureFluid, FluidCapture};
+use crate::pong::controls::{Controls, ControlsSet, PaddleInput};
+use crate::pong::level::Level;
+use crate::pong::state::{GameState, MatchRules};
+use crate::pong::{Ball, Paddle, Player1, Position, Score, Velocity};
+use crate::snapshot::FluidState;
+
+/// The version of the network protocol. Both sides must run the same one.
+pub const NET_VERSION: u32 = 3;
+
+/// How many frames after it is read an input is played, so that it has time
+/// to reach the other side first.
+const INPUT_DELAY: u32 = 3;
+/// How long to wait for the other side before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the host waits for the other side to join, which is started by
+/// hand.
+const HOST_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often inputs the other side hasn't acknowledged are sent again while
+/// waiting for it.
+const RESEND_EVERY: Duration = Duration::from_millis(15);
+/// How many of its latest state hashes each side sends with its inputs.
+const HASHES_SENT: usize = 4;
+/// Keys that act on the whole game instead of a paddle. They are shared, so
+/// that both sides act on them on the same frame.
+const SHARED_KEYS: [KeyCode; 3] = [KeyCode::Space, KeyCode::KeyP, KeyCode::Tab];
+/// The largest message that can be received.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// Which paddle a side plays. The host plays the left one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// What the two sides play by. The host decides, and the other side takes
+/// it when joining.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchSettings {
+    pub fluid: String,
+    pub rules: MatchRules,
+    /// The state the game starts in, the same on both sides.
+    pub start: GameState,
+    /// How many frames apart the sides compare their games.
+    pub check_every: u32,
+    /// The level both sides play in.
+    pub level: Level,
+}
+
+/// One side's input for a frame.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FrameInput {
+    pub paddle: PaddleInput,
+    /// The shared keys pressed.
+    pub keys: Vec<KeyCode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum Message {
+    Hello {
+        version: u32,
+    },
+    Welcome {
+        version: u32,
+        settings: MatchSettings,
+    },
+    Inputs {
+        /// The frame of the first input.
+        first: u32,
+        inputs: Vec<FrameInput>,
+        /// How many frames of the receiver's inputs the sender has.
+        ack: u32,
+        /// The sender's latest state hashes, by frame.
+        hashes: Vec<(u32, u64)>,
+    },
+    Bye,
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(std::io::Error),
+    Format(String),
+    Version(u32),
+    Timeout,
+    PeerLeft,
+    /// The two sides' games differ as of this frame.
+    Desync(u32),
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::Io(err) => write!(f, "{err}"),
+            NetError::Format(message) => write!(f, "malformed message: {message}"),
+            NetError::Version(version) => {
+                write!(f, "the other side speaks protocol version {version}, not {NET_VERSION}")
+            }
+            NetError::Timeout => write!(f, "the other side stopped responding"),
+            NetError::PeerLeft => write!(f, "the other side left"),
+            NetError::Desync(frame) => {
+                write!(f, "the games went out of sync at frame {frame}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<std::io::Error> for NetError {
+    fn from(err: std::io::Error) -> Self {
+        NetError::Io(err)
+    }
+}
+
+/// A game between two sides over UDP, in lockstep: each side sends its
+/// inputs, and plays a frame only once it has both sides' inputs for it.
+/// Each input is played `INPUT_DELAY` frames after it is read, which hides
+/// the round trip on a local network.
+///
+/// Since the game is deterministic, both sides then play the same game.
+/// They check that by exchanging hashes of their state.
+pub struct Session {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    pub side: Side,
+    pub settings: MatchSettings,
+    /// This side's inputs, by frame.
+    local: Vec<FrameInput>,
+    /// The other side's inputs, by frame.
+    remote: Vec<FrameInput>,
+    /// How many of the local inputs the other side has.
+    acked: u32,
+    local_hashes: BTreeMap<u32, u64>,
+    remote_hashes: BTreeMap<u32, u64>,
+    /// How many hashes matched.
+    matched: u32,
+    /// The last frame whose hashes were compared, so that ones sent again
+    /// are ignored.
+    compared: Option<u32>,
+    desync: Option<u32>,
+    peer_left: bool,
+}
+
+impl Session {
+    /// Waits for the other side to join on `port`, and plays the left
+    /// paddle. Gives up after `HOST_TIMEOUT`.
+    pub fn host(port: u16, settings: MatchSettings) -> Result<Self, NetError> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_read_timeout(Some(RESEND_EVERY * 10))?;
+        let mut buf = vec![0; MAX_MESSAGE];
+        let start = Instant::now();
+        while start.elapsed() < HOST_TIMEOUT {
+            let (len, from) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err) if is_timeout(&err) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if let Ok(Message::Hello { version }) = decode(&buf[..len]) {
+                let welcome = Message::Welcome { version: NET_VERSION, settings: settings.clone() };
+                socket.send_to(&encode(&welcome)?, from)?;
+                // Tell a mismatched side why, then keep waiting.
+                if version == NET_VERSION {
+                    return Self::new(socket, from, Side::Left, settings);
+                }
+            }
+        }
+        Err(NetError::Timeout)
+    }
+
+    /// Joins the side hosting at `address`, and plays the right paddle.
+    pub fn join(address: impl ToSocketAddrs) -> Result<Self, NetError> {
+        let peer = (address.to_socket_addrs()?.next())
+            .ok_or_else(|| NetError::Io(ErrorKind::AddrNotAvailable.into()))?;
+        let local = if peer.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_read_timeout(Some(RESEND_EVERY * 10))?;
+        let hello = encode(&Message::Hello { version: NET_VERSION })?;
+        let mut buf = vec![0; MAX_MESSAGE];
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            socket.send_to(&hello, peer)?;
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == peer => len,
+                Ok(_) => continue,
+                Err(err) if is_timeout(&err) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if let Ok(Message::Welcome { version, settings }) = decode(&buf[..len]) {
+                if version != NET_VERSION {
+                    return Err(NetError::Version(version));
+                }
+                return Self::new(socket, peer, Side::Right, settings);
+            }
+        }
+        Err(NetError::Timeout)
+    }
+
+    fn new(
+        socket: UdpSocket,
+        peer: SocketAddr,
+        side: Side,
+        settings: MatchSettings,
+    ) -> Result<Self, NetError> {
+        socket.set_read_timeout(Some(RESEND_EVERY))?;
+        // Nothing is pressed before the first inputs arrive.
+        let delay = vec![FrameInput::default(); INPUT_DELAY as usize];
+        Ok(Self {
+            socket,
+            peer,
+            side,
+            settings,
+            local: delay.clone(),
+            remote: delay,
+            acked: 0,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            matched: 0,
+            compared: None,
+            desync: None,
+            peer_left: false,
+        })
+    }
+
+    /// Adds this side's next input, to be played after the ones before it.
+    pub fn push(&mut self, input: FrameInput) {
+        self.local.push(input);
+    }
+
+    /// Waits for both sides' inputs for `frame`, and returns this side's and
+    /// the other side's.
+    pub fn inputs(&mut self, frame: u32) -> Result<(FrameInput, FrameInput), NetError> {
+        let start = Instant::now();
+        loop {
+            self.send()?;
+            self.receive()?;
+            if let Some(frame) = self.desync {
+                return Err(NetError::Desync(frame));
+            }
+            if let (Some(local), Some(remote)) =
+                (self.local.get(frame as usize), self.remote.get(frame as usize))
+            {
+                return Ok((local.clone(), remote.clone()));
+            }
+            if self.peer_left {
+                return Err(NetError::PeerLeft);
+            }
+            if start.elapsed() > TIMEOUT {
+                return Err(NetError::Timeout);
+            }
+        }
+    }
+
+    /// Records the hash of this side's state after `frame`, to compare with
+    /// the other side's.
+    pub fn check(&mut self, frame: u32, hash: u64) {
+        self.local_hashes.insert(frame, hash);
+        // Only the latest are sent, and kept for sending after they are
+        // compared, so that the other side can compare them too.
+        while self.local_hashes.len() > HASHES_SENT {
+            self.local_hashes.pop_first();
+        }
+        self.compare();
+    }
+
+    /// How many frames' hashes were compared, all of which matched.
+    pub fn matched(&self) -> u32 {
+        self.matched
+    }
+
+    /// Tells the other side that this one is leaving.
+    pub fn leave(&self) {
+        if let Ok(bye) = encode(&Message::Bye) {
+            let _ = self.socket.send_to(&bye, self.peer);
+        }
+    }
+
+    fn send(&self) -> Result<(), NetError> {
+        let first = self.acked.min(self.local.len() as u32);
+        let message = Message::Inputs {
+            first,
+            inputs: self.local[first as usize..].to_vec(),
+            ack: self.remote.len() as u32,
+            hashes: (self.local_hashes.iter().rev().take(HASHES_SENT))
+                .map(|(frame, hash)| (*frame, *hash))
+                .collect(),
+        };
+        self.socket.send_to(&encode(&message)?, self.peer)?;
+        Ok(())
+    }
+
+    /// Takes in what the other side sent, waiting up to `RESEND_EVERY` for
+    /// the first message.
+    fn receive(&mut self) -> Result<(), NetError> {
+        let mut buf = vec![0; MAX_MESSAGE];
+        let mut wait = true;
+        let result = loop {
+            // Only the first message is waited for.
+            if let Err(err) = self.socket.set_nonblocking(!wait) {
+                break Err(err.into());
+            }
+            let len = match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) if from == self.peer => len,
+                Ok(_) => continue,
+                Err(err) if is_timeout(&err) => break Ok(()),
+                Err(err) => break Err(err.into()),
+            };
+            if let Err(err) = decode(&buf[..len]).and_then(|message| self.handle(message)) {
+                break Err(err);
+            }
+            wait = false;
+        };
+        self.socket.set_nonblocking(false)?;
+        result
+    }
+
+    fn handle(&mut self, message: Message) -> Result<(), NetError> {
+        match message {
+            Message::Hello { .. } => {
+                // The welcome got lost.
+                let settings = self.settings.clone();
+                let welcome = Message::Welcome { version: NET_VERSION, settings };
+                self.socket.send_to(&encode(&welcome)?, self.peer)?;
+            }
+            Message::Welcome { .. } => {}
+            Message::Inputs { first, inputs, ack, hashes } => {
+                self.acked = self.acked.max(ack);
+                let known = self.remote.len() as u32;
+                if first <= known {
+                    self.remote.extend(inputs.into_iter().skip((known - first) as usize));
+                }
+                let compared = self.compared;
+                let new = hashes.into_iter().filter(|(frame, _)| Some(*frame) > compared);
+                self.remote_hashes.extend(new);
+                self.compare();
+            }
+            Message::Bye => self.peer_left = true,
+        }
+        Ok(())
+    }
+
+    /// Compares the hashes both sides have, and forgets the other side's.
+    fn compare(&mut self) {
+        let both: Vec<u32> = (self.local_hashes.keys())
+            .filter(|frame| Some(**frame) > self.compared)
+            .filter(|frame| self.remote_hashes.contains_key(frame))
+            .copied()
+            .collect();
+        for frame in both {
+            if self.local_hashes[&frame] == self.remote_hashes[&frame] {
+                self.matched += 1;
+            } else if self.desync.is_none() {
+                self.desync = Some(frame);
+            }
+            self.remote_hashes.remove(&frame);
+            self.compared = self.compared.max(Some(frame));
+        }
+    }
+}
+
+fn encode(message: &Message) -> Result<Vec<u8>, NetError> {
+    ron::to_string(message).map(String::into_bytes).map_err(|err| NetError::Format(err.to_string()))
+}
+
+fn decode(bytes: &[u8]) -> Result<Message, NetError> {
+    ron::de::from_bytes(bytes).map_err(|err| NetError::Format(err.to_string()))
+}
+
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Plays the game against another side over the network. The paddles' input
+/// and the shared keys come from both sides, and every frame waits for the
+/// other side's, so the game goes at the pace of the slower one.
+///
+/// Every `check_every` frames of the match settings, both sides hash the
+/// game and the fluid, and the game stops if they differ. The game must be
+/// deterministic.
+pub struct NetPlugin {
+    session: Mutex<Option<Session>>,
+    failed: Arc<AtomicBool>,
+}
+
+impl NetPlugin {
+    pub fn new(session: Session) -> Self {
+        Self { session: Mutex::new(Some(session)), failed: Arc::default() }
+    }
+
+    /// Set once the game failed, such as on a desync, so that the process
+    /// can exit with an error once the app has shut down.
+    pub fn failed(&self) -> Arc<AtomicBool> {
+        self.failed.clone()
+    }
+}
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        let session = (self.session.lock().unwrap().take()).expect("the session is used once");
+        // Plugins added earlier, such as the exporter, may already capture the
+        // fluid every frame.
+        let capture_always = app.world.contains_resource::<CaptureFluid>();
+        app.insert_resource(Lockstep {
+            session,
+            frame: 0,
+            injected: Vec::new(),
+            capture_always,
+            failed: self.failed.clone(),
+        })
+            .add_systems(
+                PreUpdate,
+                (
+                    release_injected_keys.before(InputSystem),
+                    exchange_inputs.after(InputSystem).in_set(ControlsSet),
+                    request_capture.after(exchange_inputs),
+                ),
+            )
+            .add_systems(PostUpdate, check_state)
+            .add_systems(Last, leave);
+    }
+}
+
+#[derive(Resource)]
+struct Lockstep {
+    session: Session,
+    /// The frame being played.
+    frame: u32,
+    /// Shared keys pressed for the other side, to release before the next
+    /// frame's input.
+    injected: Vec<KeyCode>,
+    /// Whether the fluid is captured every frame anyway, rather than only on
+    /// the frames that are checked.
+    capture_always: bool,
+    failed: Arc<AtomicBool>,
+}
+
+impl Lockstep {
+    /// Whether the state is checked after this frame.
+    fn checks_frame(&self) -> bool {
+        let check_every = self.session.settings.check_every;
+        check_every != 0 && self.frame.is_multiple_of(check_every)
+    }
+}
+
+fn release_injected_keys(
+    mut lockstep: ResMut<Lockstep>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+) {
+    for key in lockstep.injected.drain(..) {
+        keyboard_input.release(key);
+    }
+}
+
+/// Sends this side's input, and plays both sides' inputs for this frame
+/// once the other side's has arrived.
+fn exchange_inputs(
+    mut lockstep: ResMut<Lockstep>,
+    controls: Res<Controls>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut paddles: Query<(&mut PaddleInput, Has<Player1>)>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let lockstep = &mut *lockstep;
+    let side = lockstep.session.side;
+    let player = match side {
+        Side::Left => &controls.player1,
+        Side::Right => &controls.player2,
+    };
+    lockstep.session.push(FrameInput {
+        paddle: player.read(&keyboard_input, &gamepad_buttons, &gamepad_axes, controls.dead_zone),
+        keys: SHARED_KEYS.into_iter().filter(|key| keyboard_input.just_pressed(*key)).collect(),
+    });
+    let (local, remote) = match lockstep.session.inputs(lockstep.frame) {
+        Ok(inputs) => inputs,
+        Err(NetError::PeerLeft) => {
+            println!("the other side left");
+            exit.send(AppExit);
+            return;
+        }
+        Err(err) => {
+            // Exit through the app, so that the replay and the headless
+            // output are still written.
+            eprintln!("network game failed: {err}");
+            lockstep.failed.store(true, Ordering::Relaxed);
+            exit.send(AppExit);
+            return;
+        }
+    };
+
+    // The shared keys act on the frame both sides play them, not when they
+    // are pressed.
+    for key in SHARED_KEYS {
+        keyboard_input.clear_just_pressed(key);
+    }
+    for key in local.keys.iter().chain(&remote.keys) {
+        if keyboard_input.pressed(*key) {
+            // Held on this side too, so only pressed again.
+            keyboard_input.release(*key);
+        } else {
+            lockstep.injected.push(*key);
+        }
+        keyboard_input.press(*key);
+    }
+
+    let (left, right) = match side {
+        Side::Left => (local, remote),
+        Side::Right => (remote, local),
+    };
+    for (mut input, is_player1) in &mut paddles {
+        *input = if is_player1 { left.paddle.clone() } else { right.paddle.clone() };
+    }
+}
+
+/// Asks the fluid for a capture on the frames whose state is checked, since
+/// copying it every frame is slow.
+fn request_capture(mut commands: Commands, lockstep: Res<Lockstep>) {
+    if lockstep.capture_always {
+        return;
+    }
+    if lockstep.checks_frame() {
+        commands.insert_resource(CaptureFluid);
+    } else {
+        commands.remove_resource::<CaptureFluid>();
+    }
+}
+
+/// Hashes the game and the fluid every `check_every` frames, for the other
+/// side to compare with.
+fn check_state(
+    mut lockstep: ResMut<Lockstep>,
+    mut captures: EventReader<FluidCapture>,
+    state: Res<State<GameState>>,
+    score: Res<Score>,
+    ball: Query<(&Position, &Velocity), With<Ball>>,
+    paddles: Query<(&Position, Has<Player1>), With<Paddle>>,
+) {
+    let capture = captures.read().last();
+    let checked = lockstep.checks_frame();
+    let frame = lockstep.frame;
+    lockstep.frame += 1;
+    if !checked {
+        return;
+    }
+
+    let mut paddles: Vec<_> = paddles.iter().collect();
+    paddles.sort_by_key(|(_, is_player1)| *is_player1);
+    let mut game = FluidState::default()
+        .with("state", [*state.get() as u8 as f32])
+        .with("score", [score.player1 as f32, score.player2 as f32])
+        .with_vec2s("ball", ball.iter().flat_map(|(position, velocity)| [position.0, velocity.0]))
+        .with_vec2s("paddles", paddles.into_iter().map(|(position, _)| position.0));
+    if let Some(capture) = capture {
+        for (name, values) in &capture.state.arrays {
+            game.arrays.insert(format!("{}.{name}", capture.fluid), values.clone());
+        }
+    }
+    lockstep.session.check(frame, game.checksum());
+}
+
+fn leave(exit: EventReader<AppExit>, lockstep: Res<Lockstep>) {
+    if exit.is_empty() {
+        return;
+    }
+    lockstep.session.leave();
+    println!("the game matched the other side's at {} checks", lockstep.session.matched());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn settings() -> MatchSettings {
+        MatchSettings {
+            fluid: "sph".to_string(),
+            rules: MatchRules { win_score: 11, win_by_two: false },
+            start: GameState::Serve,
+            check_every: 5,
+            level: Level::default(),
+        }
+    }
+
+    fn input(frame: u32) -> FrameInput {
+        FrameInput {
+            paddle: PaddleInput { movement: frame as f32 / 100.0, ..default() },
+            keys: if frame.is_multiple_of(10) { vec![KeyCode::Space] } else { Vec::new() },
+        }
+    }
+
+    /// Plays `frames` frames as one side, hashing `hash(frame)` as often as the
+    /// settings say, and returns the other side's inputs.
+    fn play(
+        mut session: Session,
+        frames: u32,
+        hash: impl Fn(u32) -> u64,
+    ) -> Result<(Session, Vec<FrameInput>), NetError> {
+        let mut remote = Vec::new();
+        for frame in 0..frames {
+            session.push(input(frame));
+            let (local, other) = session.inputs(frame)?;
+            let expected = frame.checked_sub(INPUT_DELAY).map_or_else(FrameInput::default, input);
+            assert_eq!(local, expected);
+            remote.push(other);
+            if frame.is_multiple_of(session.settings.check_every) {
+                session.check(frame, hash(frame));
+            }
+        }
+        Ok((session, remote))
+    }
+
+    /// Hosts on a free port, joins it from another thread, and plays both
+    /// sides there.
+    fn play_both(
+        frames: u32,
+        host_hash: impl Fn(u32) -> u64 + Send + 'static,
+        join_hash: impl Fn(u32) -> u64 + Send + 'static,
+    ) -> [Result<(Session, Vec<FrameInput>), NetError>; 2] {
+        let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let host = thread::spawn(move || {
+            let session = Session::host(port, settings()).unwrap();
+            assert_eq!(session.side, Side::Left);
+            let result = play(session, frames, host_hash);
+            if let Ok((session, _)) = &result {
+                session.leave();
+            }
+            result
+        });
+        let session = Session::join(("127.0.0.1", port)).unwrap();
+        assert_eq!(session.side, Side::Right);
+        assert_eq!(session.settings, settings());
+        let joined = play(session, frames, join_hash);
+        [host.join().unwrap(), joined]
+    }
+
+    #[test]
+    fn sides_play_each_others_inputs() {
+        let [host, joined] = play_both(100, |frame| frame as u64, |frame| frame as u64);
+        let expected: Vec<_> = (0..100u32)
+            .map(|frame| frame.checked_sub(INPUT_DELAY).map_or_else(FrameInput::default, input))
+            .collect();
+        let (_, host_remote) = host.unwrap();
+        let (joined, joined_remote) = joined.unwrap();
+        assert_eq!(host_remote, expected);
+        assert_eq!(joined_remote, expected);
+        assert!(joined.matched() > 10, "only {} hashes matched", joined.matched());
+    }
+
+    #[test]
+    fn differing_states_are_caught() {
+        let [host, joined] =
+            play_both(100, |frame| frame as u64, |frame| if frame < 40 { frame as u64 } else { 0 });
+        for result in [host, joined] {
+            match result {
+                Err(NetError::Desync(frame)) => assert_eq!(frame, 40),
+                Err(err) => panic!("expected a desync, got {err}"),
+                Ok(_) => panic!("expected a desync"),
+            }
+        }
+    }
+}
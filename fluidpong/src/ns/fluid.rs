@@ -3,11 +3,14 @@ use bevy::{
     math::{Vec2, Vec4},
 };
 
+use crate::pong::level::Obstacle;
 use crate::{GAME_HEIGHT, GAME_WIDTH};
 
-use super::math::index;
+use super::math::GridSize;
 
 pub const INTERACT_VELOCITY: f32 = 5000.0;
+/// The solver time that passes in one fixed time step.
+pub const DT: f32 = 0.00001;
 pub const GRID_X: u32 = 96; //128;
 pub const GRID_Y: u32 = 72;
 pub const NUM_CELLS: usize = (GRID_X * GRID_Y) as usize;
@@ -16,78 +19,141 @@ pub const HEIGHT: f32 = GAME_HEIGHT;
 
 #[derive(Component)]
 pub struct Fluid {
+    pub size: GridSize,
     pub s: Vec<f32>,
     pub density: Vec<f32>,
     pub vx: Vec<f32>,
     pub vy: Vec<f32>,
     pub vx0: Vec<f32>,
     pub vy0: Vec<f32>,
+    /// Which cells are inside an obstacle, and the obstacles they were
+    /// worked out from.
+    pub solid: Vec<bool>,
+    obstacles: Vec<Obstacle>,
+    /// The solver time that passes in one fixed time step, from the `dt`
+    /// simulation variable.
+    pub dt: f32,
+}
+
+impl Default for Fluid {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Fluid {
     pub fn new() -> Fluid {
+        Self::with_size(GridSize::DEFAULT)
+    }
+
+    /// A fluid on a grid of another size, stretched over the same arena. The
+    /// game and the renderer use the default size.
+    pub fn with_size(size: GridSize) -> Fluid {
+        let cells = size.cells();
         Fluid {
-            s: vec![0.0; NUM_CELLS],
-            density: vec![0.0; NUM_CELLS],
-            vx: vec![0.0; NUM_CELLS],
-            vy: vec![0.0; NUM_CELLS],
-            vx0: vec![0.0; NUM_CELLS],
-            vy0: vec![0.0; NUM_CELLS],
+            size,
+            s: vec![0.0; cells],
+            density: vec![0.0; cells],
+            vx: vec![0.0; cells],
+            vy: vec![0.0; cells],
+            vx0: vec![0.0; cells],
+            vy0: vec![0.0; cells],
+            solid: vec![false; cells],
+            obstacles: Vec::new(),
+            dt: DT,
         }
     }
 
     pub fn reset(&mut self) {
-        self.s = vec![0.0; NUM_CELLS];
-        self.density = vec![0.0; NUM_CELLS];
-        self.vx = vec![0.0; NUM_CELLS];
-        self.vy = vec![0.0; NUM_CELLS];
-        self.vx0 = vec![0.0; NUM_CELLS];
-        self.vy0 = vec![0.0; NUM_CELLS];
+        let cells = self.size.cells();
+        self.s = vec![0.0; cells];
+        self.density = vec![0.0; cells];
+        self.vx = vec![0.0; cells];
+        self.vy = vec![0.0; cells];
+        self.vx0 = vec![0.0; cells];
+        self.vy0 = vec![0.0; cells];
+    }
+
+    pub fn set_obstacles(&mut self, obstacles: &[Obstacle]) {
+        if self.obstacles == obstacles {
+            return;
+        }
+        self.obstacles = obstacles.to_vec();
+        for i in 0..self.size.x {
+            for j in 0..self.size.y {
+                let center = self.grid_to_screen(i, j);
+                self.solid[self.size.index(i, j)] = obstacles.iter().any(|o| o.contains(center));
+            }
+        }
+    }
+
+    /// Empties the cells inside obstacles of what the paddles emitted into
+    /// them and of the boundary values the solver left there, so that they
+    /// neither show nor push the ball.
+    pub fn clear_solids(&mut self) {
+        for i in 0..self.size.cells() {
+            if self.solid[i] {
+                self.density[i] = 0.0;
+                self.vx[i] = 0.0;
+                self.vy[i] = 0.0;
+            }
+        }
     }
 
     pub fn add_density(&mut self, position: Vec2, amount: f32) {
-        let (i, j) = screen_to_grid(position);
+        let (i, j) = self.screen_to_grid(position);
         self.add_density_grid(i, j, amount)
     }
 
     pub fn add_density_grid(&mut self, i: u32, j: u32, amount: f32) {
-        self.density[index(i, j)] += amount;
+        self.density[self.size.index(i, j)] += amount;
     }
 
     pub fn add_velocity(&mut self, position: Vec2, amount: Vec2) {
-        let (i, j) = screen_to_grid(position);
+        let (i, j) = self.screen_to_grid(position);
         self.add_velocity_grid(i, j, amount.x, amount.y)
     }
 
     pub fn add_velocity_grid(&mut self, x: u32, y: u32, amount_x: f32, amount_y: f32) {
-        let i = index(x, y);
+        let i = self.size.index(x, y);
         let d = self.density[i];
         self.vx[i] += amount_x * d;
         self.vy[i] += amount_y * d;
     }
 
     pub fn get_density_at(&self, position: Vec2) -> f32 {
-        let (i, j) = screen_to_grid(position);
-        return self.density[index(i, j)];
+        let (i, j) = self.screen_to_grid(position);
+        self.density[self.size.index(i, j)]
     }
 
     pub fn get_velocity_at(&self, position: Vec2) -> Vec2 {
-        let (x, y) = screen_to_grid(position);
-        let i = index(x, y);
-        return Vec2::new(self.vx[i], self.vy[i]);
+        let (x, y) = self.screen_to_grid(position);
+        let i = self.size.index(x, y);
+        Vec2::new(self.vx[i], self.vy[i])
     }
 
+    /// The cells of the rendered grid, sampled from the nearest cells of
+    /// this one.
     pub fn get_cells(&self) -> [Vec4; NUM_CELLS] {
         let mut cells = [Vec4::ZERO; NUM_CELLS];
-        for i in 0..NUM_CELLS {
-            cells[i] = Vec4::new(self.vx[i], self.vy[i], self.density[i], 0.0);
+        for (c, cell) in cells.iter_mut().enumerate() {
+            let (x, y) = (c as u32 % GRID_X, c as u32 / GRID_X);
+            let i = self.size.index(x * self.size.x / GRID_X, y * self.size.y / GRID_Y);
+            *cell = Vec4::new(self.vx[i], self.vy[i], self.density[i], 0.0);
         }
         cells
     }
-}
 
-fn screen_to_grid(position: Vec2) -> (u32, u32) {
-    let i = ((position.x + WIDTH / 2.0) / (WIDTH as f32) * (GRID_X as f32)) as u32;
-    let j = ((position.y + HEIGHT / 2.0) / (HEIGHT as f32) * (GRID_Y as f32)) as u32;
-    return (i, j);
+    pub fn grid_to_screen(&self, i: u32, j: u32) -> Vec2 {
+        Vec2::new(
+            (i as f32 + 0.5) / self.size.x as f32 * WIDTH - WIDTH / 2.0,
+            (j as f32 + 0.5) / self.size.y as f32 * HEIGHT - HEIGHT / 2.0,
+        )
+    }
+
+    fn screen_to_grid(&self, position: Vec2) -> (u32, u32) {
+        let i = ((position.x + WIDTH / 2.0) / WIDTH * (self.size.x as f32)) as u32;
+        let j = ((position.y + HEIGHT / 2.0) / HEIGHT * (self.size.y as f32)) as u32;
+        (i, j)
+    }
 }
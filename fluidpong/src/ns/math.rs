@@ -2,6 +2,33 @@ use crate::ns::fluid::Fluid;
 
 use super::{GRID_X, GRID_Y};
 
+/// The size of a grid in cells, including the ring of boundary cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GridSize {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl GridSize {
+    /// The size the game plays at, which matches the rendered grid.
+    pub const DEFAULT: GridSize = GridSize { x: GRID_X, y: GRID_Y };
+
+    pub fn new(x: u32, y: u32) -> Self {
+        assert!(x >= 3 && y >= 3, "a {x}x{y} grid has no interior");
+        GridSize { x, y }
+    }
+
+    pub fn cells(self) -> usize {
+        (self.x * self.y) as usize
+    }
+
+    pub fn index(self, x: u32, y: u32) -> usize {
+        let x = constrain(x, 0, self.x - 1);
+        let y = constrain(y, 0, self.y - 1);
+        (x + y * self.x) as usize
+    }
+}
+
 fn constrain<T: PartialOrd>(val: T, min: T, max: T) -> T {
     if val < min {
         min
@@ -12,119 +39,182 @@ fn constrain<T: PartialOrd>(val: T, min: T, max: T) -> T {
     }
 }
 
-pub fn index(x: u32, y: u32) -> usize {
-    let x = constrain(x, 0, GRID_X - 1);
-    let y = constrain(y, 0, GRID_Y - 1);
-    (x + y * GRID_X) as usize
-}
-
-fn set_bnd(b: u32, x: &mut Vec<f32>) {
-    for i in 1..(GRID_X - 1) {
+/// Sets the cells outside the fluid from the fluid next to them: the ring
+/// of walls around the grid, and the `solid` cells inside obstacles. Scalars
+/// (`b == 0`) are copied so that nothing flows into a wall, and velocities
+/// (`b == 1` for x, `b == 2` for y) mirrored so that the fluid neither
+/// crosses an obstacle nor slips along it.
+fn set_bnd(n: GridSize, b: u32, x: &mut [f32], solid: &[bool]) {
+    let index = |i, j| n.index(i, j);
+    for i in 1..(n.x - 1) {
         if b == 2 {
             x[index(i, 0)] = -x[index(i, 1)];
-            x[index(i, GRID_Y - 1)] = -x[index(i, GRID_Y - 2)];
+            x[index(i, n.y - 1)] = -x[index(i, n.y - 2)];
         } else {
             x[index(i, 0)] = x[index(i, 1)];
-            x[index(i, GRID_Y - 1)] = x[index(i, GRID_Y - 2)];
+            x[index(i, n.y - 1)] = x[index(i, n.y - 2)];
         }
     }
 
-    for j in 1..(GRID_Y - 1) {
+    for j in 1..(n.y - 1) {
         if b == 1 {
             x[index(0, j)] = -x[index(1, j)];
-            x[index(GRID_X - 1, j)] = -x[index(GRID_X - 2, j)];
+            x[index(n.x - 1, j)] = -x[index(n.x - 2, j)];
         } else {
             x[index(0, j)] = x[index(1, j)];
-            x[index(GRID_X - 1, j)] = x[index(GRID_X - 2, j)];
+            x[index(n.x - 1, j)] = x[index(n.x - 2, j)];
         }
     }
 
     x[index(0, 0)] = 0.5 * (x[index(1, 0)] + x[index(0, 1)]);
-    x[index(0, GRID_Y - 1)] = 0.5 * (x[index(1, GRID_Y - 1)] + x[index(0, GRID_Y - 2)]);
-    x[index(GRID_X - 1, 0)] = 0.5 * (x[index(GRID_X - 2, 0)] + x[index(GRID_X - 1, 1)]);
-    x[index(GRID_X - 1, GRID_Y - 1)] = 0.5 * (x[index(GRID_X - 2, GRID_Y - 1)] + x[index(GRID_X - 1, GRID_Y - 2)]);
+    x[index(0, n.y - 1)] = 0.5 * (x[index(1, n.y - 1)] + x[index(0, n.y - 2)]);
+    x[index(n.x - 1, 0)] = 0.5 * (x[index(n.x - 2, 0)] + x[index(n.x - 1, 1)]);
+    x[index(n.x - 1, n.y - 1)] =
+        0.5 * (x[index(n.x - 2, n.y - 1)] + x[index(n.x - 1, n.y - 2)]);
+
+    for j in 1..(n.y - 1) {
+        for i in 1..(n.x - 1) {
+            if !solid[index(i, j)] {
+                continue;
+            }
+            let (mut sum, mut count) = (0.0, 0);
+            for k in [index(i + 1, j), index(i - 1, j), index(i, j + 1), index(i, j - 1)] {
+                if !solid[k] {
+                    sum += x[k];
+                    count += 1;
+                }
+            }
+            x[index(i, j)] = match (count, b) {
+                (0, _) => 0.0,
+                (_, 0) => sum / count as f32,
+                _ => -sum / count as f32,
+            };
+        }
+    }
 }
 
-fn lin_solve(b: u32, x: &mut Vec<f32>, x0: &Vec<f32>, a: f32, c: f32, iter: u32) {
+#[allow(clippy::too_many_arguments)]
+fn lin_solve(
+    n: GridSize,
+    b: u32,
+    x: &mut [f32],
+    x0: &[f32],
+    solid: &[bool],
+    a: f32,
+    c: f32,
+    iter: u32,
+) {
+    let index = |i, j| n.index(i, j);
     let c_recip = 1.0 / c;
     for _k in 0..iter {
-        for j in 1..(GRID_Y - 1) {
-            for i in 1..(GRID_X - 1) {
+        for j in 1..(n.y - 1) {
+            for i in 1..(n.x - 1) {
+                if solid[index(i, j)] {
+                    continue;
+                }
                 x[index(i, j)] = (x0[index(i, j)]
                     + a * (x[index(i + 1, j)]
                         + x[index(i - 1, j)]
                         + x[index(i, j + 1)]
-                        + x[index(i, j - 1)]
-                        + x[index(i, j + 1)]
                         + x[index(i, j - 1)]))
                     * c_recip;
             }
         }
-        set_bnd(b, x);
+        set_bnd(n, b, x, solid);
     }
 }
 
-fn diffuse(b: u32, x: &mut Vec<f32>, x0: &Vec<f32>, diff: f32, dt: f32, iter: u32) {
-    let a = dt * diff * ((GRID_X as f32) - 2.) * ((GRID_Y as f32) - 2.);
-    lin_solve(b, x, x0, a, 1. + 6. * a, iter);
+#[allow(clippy::too_many_arguments)]
+fn diffuse(
+    n: GridSize,
+    b: u32,
+    x: &mut [f32],
+    x0: &[f32],
+    solid: &[bool],
+    diff: f32,
+    dt: f32,
+    iter: u32,
+) {
+    let a = dt * diff * ((n.x as f32) - 2.) * ((n.y as f32) - 2.);
+    lin_solve(n, b, x, x0, solid, a, 1. + 4. * a, iter);
 }
 
 fn project(
-    veloc_x: &mut Vec<f32>,
-    veloc_y: &mut Vec<f32>,
-    p: &mut Vec<f32>,
-    div: &mut Vec<f32>,
+    n: GridSize,
+    veloc_x: &mut [f32],
+    veloc_y: &mut [f32],
+    p: &mut [f32],
+    div: &mut [f32],
+    solid: &[bool],
     iter: u32,
 ) {
-    for j in 1..(GRID_Y - 1) {
-        for i in 1..(GRID_X - 1) {
-            div[index(i, j)] = -0.5
-                * (veloc_x[index(i + 1, j)] - veloc_x[index(i - 1, j)]
-                    + veloc_y[index(i, j + 1)]
-                    - veloc_y[index(i, j - 1)])
-                / (GRID_X as f32);
+    let index = |i, j| n.index(i, j);
+    // Obstacles don't move, and have the pressure of the fluid against them.
+    let velocity = |v: &[f32], k: usize| if solid[k] { 0.0 } else { v[k] };
+    for j in 1..(n.y - 1) {
+        for i in 1..(n.x - 1) {
+            div[index(i, j)] = if solid[index(i, j)] {
+                0.
+            } else {
+                -0.5 * (velocity(veloc_x, index(i + 1, j)) - velocity(veloc_x, index(i - 1, j))
+                    + velocity(veloc_y, index(i, j + 1))
+                    - velocity(veloc_y, index(i, j - 1)))
+                    / (n.x as f32)
+            };
             p[index(i, j)] = 0.;
         }
     }
-    set_bnd(0, div);
-    set_bnd(0, p);
-    lin_solve(0, p, div, 1., 6., iter);
+    set_bnd(n, 0, div, solid);
+    set_bnd(n, 0, p, solid);
+    lin_solve(n, 0, p, div, solid, 1., 4., iter);
 
-    for j in 1..(GRID_Y - 1) {
-        for i in 1..(GRID_X - 1) {
-            veloc_x[index(i, j)] -=
-                0.5 * (p[index(i + 1, j)] - p[index(i - 1, j)]) * (GRID_X as f32);
-            veloc_y[index(i, j)] -=
-                0.5 * (p[index(i, j + 1)] - p[index(i, j - 1)]) * (GRID_Y as f32);
+    for j in 1..(n.y - 1) {
+        for i in 1..(n.x - 1) {
+            let centre = index(i, j);
+            if solid[centre] {
+                continue;
+            }
+            let pressure = |k: usize| if solid[k] { p[centre] } else { p[k] };
+            veloc_x[centre] -=
+                0.5 * (pressure(index(i + 1, j)) - pressure(index(i - 1, j))) * (n.x as f32);
+            veloc_y[centre] -=
+                0.5 * (pressure(index(i, j + 1)) - pressure(index(i, j - 1))) * (n.x as f32);
         }
     }
-    set_bnd(1, veloc_x);
-    set_bnd(2, veloc_y);
+    set_bnd(n, 1, veloc_x, solid);
+    set_bnd(n, 2, veloc_y, solid);
 }
 
+#[allow(clippy::too_many_arguments)]
 fn advect(
+    n: GridSize,
     b: u32,
-    d: &mut Vec<f32>,
-    d0: &Vec<f32>,
-    veloc_x: &Vec<f32>,
-    veloc_y: &Vec<f32>,
+    d: &mut [f32],
+    d0: &[f32],
+    veloc_x: &[f32],
+    veloc_y: &[f32],
+    solid: &[bool],
     dt: f32,
 ) {
+    let index = |i, j| n.index(i, j);
     let (mut i0, mut i1, mut j0, mut j1);
 
-    let dtx = dt * (GRID_X - 2) as f32;
-    let dty = dt * (GRID_Y - 2) as f32;
+    let dtx = dt * (n.x - 2) as f32;
+    let dty = dt * (n.y - 2) as f32;
 
     let (mut s0, mut s1);
     let (mut t0, mut t1);
     let (mut tmp1, mut tmp2);
     let (mut x, mut y);
 
-    let grid_x_float = GRID_X as f32;
-    let grid_y_float = GRID_Y as f32;
+    let grid_x_float = n.x as f32;
+    let grid_y_float = n.y as f32;
 
-    for j in 1..(GRID_Y - 1) {
-        for i in 1..(GRID_X - 1) {
+    for j in 1..(n.y - 1) {
+        for i in 1..(n.x - 1) {
+            if solid[index(i, j)] {
+                continue;
+            }
             tmp1 = dtx * veloc_x[index(i, j)];
             tmp2 = dty * veloc_y[index(i, j)];
             x = (i as f32) - tmp1;
@@ -157,37 +247,272 @@ fn advect(
             let j0i = j0 as u32;
             let j1i = j1 as u32;
 
-            d[index(i, j)] = s0 * (t0 * d0[index(i0i, j0i)] + t1 * d0[index(i0i, j1i)])
-                + s1 * (t0 * d0[index(i1i, j0i)] + t1 * d0[index(i1i, j1i)]);
+            let corners = [
+                (index(i0i, j0i), s0 * t0),
+                (index(i0i, j1i), s0 * t1),
+                (index(i1i, j0i), s1 * t0),
+                (index(i1i, j1i), s1 * t1),
+            ];
+            d[index(i, j)] = if corners.iter().all(|(k, _)| !solid[*k]) {
+                s0 * (t0 * d0[index(i0i, j0i)] + t1 * d0[index(i0i, j1i)])
+                    + s1 * (t0 * d0[index(i1i, j0i)] + t1 * d0[index(i1i, j1i)])
+            } else {
+                // Only the fluid is carried, not what's inside an obstacle.
+                let fluid = corners.iter().filter(|(k, _)| !solid[*k]);
+                let weight: f32 = fluid.clone().map(|(_, w)| w).sum();
+                if weight > 0.0 {
+                    fluid.map(|(k, w)| w * d0[*k]).sum::<f32>() / weight
+                } else {
+                    d0[index(i, j)]
+                }
+            };
         }
     }
-    set_bnd(b, d);
+    set_bnd(n, b, d, solid);
 }
 
 pub fn fluid_step(fluid: &mut Fluid, visc: f32, diff: f32, dt: f32, iter: u32) {
+    let n = fluid.size;
 
-    diffuse(1, &mut fluid.vx0, &fluid.vx, visc, dt, iter);
-    diffuse(2, &mut fluid.vy0, &fluid.vy, visc, dt, iter);
+    let solid = &fluid.solid;
+
+    diffuse(n, 1, &mut fluid.vx0, &fluid.vx, solid, visc, dt, iter);
+    diffuse(n, 2, &mut fluid.vy0, &fluid.vy, solid, visc, dt, iter);
 
     project(
+        n,
         &mut fluid.vx0,
         &mut fluid.vy0,
         &mut fluid.vx,
         &mut fluid.vy,
+        solid,
         iter,
     );
 
-    advect(1, &mut fluid.vx, &fluid.vx0, &fluid.vx0, &fluid.vy0, dt);
-    advect(2, &mut fluid.vy, &fluid.vy0, &fluid.vx0, &fluid.vy0, dt);
+    advect(n, 1, &mut fluid.vx, &fluid.vx0, &fluid.vx0, &fluid.vy0, solid, dt);
+    advect(n, 2, &mut fluid.vy, &fluid.vy0, &fluid.vx0, &fluid.vy0, solid, dt);
 
     project(
+        n,
         &mut fluid.vx,
         &mut fluid.vy,
         &mut fluid.vx0,
         &mut fluid.vy0,
+        solid,
         iter,
     );
 
-    diffuse(0, &mut fluid.s, &fluid.density, diff, dt, iter);
-    advect(0, &mut fluid.density, &fluid.s, &fluid.vx, &fluid.vy, dt);
+    diffuse(n, 0, &mut fluid.s, &fluid.density, solid, diff, dt, iter);
+    advect(n, 0, &mut fluid.density, &fluid.s, &fluid.vx, &fluid.vy, solid, dt);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::Vec2;
+
+    use super::*;
+    use crate::backend::FluidBackend;
+    use crate::ns::fluid::NUM_CELLS;
+    use crate::pong::pongfluid::PongFluid;
+    use crate::simui::FluidSimVars;
+
+    const SIZE: GridSize = GridSize::DEFAULT;
+
+    /// Steps the solver with the backend's default simulation variables.
+    fn step(fluid: &mut Fluid) {
+        let simvars = FluidSimVars::new(
+            Fluid::simvars().into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+        );
+        fluid_step(
+            fluid,
+            simvars.get("viscosity"),
+            simvars.get("diffusion"),
+            simvars.get("dt"),
+            simvars.get("iter") as u32,
+        );
+    }
+
+    /// Fills the interior of a grid with `f` of the cell coordinates.
+    fn interior(size: GridSize, f: impl Fn(f32, f32) -> f32) -> Vec<f32> {
+        let mut x = vec![0.0; size.cells()];
+        for j in 1..(size.y - 1) {
+            for i in 1..(size.x - 1) {
+                x[size.index(i, j)] = f(i as f32, j as f32);
+            }
+        }
+        x
+    }
+
+    fn total(size: GridSize, x: &[f32]) -> f32 {
+        let mut total = 0.0;
+        for j in 1..(size.y - 1) {
+            for i in 1..(size.x - 1) {
+                total += x[size.index(i, j)];
+            }
+        }
+        total
+    }
+
+    /// The RMS of the central-difference divergence that `project` removes,
+    /// away from the walls.
+    fn divergence(veloc_x: &[f32], veloc_y: &[f32]) -> f32 {
+        let mut sum = 0.0;
+        let mut n = 0;
+        for j in 2..(GRID_Y - 2) {
+            for i in 2..(GRID_X - 2) {
+                let div = 0.5
+                    * (veloc_x[SIZE.index(i + 1, j)] - veloc_x[SIZE.index(i - 1, j)]
+                        + veloc_y[SIZE.index(i, j + 1)]
+                        - veloc_y[SIZE.index(i, j - 1)]);
+                sum += div * div;
+                n += 1;
+            }
+        }
+        (sum / n as f32).sqrt()
+    }
+
+    fn is_finite(fluid: &Fluid) -> bool {
+        [&fluid.density, &fluid.vx, &fluid.vy, &fluid.s, &fluid.vx0, &fluid.vy0]
+            .iter()
+            .all(|x| x.iter().all(|v| v.is_finite()))
+    }
+
+    #[test]
+    fn density_is_conserved_without_dissipation() {
+        // Dissipation is applied by the backend after `fluid_step`, and the
+        // walls are closed, so only the solver can gain or lose density.
+        for size in [SIZE, GridSize::new(48, 36), GridSize::new(192, 144)] {
+            let (w, h) = (size.x as f32, size.y as f32);
+            let mut fluid = Fluid::with_size(size);
+            // A block over the middle sixth of the arena each way.
+            let middle = |i: f32, w: f32| (5.0 * w..7.0 * w).contains(&(12.0 * i));
+            fluid.density =
+                interior(size, |i, j| if middle(i, w) && middle(j, h) { 10.0 } else { 0.0 });
+            fluid.vx = interior(size, |i, j| (j / h * 6.0).sin() + i / w);
+            fluid.vy = interior(size, |i, _| (i / w * 6.0).cos());
+            // `s` is the previous density, which the diffusion solve starts from.
+            fluid.s = fluid.density.clone();
+            let before = total(size, &fluid.density);
+            for _ in 0..100 {
+                step(&mut fluid);
+            }
+            let after = total(size, &fluid.density);
+            assert!(
+                (after / before - 1.0).abs() < 0.01,
+                "density on {size:?} went from {before} to {after}"
+            );
+        }
+    }
+
+    #[test]
+    fn momentum_is_conserved_away_from_walls() {
+        // The solver has no periodic boundaries, so a blob in the middle of
+        // the arena, which never reaches the walls, stands in for a periodic
+        // box. In a closed box the pressure solve turns any net momentum into
+        // a backflow off the walls, so only viscosity and advection by a
+        // divergence-free flow, here a uniform drift, are checked.
+        let simvars = FluidSimVars::new(
+            Fluid::simvars().into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+        );
+        let (visc, dt, iter) = (simvars.get("viscosity"), simvars.get("dt"), simvars.get("iter"));
+        let blob = |i: f32, j: f32| {
+            let r = Vec2::new(i - GRID_X as f32 / 2.0, j - GRID_Y as f32 / 2.0);
+            (-r.length_squared() / 40.0).exp() * (Vec2::new(300.0, -100.0) + r.perp() * 40.0)
+        };
+        let mut fluid = Fluid::new();
+        fluid.vx = interior(SIZE, |i, j| blob(i, j).x);
+        fluid.vy = interior(SIZE, |i, j| blob(i, j).y);
+        let (drift_x, drift_y) = (interior(SIZE, |_, _| 100.0), interior(SIZE, |_, _| 50.0));
+        let momentum = |fluid: &Fluid| Vec2::new(total(SIZE, &fluid.vx), total(SIZE, &fluid.vy));
+        let before = momentum(&fluid);
+        let solid = vec![false; NUM_CELLS];
+        for _ in 0..100 {
+            diffuse(SIZE, 1, &mut fluid.vx0, &fluid.vx, &solid, visc, dt, iter as u32);
+            diffuse(SIZE, 2, &mut fluid.vy0, &fluid.vy, &solid, visc, dt, iter as u32);
+            advect(SIZE, 1, &mut fluid.vx, &fluid.vx0, &drift_x, &drift_y, &solid, dt);
+            advect(SIZE, 2, &mut fluid.vy, &fluid.vy0, &drift_x, &drift_y, &solid, dt);
+        }
+        let after = momentum(&fluid);
+        let by_walls = |i: u32, j: u32| i.min(j) < 2 || i > GRID_X - 3 || j > GRID_Y - 3;
+        assert!((0..NUM_CELLS as u32).all(|c| {
+            let (i, j) = (c % GRID_X, c / GRID_X);
+            !by_walls(i, j) || fluid.vx[c as usize].abs() + fluid.vy[c as usize].abs() < 1e-3
+        }));
+        assert!(
+            after.distance(before) < 1e-3 * before.length(),
+            "momentum went from {before} to {after}"
+        );
+    }
+
+    #[test]
+    fn project_removes_divergence() {
+        // A source in the middle of the arena, which is all divergence.
+        let source = |i: f32, j: f32| {
+            let r = Vec2::new(i - GRID_X as f32 / 2.0, j - GRID_Y as f32 / 2.0);
+            (-r.length_squared() / 50.0).exp() * r * 0.1
+        };
+        let mut veloc_x = interior(SIZE, |i, j| source(i, j).x);
+        let mut veloc_y = interior(SIZE, |i, j| source(i, j).y);
+        let (mut p, mut div) = (vec![0.0; NUM_CELLS], vec![0.0; NUM_CELLS]);
+        let before = divergence(&veloc_x, &veloc_y);
+        let solid = vec![false; NUM_CELLS];
+        project(SIZE, &mut veloc_x, &mut veloc_y, &mut p, &mut div, &solid, 500);
+        let after = divergence(&veloc_x, &veloc_y);
+        assert!(after < 0.05 * before, "divergence went from {before} to {after}");
+    }
+
+    #[test]
+    fn fluid_at_rest_stays_at_rest() {
+        let mut fluid = Fluid::new();
+        fluid.density = vec![1.0; NUM_CELLS];
+        fluid.s = fluid.density.clone();
+        for _ in 0..100 {
+            step(&mut fluid);
+        }
+        assert!(fluid.vx.iter().chain(&fluid.vy).all(|&v| v == 0.0));
+        let max_change = fluid.density.iter().map(|d| (d - 1.0).abs()).fold(0.0, f32::max);
+        assert!(max_change < 1e-6, "density changed by {max_change}");
+    }
+
+    #[test]
+    fn extreme_emit_force_gives_no_nans() {
+        let mut fluid = Fluid::new();
+        for frame in 0..100 {
+            let paddle = Vec2::new(-270.0 + frame as f32, 0.0);
+            fluid.apply_emit_force(paddle, Vec2::new(1e6, -1e6));
+            fluid.apply_emit_force(Vec2::splat(1e9), Vec2::splat(1e6));
+            step(&mut fluid);
+            assert!(is_finite(&fluid), "not finite after frame {frame}");
+        }
+    }
+
+    #[test]
+    fn obstacles_are_walls_not_sinks() {
+        let mut fluid = Fluid::new();
+        // A pillar in the middle of the arena, in the way of a flow to the right.
+        let pillar = |i: f32, j: f32| (44.0..52.0).contains(&i) && (20.0..52.0).contains(&j);
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                fluid.solid[SIZE.index(i, j)] = pillar(i as f32, j as f32);
+            }
+        }
+        let blob = |i: f32, j: f32| (20.0..40.0).contains(&i) && (28.0..44.0).contains(&j);
+        fluid.density = interior(SIZE, |i, j| if blob(i, j) { 10.0 } else { 0.0 });
+        fluid.vx = interior(SIZE, |i, j| if pillar(i, j) { 0.0 } else { 0.5 });
+        fluid.s = fluid.density.clone();
+        let before = total(SIZE, &fluid.density);
+        for frame in 0..100 {
+            step(&mut fluid);
+            // The flow through the face of the pillar between its corners,
+            // halfway between the last cell of fluid and the first solid one.
+            for j in 21..51 {
+                let through = fluid.vx[SIZE.index(43, j)] + fluid.vx[SIZE.index(44, j)];
+                assert!(through.abs() < 1e-6, "{through} went through row {j} on frame {frame}");
+            }
+            // As the backend does.
+            fluid.clear_solids();
+        }
+        let after = total(SIZE, &fluid.density);
+        assert!((after / before - 1.0).abs() < 0.02, "density went from {before} to {after}");
+    }
 }
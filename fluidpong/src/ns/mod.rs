@@ -3,135 +3,162 @@ pub mod math;
 mod pongfluid;
 
 use crate::{
+    backend::{field::VelocityField, FluidBackend, FIXED_TIMESTEP},
+    export::FluidExport,
     ns::fluid::*,
-    ns::math::{fluid_step, index},
+    ns::math::fluid_step,
     simui::FluidSimVars,
+    snapshot::{FluidState, SnapshotError},
 };
 use bevy::{
-    input::mouse::MouseMotion,
     prelude::*,
     render::render_resource::{AsBindGroup, ShaderRef},
-    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle},
-    utils::HashMap,
-    window::PrimaryWindow,
+    sprite::Material2d,
 };
 
-pub struct FluidPlugin {
-    pub debug: bool,
-}
 
-impl Plugin for FluidPlugin {
-    fn build(&self, app: &mut App) {
-        app.add_plugins(Material2dPlugin::<FluidGridMaterial>::default())
-            .add_systems(PostStartup, init_fluid)
-            .add_systems(Update, update_fluid);
-        if self.debug {
-            app.add_systems(Update, update_interactive);
-        }
+impl FluidBackend for Fluid {
+    const NAME: &'static str = "ns";
+    type Material = FluidGridMaterial;
+
+    fn simvars() -> Vec<(&'static str, f32)> {
+        vec![
+            ("dt", DT),
+            ("iter", 4.0),
+            ("viscosity", 0.2),
+            ("diffusion", 10.0),
+            ("interact_force", 1000.0),
+            ("interact_velocity", 0.0),
+            ("dissipation", 0.001),
+        ]
     }
-}
 
-#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
-pub struct FluidGridMaterial {
-    #[uniform(0)]
-    screen_size: Vec2,
-    #[uniform(1)]
-    grid_size: Vec2,
-    #[uniform(2)]
-    cells: [Vec4; NUM_CELLS],
-}
+    fn debug_simvars() -> Vec<(&'static str, f32)> {
+        vec![
+            ("dt", DT),
+            ("iter", 4.),
+            ("viscosity", 0.0),
+            ("diffusion", 0.2),
+            ("interact_force", 10.0),
+            ("interact_velocity", 1000.0),
+            ("dissipation", 0.1),
+        ]
+    }
 
-impl Material2d for FluidGridMaterial {
-    fn fragment_shader() -> ShaderRef {
-        "shaders/fluidgrid.wgsl".into()
+    fn new(simvars: &FluidSimVars) -> Self {
+        let mut fluid = Self::new();
+        fluid.dt = simvars.get("dt");
+        fluid
     }
-}
 
-fn init_fluid(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<FluidGridMaterial>>,
-) {
-    let fluid: Fluid = Fluid::new();
-    let simvars = FluidSimVars::new(HashMap::from([
-        ("dt".to_string(), 0.00001),
-        ("iter".to_string(), 4.0),
-        ("viscosity".to_string(), 0.2),
-        ("diffusion".to_string(), 10.0),
-        ("interact_force".to_string(), 1000.0),
-        ("interact_velocity".to_string(), 0.0),
-        ("dissipation".to_string(), 0.001),
-    ]));
-    let cells = fluid.get_cells();
-
-    commands.spawn((
-        fluid,
-        simvars,
-        MaterialMesh2dBundle {
-            mesh: Mesh2dHandle(meshes.add(Rectangle::new(WIDTH, HEIGHT))),
-            material: materials.add(FluidGridMaterial {
-                screen_size: Vec2::new(WIDTH as f32, HEIGHT as f32),
-                grid_size: Vec2::new(GRID_X as f32, GRID_Y as f32),
-                cells: cells,
-            }),
-            transform: Transform::from_translation(Vec3::ZERO),
-            ..default()
-        },
-    ));
-}
+    fn reset(&mut self, _simvars: &FluidSimVars) {
+        self.reset();
+    }
 
-fn update_interactive(
-    camera_query: Query<(&Camera, &GlobalTransform)>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    mb: Res<ButtonInput<MouseButton>>,
-    mut motion_er: EventReader<MouseMotion>,
-    mut query: Query<(&mut Fluid, &FluidSimVars)>,
-    mut gizmos: Gizmos,
-) {
-    let (camera, camera_transform) = camera_query.single();
-    let (mut fluid, simvars) = query.single_mut();
-
-    let window: &Window = window_query.single();
-    if let Some(cursor_position) = window.cursor_position() {
-        if let Some(world_position) = camera.viewport_to_world_2d(camera_transform, cursor_position)
-        {
-            let point = Vec2::new(world_position.x, world_position.y);
-
-            if mb.pressed(MouseButton::Left) {
-                gizmos.circle_2d(world_position, 10., Color::WHITE);
-                let strength = simvars.get("interact_force");
-                fluid.add_density(point, strength);
-                for motion in motion_er.read() {
-                    fluid
-                        .add_velocity(point, Vec2::new(1., -1.) * motion.delta * INTERACT_VELOCITY);
+    fn step(&mut self, dt: f32, simvars: &FluidSimVars) {
+        let dissipation = simvars.get("dissipation");
+        let viscosity = simvars.get("viscosity");
+        let diffusion = simvars.get("diffusion");
+        // The `dt` simulation variable is the solver time for one fixed time
+        // step, so other steps scale it.
+        self.dt = simvars.get("dt");
+        let dt = self.dt * (dt / FIXED_TIMESTEP);
+        let iter = simvars.get("iter") as u32;
+        self.clear_solids();
+        fluid_step(self, viscosity, diffusion, dt, iter);
+        self.clear_solids();
+        for i in 0..self.size.x {
+            for j in 0..self.size.y {
+                if self.density[self.size.index(i, j)] > dissipation {
+                    self.add_density_grid(i, j, -dissipation);
                 }
             }
         }
     }
-}
 
-fn update_fluid(
-    mut query: Query<(&mut Fluid, &Handle<FluidGridMaterial>, &FluidSimVars)>,
-    mut materials: ResMut<Assets<FluidGridMaterial>>,
-) {
-    let (mut fluid, handle, simvars) = query.single_mut();
-    let dissipation = simvars.get("dissipation");
-    let viscosity = simvars.get("viscosity");
-    let diffusion = simvars.get("diffusion");
-    let dt = simvars.get("dt");
-    let iter = simvars.get("iter") as u32;
-    if !simvars.paused {
-        fluid_step(&mut fluid, viscosity, diffusion, dt, iter);
-        for i in 0..GRID_X {
-            for j in 0..GRID_Y {
-                if fluid.density[index(i, j)] > dissipation {
-                    fluid.add_density_grid(i, j, -dissipation);
-                }
+    fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        self.get_velocity_at(position) * self.velocity_to_pixels()
+    }
+
+    fn interact(&mut self, position: Vec2, drag: Vec2, pressed: bool, simvars: &FluidSimVars) {
+        if pressed {
+            self.add_density(position, simvars.get("interact_force"));
+            self.add_velocity(position, drag * INTERACT_VELOCITY);
+        }
+    }
+
+    fn seed_velocity(&mut self, field: &VelocityField) {
+        for i in 0..self.size.x {
+            for j in 0..self.size.y {
+                let velocity = field.sample(self.grid_to_screen(i, j)) / self.velocity_to_pixels();
+                self.vx[self.size.index(i, j)] = velocity.x;
+                self.vy[self.size.index(i, j)] = velocity.y;
             }
         }
+    }
 
-        if let Some(material) = materials.get_mut(&*handle) {
-            material.cells = fluid.get_cells();
+    fn save_state(&self) -> FluidState {
+        FluidState::default()
+            .with("s", self.s.iter().copied())
+            .with("density", self.density.iter().copied())
+            .with("vx", self.vx.iter().copied())
+            .with("vy", self.vy.iter().copied())
+            .with("vx0", self.vx0.iter().copied())
+            .with("vy0", self.vy0.iter().copied())
+    }
+
+    fn export(&self) -> FluidExport {
+        let velocity = self.vx.iter().zip(&self.vy).map(|(&vx, &vy)| Vec2::new(vx, vy));
+        let to_pixels = self.velocity_to_pixels();
+        FluidExport::grid(UVec2::new(self.size.x, self.size.y))
+            .with_vectors("velocity", velocity.map(|velocity| velocity * to_pixels))
+            .with_scalars("density", self.density.iter().copied())
+    }
+
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError> {
+        let cells = self.size.cells();
+        self.s.copy_from_slice(state.get_len("s", cells)?);
+        self.density.copy_from_slice(state.get_len("density", cells)?);
+        self.vx.copy_from_slice(state.get_len("vx", cells)?);
+        self.vy.copy_from_slice(state.get_len("vy", cells)?);
+        self.vx0.copy_from_slice(state.get_len("vx0", cells)?);
+        self.vy0.copy_from_slice(state.get_len("vy0", cells)?);
+        Ok(())
+    }
+
+    fn material(&self) -> FluidGridMaterial {
+        FluidGridMaterial {
+            screen_size: Vec2::new(WIDTH, HEIGHT),
+            grid_size: Vec2::new(GRID_X as f32, GRID_Y as f32),
+            cells: self.get_cells(),
         }
     }
-}
\ No newline at end of file
+
+    fn update_material(&self, material: &mut FluidGridMaterial, _simvars: &FluidSimVars) {
+        material.cells = self.get_cells();
+    }
+}
+
+impl Fluid {
+    /// Converts grid velocities to pixels per second, with `dt` of solver time
+    /// passing in every fixed time step.
+    fn velocity_to_pixels(&self) -> f32 {
+        self.dt * (self.size.x - 2) as f32 * WIDTH / self.size.x as f32 / FIXED_TIMESTEP
+    }
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct FluidGridMaterial {
+    #[uniform(0)]
+    pub(crate) screen_size: Vec2,
+    #[uniform(1)]
+    pub(crate) grid_size: Vec2,
+    #[uniform(2)]
+    pub(crate) cells: [Vec4; NUM_CELLS],
+}
+
+impl Material2d for FluidGridMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/fluidgrid.wgsl".into()
+    }
+}
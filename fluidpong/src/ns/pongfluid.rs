@@ -1,7 +1,8 @@
 use bevy::prelude::*;
 
 use crate::ns;
-use crate::pong::pongfluid::PongFluid;
+use crate::pong::level::Obstacle;
+use crate::pong::pongfluid::{is_in_wall, PongFluid};
 
 const EMIT_DENSITY: f32 = 10.0;
 const EMIT_VELOCITY: f32 = 10000.0;
@@ -9,6 +10,7 @@ const PADDLE_VELOCITY: f32 = 10.0;
 const BALL_VELOCITY: f32 = 5.0;
 const FLUID_ON_BALL_VELOCITY: f32 = 0.0001;
 const FLUID_ON_BALL_DENSITY: f32 = 0.000001;
+const WALL_DENSITY: f32 = 50.0;
 
 impl PongFluid for ns::fluid::Fluid {
      fn apply_emit_force(&mut self, position: Vec2, velocity: Vec2) {
@@ -16,12 +18,29 @@ impl PongFluid for ns::fluid::Fluid {
         self.add_velocity(position, velocity * EMIT_VELOCITY);
      }
     fn apply_paddle_force(&mut self, position: Vec2, velocity: Vec2) {
+        if velocity == Vec2::ZERO {
+            return;
+        }
         self.add_velocity(position, velocity * PADDLE_VELOCITY);
     }
     fn apply_ball_force(&mut self, position: Vec2, velocity: Vec2) {
         self.add_velocity(position, velocity * BALL_VELOCITY);
     }
+    fn apply_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.set_obstacles(obstacles);
+    }
+    // The fluid can't be packed, so the wall is a burst of new dense fluid for
+    // the ball to push through.
+    fn apply_wall(&mut self, position: Vec2, direction: Vec2) {
+        for i in 0..self.size.x {
+            for j in 0..self.size.y {
+                if is_in_wall(self.grid_to_screen(i, j), position, direction) {
+                    self.add_density_grid(i, j, WALL_DENSITY * direction.length());
+                }
+            }
+        }
+    }
     fn get_fluid_force_at(&self, position: Vec2, velocity: Vec2) -> Vec2 {
-        return self.get_velocity_at(position) * FLUID_ON_BALL_VELOCITY - self.get_density_at(position) * velocity * FLUID_ON_BALL_DENSITY;
+        self.get_velocity_at(position) * FLUID_ON_BALL_VELOCITY - self.get_density_at(position) * velocity * FLUID_ON_BALL_DENSITY
     }
 }
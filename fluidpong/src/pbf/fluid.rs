@@ -0,0 +1,300 @@
+use std::ptr;
+
+use bevy::ecs::component::Component;
+use bevy::math::{Vec2, Vec4};
+use rayon::prelude::*;
+
+use crate::pbf::particle::Particle;
+use crate::pong::level::{self, Obstacle};
+use crate::sph::kernel::{Kernel, KernelFunction, Poly6Kernel, SpikyKernel};
+use crate::sph::spatial_grid::SpatialGrid2D;
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+const NUM_PARTICLES_X: u32 = 36;
+const NUM_PARTICLES_Y: u32 = 48;
+
+pub const WALL_X: f32 = GAME_WIDTH / 2.0;
+pub const WALL_Y: f32 = GAME_HEIGHT / 2.0;
+
+const EPS: f32 = 1.0;
+pub const NUM_PARTICLES: usize = (NUM_PARTICLES_X * NUM_PARTICLES_Y) as usize;
+
+/// A Position Based Fluid (Macklin and Müller, 2013).
+///
+/// Instead of integrating pressure forces, each step predicts new particle
+/// positions and then iteratively projects them onto a per-particle density
+/// constraint. Velocities are recovered from the corrected positions, so the
+/// simulation stays stable even at the large, uneven time steps of a game loop.
+#[derive(Component)]
+pub struct Fluid {
+    particles: SpatialGrid2D<Particle>,
+    density_kernel: Kernel,
+    gradient_kernel: Kernel,
+    rest_density: f32,
+    obstacles: Vec<Obstacle>,
+}
+
+impl Fluid {
+    /// Creates a new fluid simulation with a grid of particles.
+    pub fn new(kernel_radius: f32, particle_mass: f32) -> Self {
+        Self {
+            particles: Self::spawn_particles(kernel_radius, particle_mass),
+            density_kernel: Poly6Kernel::new(kernel_radius).into(),
+            gradient_kernel: SpikyKernel::new(kernel_radius).into(),
+            rest_density: 1.0,
+            obstacles: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self, kernel_radius: f32, particle_mass: f32) {
+        self.particles = Self::spawn_particles(kernel_radius, particle_mass);
+        self.density_kernel = Poly6Kernel::new(kernel_radius).into();
+        self.gradient_kernel = SpikyKernel::new(kernel_radius).into();
+    }
+
+    /// Sets the obstacles the particles are kept out of, like the walls.
+    pub fn set_obstacles(&mut self, obstacles: &[Obstacle]) {
+        if self.obstacles != obstacles {
+            self.obstacles = obstacles.to_vec();
+        }
+    }
+
+    fn spawn_particles(kernel_radius: f32, particle_mass: f32) -> SpatialGrid2D<Particle> {
+        let mut particles = SpatialGrid2D::new(kernel_radius);
+        let dx = WALL_X * 2.0 / NUM_PARTICLES_X as f32;
+        let dy = WALL_Y * 2.0 / NUM_PARTICLES_Y as f32;
+        for i in 0..NUM_PARTICLES_X {
+            for j in 0..NUM_PARTICLES_Y {
+                let x = -WALL_X * 0.9 + i as f32 * dx * 0.9;
+                let y = -WALL_Y * 0.9 + j as f32 * dy * 0.9;
+                particles.insert(Particle::new(Vec2::new(x, y), particle_mass));
+            }
+        }
+        particles
+    }
+
+    /// Applies external forces and gravity to the velocities, then predicts
+    /// the position of each particle at the end of the time step.
+    pub fn predict(&mut self, dt: f32, gravity: f32) {
+        let obstacles = &self.obstacles;
+        self.particles.iter_mut().par_bridge().for_each(|pi| {
+            pi.velocity += dt * (Vec2::new(0.0, -gravity) + pi.ext_force / pi.mass);
+            pi.predicted = keep_out(obstacles, pi.position + dt * pi.velocity);
+        });
+        self.particles.recompute();
+    }
+
+    /// Runs one Jacobi iteration of the density constraint solver on the
+    /// predicted positions. `relaxation` is the constraint force mixing term
+    /// that keeps the solve well-conditioned when particles have few
+    /// neighbors.
+    pub fn solve_density_constraints(&mut self, rest_dens: f32, relaxation: f32) {
+        self.rest_density = rest_dens;
+
+        // Each pass works from the particles as they are, and writes its
+        // results back once every particle has been worked out.
+        let particles: Vec<&Particle> = self.particles.iter().collect();
+        let lambdas: Vec<(f32, f32)> = (particles.par_iter())
+            .map(|pi| {
+                let neighbors = self.particles.query(pi.predicted);
+                let density: f32 = (neighbors.iter())
+                    .map(|pj| pj.mass * self.density_kernel.evaluate(pi.predicted - pj.predicted))
+                    .sum();
+
+                // Only resist compression, so that particles do not clump
+                // together at the free surface.
+                let constraint = (density / rest_dens - 1.0).max(0.0);
+
+                let mut grad_i = Vec2::ZERO;
+                let mut grad_sum = 0.0;
+                for pj in neighbors.iter().filter(|pj| !ptr::eq(**pj, *pi)) {
+                    let grad_j = pj.mass / rest_dens
+                        * self.gradient_kernel.gradient(pi.predicted - pj.predicted);
+                    grad_i += grad_j;
+                    grad_sum += grad_j.length_squared();
+                }
+                grad_sum += grad_i.length_squared();
+
+                (density, -constraint / (grad_sum + relaxation))
+            })
+            .collect();
+        for (pi, (density, lambda)) in self.particles.iter_mut().zip(lambdas) {
+            pi.density = density;
+            pi.lambda = lambda;
+        }
+
+        let particles: Vec<&Particle> = self.particles.iter().collect();
+        let deltas: Vec<Vec2> = (particles.par_iter())
+            .map(|pi| {
+                (self.particles.query(pi.predicted).into_iter())
+                    .filter(|pj| !ptr::eq(*pj, *pi))
+                    .map(|pj| {
+                        (pi.lambda + pj.lambda)
+                            * pj.mass
+                            * self.gradient_kernel.gradient(pi.predicted - pj.predicted)
+                    })
+                    .sum::<Vec2>()
+                    / rest_dens
+            })
+            .collect();
+        for (pi, delta) in self.particles.iter_mut().zip(deltas) {
+            pi.delta = delta;
+            pi.predicted = keep_out(&self.obstacles, pi.predicted + delta);
+        }
+
+        self.particles.recompute();
+    }
+
+    /// Derives velocities from the corrected positions, smooths them with XSPH
+    /// viscosity and commits the predicted positions.
+    pub fn update_velocities(&mut self, dt: f32, xsph_visc: f32) {
+        self.particles.iter_mut().par_bridge().for_each(|pi| {
+            pi.velocity = (pi.predicted - pi.position) / dt;
+        });
+
+        let particles: Vec<&Particle> = self.particles.iter().collect();
+        let dvs: Vec<Vec2> = (particles.par_iter())
+            .map(|pi| {
+                (self.particles.query(pi.predicted).into_iter())
+                    .map(|pj| {
+                        pj.mass / pj.density.max(f32::EPSILON)
+                            * (pj.velocity - pi.velocity)
+                            * self.density_kernel.evaluate(pi.predicted - pj.predicted)
+                    })
+                    .sum()
+            })
+            .collect();
+        // The grid is keyed on the predicted positions, which stay put.
+        for (pi, dv) in self.particles.iter_mut().zip(dvs) {
+            pi.velocity += xsph_visc * dv;
+            pi.position = pi.predicted;
+        }
+    }
+
+    /// Advances the simulation by one time step.
+    pub fn step(
+        &mut self,
+        dt: f32,
+        iterations: u32,
+        rest_dens: f32,
+        relaxation: f32,
+        xsph_visc: f32,
+        gravity: f32,
+    ) {
+        if dt <= 0.0 {
+            return;
+        }
+        self.predict(dt, gravity);
+        for _ in 0..iterations {
+            self.solve_density_constraints(rest_dens, relaxation);
+        }
+        self.update_velocities(dt, xsph_visc);
+    }
+
+    /// Sets the external force acting on the fluid at the given point.
+    pub fn set_external_force(&mut self, point: Vec2, force: Vec2, radius: f32) {
+        for particle in self.particles.iter_mut() {
+            let distance = (particle.position.distance(point) - radius).max(0.0);
+            let logistic_response = 1.0 / (1.0 + f32::exp(1.0 + distance));
+            particle.ext_force = force * logistic_response;
+        }
+    }
+
+    /// Add the external force acting on the fluid at the given point.
+    pub fn add_external_force(&mut self, point: Vec2, force: Vec2, radius: f32) {
+        for particle in self.particles.iter_mut() {
+            let distance = (particle.position.distance(point) - radius).max(0.0);
+            let logistic_response = 1.0 / (1.0 + f32::exp(1.0 + distance));
+            particle.ext_force += force * logistic_response;
+        }
+    }
+
+    /// Returns the velocity of the fluid relative to the given velocity,
+    /// interpolated from the particles near the given point. The result fades
+    /// to zero where there is no fluid.
+    pub fn get_force_at(&self, point: Vec2, velocity: Vec2) -> Vec2 {
+        (self.particles.query(point).into_iter())
+            .map(|pj| {
+                pj.mass / pj.density.max(f32::EPSILON)
+                    * (pj.velocity - velocity)
+                    * self.density_kernel.evaluate(point - pj.position)
+            })
+            .sum()
+    }
+
+    /// Returns the velocity of the fluid at the given point, averaged over the
+    /// particles within the kernel radius. Zero where there is no fluid.
+    pub fn get_velocity_at(&self, point: Vec2) -> Vec2 {
+        let (velocity, weight) = (self.particles.query(point).into_iter()).fold(
+            (Vec2::ZERO, 0.0),
+            |(velocity, weight), pj| {
+                let w = self.density_kernel.evaluate(point - pj.position);
+                (velocity + pj.velocity * w, weight + w)
+            },
+        );
+        if weight > 0.0 {
+            velocity / weight
+        } else {
+            Vec2::ZERO
+        }
+    }
+
+    /// Sets the velocity of every particle from a velocity field.
+    pub fn set_velocities(&mut self, velocity_at: impl Fn(Vec2) -> Vec2) {
+        for particle in self.particles.iter_mut() {
+            particle.velocity = velocity_at(particle.position);
+        }
+    }
+
+    /// Moves every particle from where it is to where `to` puts it, as if it
+    /// had started the step there.
+    pub fn move_particles(&mut self, to: impl Fn(Vec2) -> Vec2) {
+        for particle in self.particles.iter_mut() {
+            particle.position = to(particle.position);
+            particle.predicted = particle.position;
+        }
+        self.particles.recompute();
+    }
+
+    /// Replaces the particles in the fluid, keeping the kernel radius.
+    pub fn set_particles(&mut self, particles: impl IntoIterator<Item = Particle>) {
+        self.particles.clear();
+        for particle in particles {
+            self.particles.insert(particle);
+        }
+    }
+
+    /// Returns a reference to the particles in the fluid.
+    pub fn particles(&self) -> &SpatialGrid2D<Particle> {
+        &self.particles
+    }
+
+    /// Returns metaball information for the shader
+    pub fn get_balls(&self) -> [Vec4; NUM_PARTICLES] {
+        let mut balls = [Vec4::ZERO; NUM_PARTICLES];
+        for (i, particle) in self.particles.iter().enumerate() {
+            if i >= NUM_PARTICLES {
+                break;
+            }
+            balls[i] = Vec4::new(
+                particle.position.x,
+                particle.position.y,
+                particle.density / self.rest_density,
+                particle.velocity.length(),
+            );
+        }
+        balls
+    }
+}
+
+/// Moves a predicted position out of the obstacles, then inside the walls.
+fn keep_out(obstacles: &[Obstacle], position: Vec2) -> Vec2 {
+    clamp_to_walls(level::push_out_of(obstacles, position, EPS).0)
+}
+
+fn clamp_to_walls(position: Vec2) -> Vec2 {
+    Vec2::new(
+        position.x.clamp(EPS - WALL_X, WALL_X - EPS),
+        position.y.clamp(EPS - WALL_Y, WALL_Y - EPS),
+    )
+}
@@ -0,0 +1,100 @@
+pub mod fluid;
+pub mod particle;
+mod pongfluid;
+
+use bevy::prelude::*;
+
+use crate::backend::field::VelocityField;
+use crate::backend::FluidBackend;
+use crate::pbf::particle::Particle;
+use crate::simui::FluidSimVars;
+use crate::snapshot::{FluidState, SnapshotError};
+use crate::sph::MetaballMaterial;
+
+impl FluidBackend for fluid::Fluid {
+    const NAME: &'static str = "pbf";
+    type Material = MetaballMaterial;
+
+    fn simvars() -> Vec<(&'static str, f32)> {
+        vec![
+            ("kernel_radius", 16.0),
+            ("particle_mass", 1.0),
+            ("rest_dens", 0.015),
+            ("relaxation", 0.0001),
+            ("xsph_visc", 0.1),
+            ("iterations", 4.0),
+            ("gravity", 0.0),
+        ]
+    }
+
+    fn debug_simvars() -> Vec<(&'static str, f32)> {
+        let mut simvars = Self::simvars();
+        simvars.push(("interact_force", 300.0));
+        simvars
+    }
+
+    fn new(simvars: &FluidSimVars) -> Self {
+        Self::new(simvars.get("kernel_radius"), simvars.get("particle_mass"))
+    }
+
+    fn reset(&mut self, simvars: &FluidSimVars) {
+        self.reset(simvars.get("kernel_radius"), simvars.get("particle_mass"));
+    }
+
+    fn step(&mut self, dt: f32, simvars: &FluidSimVars) {
+        self.step(
+            dt,
+            simvars.get("iterations") as u32,
+            simvars.get("rest_dens"),
+            simvars.get("relaxation"),
+            simvars.get("xsph_visc"),
+            simvars.get("gravity"),
+        );
+    }
+
+    fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        self.get_velocity_at(position)
+    }
+
+    fn interact(&mut self, position: Vec2, drag: Vec2, _pressed: bool, simvars: &FluidSimVars) {
+        if drag != Vec2::ZERO {
+            self.add_external_force(position, drag * simvars.get("interact_force"), 10.0);
+        }
+    }
+
+    fn seed_velocity(&mut self, field: &VelocityField) {
+        self.set_velocities(|position| field.sample(position));
+    }
+
+    fn save_state(&self) -> FluidState {
+        let particles: Vec<&Particle> = self.particles().iter().collect();
+        FluidState::default()
+            .with("mass", particles.iter().map(|particle| particle.mass))
+            .with_vec2s("position", particles.iter().map(|particle| particle.position))
+            .with_vec2s("velocity", particles.iter().map(|particle| particle.velocity))
+    }
+
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError> {
+        let mass = state.get("mass")?;
+        let position = state.get_vec2s("position", mass.len())?;
+        let velocity = state.get_vec2s("velocity", mass.len())?;
+        let particles = (0..mass.len())
+            .map(|i| Particle { velocity: velocity[i], ..Particle::new(position[i], mass[i]) });
+        self.set_particles(particles);
+        Ok(())
+    }
+
+    fn material(&self) -> MetaballMaterial {
+        MetaballMaterial { color: Color::BLUE, balls: self.get_balls() }
+    }
+
+    fn update_material(&self, material: &mut MetaballMaterial, _simvars: &FluidSimVars) {
+        material.balls = self.get_balls();
+    }
+
+    fn draw_gizmos(&self, gizmos: &mut Gizmos, _simvars: &FluidSimVars) {
+        for particle in self.particles() {
+            gizmos.circle_2d(particle.position, 2.0, Color::rgba(0.6, 0.8, 1.0, 0.3));
+        }
+    }
+}
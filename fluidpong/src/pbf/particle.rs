@@ -0,0 +1,46 @@
+use bevy::math::Vec2;
+
+use crate::sph::spatial_grid::Position;
+
+/// A particle in the PBF simulation.
+///
+/// Unlike an SPH particle, a PBF particle keeps both its position at the start
+/// of the step and a predicted position that the density constraints act on.
+/// Neighbor queries are made against the predicted position.
+#[derive(Debug, Clone)]
+pub struct Particle {
+    pub mass: f32,
+    pub position: Vec2,
+    pub predicted: Vec2,
+    pub velocity: Vec2,
+    pub ext_force: Vec2,
+
+    pub density: f32,
+    pub lambda: f32,
+    pub delta: Vec2,
+}
+
+impl Particle {
+    /// Creates a new particle with the given position and mass.
+    pub fn new(position: Vec2, mass: f32) -> Self {
+        Self {
+            mass,
+            position,
+            predicted: position,
+            velocity: Vec2::ZERO,
+            ext_force: Vec2::ZERO,
+            density: 0.0,
+            lambda: 0.0,
+            delta: Vec2::ZERO,
+        }
+    }
+}
+
+impl Position for Particle {
+    // The grid is keyed on where the constraint solver currently has the
+    // particle, not where it started the step.
+    #[allow(clippy::misnamed_getters)]
+    fn position(&self) -> Vec2 {
+        self.predicted
+    }
+}
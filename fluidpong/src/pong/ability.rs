@@ -0,0 +1,192 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::controls::PaddleInput;
+use super::pongfluid::PongFluid;
+use super::{Paddle, Player1, Position};
+use crate::SCREEN_WIDTH;
+
+/// How far in front of the paddle each ability is centered.
+const VORTEX_DISTANCE: f32 = 60.0;
+const SUCTION_DISTANCE: f32 = 30.0;
+const WALL_DISTANCE: f32 = 90.0;
+const WAVE_DISTANCE: f32 = 20.0;
+
+/// The strength of each ability, in steps of emitting. Suction's is for each
+/// step it lasts.
+const VORTEX_STRENGTH: f32 = 15.0;
+const SUCTION_STRENGTH: f32 = 2.0;
+const WALL_STRENGTH: f32 = 3.0;
+const WAVE_STRENGTH: f32 = 4.0;
+
+const ICON_SIZE: f32 = 16.0;
+
+/// A fluid move a paddle can make besides emitting.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ability {
+    /// Spins up a swirl in front of the paddle.
+    Vortex,
+    /// Draws the fluid, and the ball with it, toward the paddle for a moment.
+    Suction,
+    /// Packs a band of fluid in front of the paddle that slows the ball.
+    Wall,
+    /// Sends a wave across the whole arena.
+    Wave,
+}
+
+impl Ability {
+    pub const ALL: [Ability; 4] = [Ability::Vortex, Ability::Suction, Ability::Wall, Ability::Wave];
+
+    /// How many seconds after use it can be used again.
+    pub fn cooldown(self) -> f32 {
+        match self {
+            Ability::Vortex => 3.0,
+            Ability::Suction => 5.0,
+            Ability::Wall => 6.0,
+            Ability::Wave => 4.0,
+        }
+    }
+
+    /// How many seconds it keeps acting on the fluid once used, 0 for a single
+    /// step.
+    pub fn duration(self) -> f32 {
+        match self {
+            Ability::Suction => 1.0,
+            Ability::Vortex | Ability::Wall | Ability::Wave => 0.0,
+        }
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Ability::Vortex => Color::CYAN,
+            Ability::Suction => Color::FUCHSIA,
+            Ability::Wall => Color::ORANGE,
+            Ability::Wave => Color::LIME_GREEN,
+        }
+    }
+}
+
+/// A paddle's abilities, and how long until each can be used again.
+#[derive(Component, Debug, Default, Clone, PartialEq)]
+pub struct Abilities {
+    /// Seconds until each ability is ready, in the order of `Ability::ALL`.
+    pub cooldowns: [f32; 4],
+    /// Seconds each ability keeps acting, in the same order.
+    active: [f32; 4],
+}
+
+impl Abilities {
+    /// Advances the cooldowns by a step of `dt` seconds, using the abilities
+    /// whose keys are held and that are ready.
+    pub fn step(&mut self, held: impl Fn(Ability) -> bool, dt: f32) {
+        for ability in Ability::ALL {
+            let i = ability.index();
+            self.cooldowns[i] = (self.cooldowns[i] - dt).max(0.0);
+            self.active[i] = (self.active[i] - dt).max(0.0);
+            if held(ability) && self.cooldowns[i] == 0.0 {
+                self.cooldowns[i] = ability.cooldown();
+                // Acts at least this step.
+                self.active[i] = ability.duration().max(dt);
+            }
+        }
+    }
+
+    /// Whether the ability acts on the fluid this step.
+    pub fn is_active(&self, ability: Ability) -> bool {
+        self.active[ability.index()] > 0.0
+    }
+}
+
+/// An icon for one of a paddle's abilities, dimmed while it cools down.
+#[derive(Component)]
+pub(crate) struct AbilityIcon(Entity, Ability);
+
+/// Uses the abilities the players hold.
+pub(crate) fn update_abilities(
+    time: Res<Time>,
+    mut paddles: Query<(&mut Abilities, &PaddleInput)>,
+) {
+    for (mut abilities, input) in &mut paddles {
+        abilities.step(|ability| input.abilities[ability.index()], time.delta_seconds());
+    }
+}
+
+pub(crate) fn use_abilities<F: PongFluid + Component>(
+    mut fluid_query: Query<&mut F>,
+    paddles: Query<(&Position, &Abilities, Has<Player1>), With<Paddle>>,
+) {
+    let Ok(mut fluid) = fluid_query.get_single_mut() else {
+        return;
+    };
+    for (position, abilities, is_player1) in &paddles {
+        let direction = if is_player1 { Vec2::X } else { Vec2::NEG_X };
+        let ahead = |distance: f32| position.0 + direction * distance;
+        for ability in Ability::ALL.into_iter().filter(|ability| abilities.is_active(*ability)) {
+            match ability {
+                // The left and right paddles spin their swirls opposite ways.
+                Ability::Vortex => {
+                    fluid.apply_vortex(ahead(VORTEX_DISTANCE), VORTEX_STRENGTH * direction.x)
+                }
+                Ability::Suction => fluid.apply_suction(ahead(SUCTION_DISTANCE), SUCTION_STRENGTH),
+                Ability::Wall => fluid.apply_wall(ahead(WALL_DISTANCE), direction * WALL_STRENGTH),
+                Ability::Wave => fluid.apply_wave(ahead(WAVE_DISTANCE), direction * WAVE_STRENGTH),
+            }
+        }
+    }
+}
+
+/// Puts a row of ability icons under each paddle's energy meter.
+pub(crate) fn spawn_ability_icons(
+    mut commands: Commands,
+    paddles: Query<(Entity, Has<Player1>), With<Abilities>>,
+) {
+    for (paddle, is_player1) in &paddles {
+        let beside_score = Val::Px(SCREEN_WIDTH / 2.0 * 1.2 + 10.0);
+        let (left, right) =
+            if is_player1 { (Val::Auto, beside_score) } else { (beside_score, Val::Auto) };
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(55.0),
+                    left,
+                    right,
+                    column_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|parent| {
+                for ability in Ability::ALL {
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Px(ICON_SIZE),
+                                height: Val::Px(ICON_SIZE),
+                                ..default()
+                            },
+                            background_color: ability.color().into(),
+                            ..default()
+                        },
+                        AbilityIcon(paddle, ability),
+                    ));
+                }
+            });
+    }
+}
+
+pub(crate) fn update_ability_icons(
+    paddles: Query<&Abilities>,
+    mut icons: Query<(&AbilityIcon, &mut BackgroundColor)>,
+) {
+    for (icon, mut background) in &mut icons {
+        if let Ok(abilities) = paddles.get(icon.0) {
+            let cooldown = abilities.cooldowns[icon.1.index()] / icon.1.cooldown();
+            background.0 = icon.1.color().with_a(1.0 - 0.8 * cooldown);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::collision::move_ball;
+use super::level::{Level, Obstacle};
+use super::pongfluid::PongFluid;
+use super::{
+    Ball, Paddle, Position, Velocity, BALL_SIZE, PADDLE_HEIGHT, PADDLE_SPEED, PADDLE_WIDTH,
+};
+use crate::GAME_WIDTH;
+
+/// How many fixed steps apart the ball's path is predicted. Predicting samples
+/// the fluid every step ahead, which is slow for particle fluids.
+const FORECAST_EVERY: u32 = 10;
+
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Difficulty {
+    /// Follows the ball once it is close, slowly.
+    Easy,
+    /// Meets the ball where the fluid will carry it, and emits when it would
+    /// otherwise slip past.
+    #[default]
+    Medium,
+    /// Like medium, at full speed and looking further ahead.
+    Hard,
+}
+
+struct Skill {
+    /// How far away, in x, the paddle starts going for an approaching ball.
+    sight: f32,
+    /// How many fixed steps ahead the ball's path is predicted. 0 to follow
+    /// the ball instead.
+    lookahead: u32,
+    /// The fastest the paddle moves, as a fraction of a player's.
+    speed: f32,
+    /// How close the ball gets before the paddle emits to deflect it. 0 to
+    /// never emit.
+    emit_range: f32,
+    /// How far from the middle of the paddle it tries to meet the ball, as a
+    /// fraction of half the paddle, to send it back at an angle.
+    aim: f32,
+}
+
+impl Difficulty {
+    fn skill(self) -> Skill {
+        match self {
+            Difficulty::Easy => Skill {
+                sight: GAME_WIDTH / 4.0,
+                lookahead: 0,
+                speed: 0.15,
+                emit_range: 0.0,
+                aim: 0.0,
+            },
+            Difficulty::Medium => Skill {
+                sight: GAME_WIDTH,
+                lookahead: 120,
+                speed: 0.3,
+                emit_range: 80.0,
+                aim: 0.6,
+            },
+            Difficulty::Hard => Skill {
+                sight: GAME_WIDTH,
+                lookahead: 240,
+                speed: 1.0,
+                emit_range: 200.0,
+                aim: 0.9,
+            },
+        }
+    }
+}
+
+/// Which paddles the computer plays, and how well.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AiPlayers {
+    pub player1: Option<Difficulty>,
+    pub player2: Option<Difficulty>,
+}
+
+/// Moves a paddle instead of the keyboard, and emits for it.
+#[derive(Component)]
+pub struct AiController {
+    pub difficulty: Difficulty,
+    /// Whether the paddle emits this step.
+    pub(crate) emitting: bool,
+    /// Where the ball was last predicted to reach the paddle, and in how many
+    /// steps from now.
+    forecast: Option<(f32, u32)>,
+    /// Steps until the next prediction.
+    forecast_in: u32,
+    /// The ball it goes for, of those in play.
+    ball: Option<Entity>,
+}
+
+impl AiController {
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self { difficulty, emitting: false, forecast: None, forecast_in: 0, ball: None }
+    }
+}
+
+/// The AI systems. They run in `FixedUpdate`, before the paddles move.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AiSet;
+
+/// Follows the ball through a fluid frozen in its current state, the way
+/// `push_fluid_with_ball` and `handle_collisions` move it in steps of `dt` but
+/// without the paddles, bouncing off `obstacles`, until it crosses `x`.
+/// Returns where it crosses and after how many steps, or `None` if it doesn't
+/// within `steps`.
+pub fn predict_ball(
+    fluid: &impl PongFluid,
+    mut position: Vec2,
+    mut velocity: Vec2,
+    x: f32,
+    dt: f32,
+    steps: u32,
+    obstacles: &[Obstacle],
+) -> Option<(Vec2, u32)> {
+    let side = (position.x - x).signum();
+    for step in 1..=steps {
+        velocity += fluid.get_fluid_force_at(position, velocity * dt) / dt;
+        (position, velocity) = move_ball(position, velocity, dt, &[], obstacles);
+        if (position.x - x).signum() != side {
+            return Some((position, step));
+        }
+    }
+    None
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn drive_ai_paddles<F: PongFluid + Component>(
+    time: Res<Time>,
+    level: Res<Level>,
+    fluid: Query<&F>,
+    balls: Query<(Entity, &Position, &Velocity), With<Ball>>,
+    mut paddles: Query<
+        (&Position, &mut Velocity, &mut AiController),
+        (With<Paddle>, Without<Ball>),
+    >,
+) {
+    let Ok(fluid) = fluid.get_single() else {
+        return;
+    };
+    let dt = time.delta_seconds();
+    let obstacles = level.ball_obstacles();
+    for (position, mut velocity, mut ai) in &mut paddles {
+        let skill = ai.difficulty.skill();
+        // The side of the paddle the ball bounces off.
+        let toward_center = -position.0.x.signum();
+        let contact_x = position.0.x + toward_center * (PADDLE_WIDTH / 2.0 + BALL_SIZE);
+        let approaching = |velocity: &Velocity| velocity.0.x * toward_center < 0.0;
+
+        // Goes for the closest ball coming at it, or the closest one if none
+        // is.
+        let Some((ball, ball_position, ball_velocity)) = balls.iter().min_by(|a, b| {
+            let key = |(_, position, velocity): &(Entity, &Position, &Velocity)| {
+                (!approaching(velocity), (position.0.x - contact_x).abs())
+            };
+            key(a).partial_cmp(&key(b)).unwrap_or(std::cmp::Ordering::Equal)
+        }) else {
+            continue;
+        };
+        if ai.ball != Some(ball) {
+            ai.ball = Some(ball);
+            ai.forecast_in = 0;
+        }
+        let approaching = approaching(ball_velocity);
+        let distance = (ball_position.0.x - contact_x).abs();
+
+        let (target, steps_left) = if !approaching || distance > skill.sight {
+            ai.forecast_in = 0;
+            // Wait in the middle, or where it is if it only follows the ball.
+            (if skill.lookahead == 0 { position.0.y } else { 0.0 }, u32::MAX)
+        } else if skill.lookahead == 0 {
+            (ball_position.0.y, u32::MAX)
+        } else {
+            if ai.forecast_in == 0 {
+                ai.forecast = predict_ball(
+                    fluid,
+                    ball_position.0,
+                    ball_velocity.0,
+                    contact_x,
+                    dt,
+                    skill.lookahead,
+                    &obstacles,
+                )
+                .map(|(crossing, steps)| (crossing.y, steps));
+                ai.forecast_in = FORECAST_EVERY;
+            } else if let Some((_, steps)) = &mut ai.forecast {
+                *steps = steps.saturating_sub(1);
+            }
+            ai.forecast_in -= 1;
+            match ai.forecast {
+                // Meet it off center, to send it toward the far side.
+                Some((y, steps)) => (y + y.signum() * skill.aim * PADDLE_HEIGHT / 2.0, steps),
+                None => (ball_position.0.y, u32::MAX),
+            }
+        };
+
+        let offset = target - position.0.y;
+        let top_speed = skill.speed * PADDLE_SPEED;
+        velocity.0.y = (offset / dt).clamp(-top_speed, top_speed);
+
+        // Emit when the paddle can't get to the ball in time, to push it back.
+        let reach = steps_left as f32 * dt * top_speed + PADDLE_HEIGHT / 2.0;
+        let in_range = distance < skill.emit_range;
+        ai.emitting = approaching && in_range && offset.abs() > reach;
+    }
+}
@@ -0,0 +1,309 @@
+use std::f32::consts::{FRAC_PI_3, PI};
+
+use bevy::prelude::*;
+
+use super::level::Obstacle;
+use super::{BALL_SIZE, PADDLE_SPEED};
+use crate::GAME_HEIGHT;
+
+/// The steepest a paddle sends the ball back, from its edges.
+const MAX_BOUNCE_ANGLE: f32 = FRAC_PI_3;
+/// How much a paddle moving at full speed turns the ball.
+const SPIN_ANGLE: f32 = PI / 12.0;
+/// The most bounces in one step. A ball fast enough for more is left to the
+/// next step.
+const MAX_HITS: usize = 4;
+/// The most times the ball is checked against the obstacles in one step.
+const MAX_OBSTACLE_CHECKS: usize = 16;
+
+/// A paddle, as the ball sees it.
+#[derive(Debug, Clone, Copy)]
+pub struct PaddleBody {
+    pub position: Vec2,
+    pub size: Vec2,
+    /// In pixels per second.
+    pub velocity: Vec2,
+}
+
+enum Hit {
+    Wall,
+    Paddle(usize),
+}
+
+/// Moves the ball by `dt` seconds of `velocity`, in pixels per second,
+/// bouncing off the walls and the inner faces of the paddles along the way
+/// instead of only where it ends up, so that a fast ball can't pass through
+/// them. Returns the new position and velocity.
+///
+/// Off a paddle, the ball leaves at an angle set by how far from the middle
+/// it hit and by how the paddle was moving, at the same speed.
+pub fn sweep_ball(
+    mut position: Vec2,
+    mut velocity: Vec2,
+    dt: f32,
+    paddles: &[PaddleBody],
+) -> (Vec2, Vec2) {
+    let wall = GAME_HEIGHT / 2.0;
+    if position.y.abs() > wall {
+        position.y = position.y.clamp(-wall, wall);
+        if velocity.y * position.y > 0.0 {
+            velocity.y *= -1.0;
+        }
+    }
+
+    let mut remaining = dt;
+    for _ in 0..MAX_HITS {
+        let mut first: Option<(f32, Hit)> = None;
+        if velocity.y != 0.0 {
+            let t = (wall * velocity.y.signum() - position.y) / velocity.y;
+            if (0.0..remaining).contains(&t) {
+                first = Some((t, Hit::Wall));
+            }
+        }
+        for (i, paddle) in paddles.iter().enumerate() {
+            let toward_center = -paddle.position.x.signum();
+            if velocity.x * toward_center >= 0.0 {
+                continue;
+            }
+            let face = paddle.position.x + toward_center * (paddle.size.x / 2.0 + BALL_SIZE);
+            let t = (face - position.x) / velocity.x;
+            if !(0.0..remaining).contains(&t)
+                || first.as_ref().is_some_and(|(first, _)| *first <= t)
+            {
+                continue;
+            }
+            let y = position.y + velocity.y * t;
+            if (y - paddle.position.y).abs() <= paddle.size.y / 2.0 + BALL_SIZE {
+                first = Some((t, Hit::Paddle(i)));
+            }
+        }
+
+        let Some((t, hit)) = first else {
+            break;
+        };
+        position += velocity * t;
+        remaining -= t;
+        match hit {
+            Hit::Wall => velocity.y *= -1.0,
+            Hit::Paddle(i) => velocity = bounce(&paddles[i], position, velocity),
+        }
+    }
+    (position + velocity * remaining, velocity)
+}
+
+/// Moves the ball like `sweep_ball`, also bouncing it off `obstacles`. The
+/// obstacles are checked at points no further apart than the ball's radius,
+/// so that it can't pass through even a thin one unless it is fast enough to
+/// need more than `MAX_OBSTACLE_CHECKS`.
+pub fn move_ball(
+    mut position: Vec2,
+    mut velocity: Vec2,
+    dt: f32,
+    paddles: &[PaddleBody],
+    obstacles: &[Obstacle],
+) -> (Vec2, Vec2) {
+    if obstacles.is_empty() {
+        return sweep_ball(position, velocity, dt, paddles);
+    }
+    let checks =
+        ((velocity.length() * dt / BALL_SIZE).ceil() as usize).clamp(1, MAX_OBSTACLE_CHECKS);
+    let dt = dt / checks as f32;
+    for _ in 0..checks {
+        (position, velocity) = sweep_ball(position, velocity, dt, paddles);
+        for obstacle in obstacles {
+            if let Some((outside, normal)) = obstacle.push_out(position, BALL_SIZE) {
+                position = outside;
+                let inward = velocity.dot(normal);
+                if inward < 0.0 {
+                    velocity -= 2.0 * inward * normal;
+                }
+            }
+        }
+    }
+    (position, velocity)
+}
+
+/// Bounces two balls off each other if they touch and are moving together,
+/// exchanging their velocities along the line between their centers as
+/// balls of equal mass do. Returns their new velocities, or `None` if they
+/// don't collide.
+///
+/// Balls are only checked where they end up each step, so two fast balls can
+/// pass through each other, unlike a ball and a paddle.
+pub fn collide_balls(
+    a: Vec2,
+    a_velocity: Vec2,
+    b: Vec2,
+    b_velocity: Vec2,
+) -> Option<(Vec2, Vec2)> {
+    let offset = b - a;
+    let distance = offset.length();
+    // Balls at the same spot, like a ball and the one it split into, have no
+    // side to bounce off.
+    if distance == 0.0 || distance > 2.0 * BALL_SIZE {
+        return None;
+    }
+    let normal = offset / distance;
+    let closing = (a_velocity - b_velocity).dot(normal);
+    if closing <= 0.0 {
+        return None;
+    }
+    Some((a_velocity - normal * closing, b_velocity + normal * closing))
+}
+
+fn bounce(paddle: &PaddleBody, position: Vec2, velocity: Vec2) -> Vec2 {
+    let toward_center = -paddle.position.x.signum();
+    let offset = (position.y - paddle.position.y) / (paddle.size.y / 2.0 + BALL_SIZE);
+    let spin = paddle.velocity.y / PADDLE_SPEED;
+    let angle = (offset.clamp(-1.0, 1.0) * MAX_BOUNCE_ANGLE + spin * SPIN_ANGLE)
+        .clamp(-MAX_BOUNCE_ANGLE, MAX_BOUNCE_ANGLE);
+    Vec2::new(toward_center * angle.cos(), angle.sin()) * velocity.length()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FIXED_TIMESTEP as DT;
+    use crate::pong::{PADDLE_HEIGHT, PADDLE_WIDTH};
+    use crate::GAME_WIDTH;
+
+    const RIGHT_PADDLE_X: f32 = GAME_WIDTH / 2.0 - 50.0;
+    /// Where the ball's center stops against the right paddle.
+    const RIGHT_FACE: f32 = RIGHT_PADDLE_X - PADDLE_WIDTH / 2.0 - BALL_SIZE;
+
+    fn right_paddle(y: f32, velocity: f32) -> PaddleBody {
+        PaddleBody {
+            position: Vec2::new(RIGHT_PADDLE_X, y),
+            size: Vec2::new(PADDLE_WIDTH, PADDLE_HEIGHT),
+            velocity: Vec2::new(0.0, velocity),
+        }
+    }
+
+    #[test]
+    fn fast_ball_does_not_tunnel_through_paddle() {
+        // Far enough in one step to end up past the paddle and off the board.
+        let speed = GAME_WIDTH / DT;
+        let (position, velocity) =
+            sweep_ball(Vec2::ZERO, Vec2::new(speed, 0.0), DT, &[right_paddle(0.0, 0.0)]);
+        assert!(velocity.x < 0.0, "ball went through the paddle: {velocity}");
+        assert!(position.x < RIGHT_FACE, "ball ended inside the paddle: {position}");
+        assert!((velocity.length() - speed).abs() < 1e-3 * speed);
+    }
+
+    #[test]
+    fn ball_pushed_diagonally_by_fluid_does_not_tunnel() {
+        // A strong push sends the ball down steeply; the paddle sits where it
+        // crosses the face, far from where it starts.
+        let start = Vec2::new(100.0, 100.0);
+        let velocity = Vec2::new(400.0, -300.0) / DT;
+        let crossing = start.y + velocity.y * (RIGHT_FACE - start.x) / velocity.x;
+        let (position, velocity) =
+            sweep_ball(start, velocity, DT, &[right_paddle(crossing + 20.0, 0.0)]);
+        assert!(velocity.x < 0.0, "ball went through the paddle: {velocity}");
+        assert!(position.x < RIGHT_FACE);
+    }
+
+    #[test]
+    fn ball_passing_beside_paddle_is_not_deflected() {
+        let velocity = Vec2::new(GAME_WIDTH / DT, 0.0);
+        let (position, after) =
+            sweep_ball(Vec2::new(0.0, 100.0), velocity, DT, &[right_paddle(0.0, 0.0)]);
+        assert_eq!(after, velocity);
+        assert!((position - Vec2::new(GAME_WIDTH, 100.0)).length() < 1e-3);
+    }
+
+    #[test]
+    fn ball_leaving_paddle_is_not_caught_again() {
+        let velocity = Vec2::new(-180.0, 0.0);
+        let (_, after) =
+            sweep_ball(Vec2::new(RIGHT_FACE, 0.0), velocity, DT, &[right_paddle(0.0, 0.0)]);
+        assert_eq!(after, velocity);
+    }
+
+    #[test]
+    fn fast_ball_does_not_tunnel_through_walls() {
+        // Bounces off the top wall, then crosses most of the board back down.
+        let (position, velocity) =
+            sweep_ball(Vec2::ZERO, Vec2::new(0.0, 1.2 * GAME_HEIGHT / DT), DT, &[]);
+        assert!(velocity.y < 0.0);
+        assert!((position.y - -0.2 * GAME_HEIGHT).abs() < 1e-3, "ended at {position}");
+    }
+
+    #[test]
+    fn ball_outside_wall_is_brought_back() {
+        let (position, velocity) =
+            sweep_ball(Vec2::new(0.0, GAME_HEIGHT), Vec2::new(0.0, 120.0), DT, &[]);
+        assert_eq!(velocity, Vec2::new(0.0, -120.0));
+        assert!(position.y < GAME_HEIGHT / 2.0);
+    }
+
+    /// Sends the ball straight at the right paddle, to hit it halfway through
+    /// the step, and returns its velocity after.
+    fn hit(y: f32, paddle: PaddleBody) -> Vec2 {
+        let speed = 240.0;
+        sweep_ball(
+            Vec2::new(RIGHT_FACE - speed * DT / 2.0, y),
+            Vec2::new(speed, 0.0),
+            DT,
+            &[paddle],
+        )
+        .1
+    }
+
+    #[test]
+    fn bounce_angle_depends_on_hit_offset() {
+        let middle = hit(0.0, right_paddle(0.0, 0.0));
+        assert!(middle.y.abs() < 1e-3, "a hit in the middle goes straight back: {middle}");
+        let above = hit(PADDLE_HEIGHT / 4.0, right_paddle(0.0, 0.0));
+        let edge = hit(PADDLE_HEIGHT / 2.0, right_paddle(0.0, 0.0));
+        assert!(0.0 < above.y && above.y < edge.y);
+        assert!(hit(-PADDLE_HEIGHT / 4.0, right_paddle(0.0, 0.0)).y < 0.0);
+        let steepest = Vec2::from_angle(PI - MAX_BOUNCE_ANGLE);
+        assert!(edge.normalize().y <= steepest.y + 1e-5);
+        for velocity in [middle, above, edge] {
+            assert!(velocity.x < 0.0);
+            assert!((velocity.length() - 240.0).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn ball_bounces_off_obstacles() {
+        let block = Obstacle::Rect { center: Vec2::new(50.0, 0.0), size: Vec2::new(20.0, 100.0) };
+        let velocity = Vec2::new(240.0, 60.0);
+        let start = Vec2::new(40.0 - BALL_SIZE - 1.0, 0.0);
+        let (position, after) = move_ball(start, velocity, DT, &[], &[block]);
+        assert_eq!(after, Vec2::new(-240.0, 60.0));
+        assert!(position.x <= 40.0 - BALL_SIZE);
+
+        // Far enough in one step to pass a thin wall, checking only the end.
+        let wall = Obstacle::Rect { center: Vec2::new(50.0, 0.0), size: Vec2::new(2.0, 100.0) };
+        let velocity = Vec2::new(75.0 / DT, 0.0);
+        let (position, after) = move_ball(Vec2::ZERO, velocity, DT, &[], &[wall]);
+        assert!(after.x < 0.0, "ball went through the wall: {after}");
+        assert!(position.x < 49.0);
+    }
+
+    #[test]
+    fn balls_meeting_head_on_swap_velocities() {
+        let a = Vec2::new(-BALL_SIZE * 0.9, 0.0);
+        let b = Vec2::new(BALL_SIZE * 0.9, 0.0);
+        let (a_velocity, b_velocity) =
+            collide_balls(a, Vec2::new(120.0, 30.0), b, Vec2::new(-60.0, 0.0)).unwrap();
+        assert_eq!(a_velocity, Vec2::new(-60.0, 30.0));
+        assert_eq!(b_velocity, Vec2::new(120.0, 0.0));
+    }
+
+    #[test]
+    fn balls_moving_apart_do_not_collide() {
+        let a = Vec2::ZERO;
+        let b = Vec2::new(BALL_SIZE, BALL_SIZE);
+        assert!(collide_balls(a, Vec2::new(-60.0, 0.0), b, Vec2::new(60.0, 60.0)).is_none());
+        assert!(collide_balls(a, Vec2::X, a, Vec2::Y).is_none());
+    }
+
+    #[test]
+    fn moving_paddle_turns_the_ball() {
+        assert!(hit(0.0, right_paddle(0.0, PADDLE_SPEED)).y > 0.0);
+        assert!(hit(0.0, right_paddle(0.0, -PADDLE_SPEED)).y < 0.0);
+    }
+}
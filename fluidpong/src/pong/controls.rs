@@ -0,0 +1,212 @@
+use std::fmt;
+use std::path::Path;
+
+use bevy::input::gamepad::{GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::ability::Ability;
+use super::ai::AiController;
+use super::Player1;
+
+/// Something a player does with their paddle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    Emit,
+    Ability(Ability),
+}
+
+/// A key or gamepad button that performs an action while held.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyCode),
+    Button(GamepadButtonType),
+}
+
+/// How a player plays their paddle.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerControls {
+    /// The id of the gamepad that plays the paddle, besides the keyboard.
+    /// Gamepads are numbered from 0 in the order they connect.
+    pub gamepad: usize,
+    /// The stick that moves the paddle, as fast as it is pushed.
+    pub move_axis: Option<GamepadAxisType>,
+    /// An action can have several bindings, and a binding several actions.
+    pub bindings: Vec<(Action, Binding)>,
+}
+
+impl PlayerControls {
+    /// Reads what the player is doing, with sticks pushed less than
+    /// `dead_zone` counted as centered.
+    pub fn read(
+        &self,
+        keyboard_input: &ButtonInput<KeyCode>,
+        gamepad_buttons: &ButtonInput<GamepadButton>,
+        gamepad_axes: &Axis<GamepadAxis>,
+        dead_zone: f32,
+    ) -> PaddleInput {
+        let gamepad = Gamepad::new(self.gamepad);
+        let held = |action: Action| {
+            self.bindings.iter().any(|(bound, binding)| {
+                *bound == action
+                    && match *binding {
+                        Binding::Key(key) => keyboard_input.pressed(key),
+                        Binding::Button(button) => {
+                            gamepad_buttons.pressed(GamepadButton::new(gamepad, button))
+                        }
+                    }
+            })
+        };
+
+        let buttons = held(Action::MoveUp) as i32 - held(Action::MoveDown) as i32;
+        let stick = (self.move_axis)
+            .and_then(|axis| gamepad_axes.get(GamepadAxis::new(gamepad, axis)))
+            .unwrap_or(0.0);
+        let movement = if buttons != 0 {
+            buttons as f32
+        } else if stick.abs() > dead_zone {
+            // Rescaled so that the paddle starts from a standstill at the
+            // edge of the dead zone.
+            stick.signum() * (stick.abs() - dead_zone) / (1.0 - dead_zone)
+        } else {
+            0.0
+        };
+        PaddleInput {
+            movement: movement.clamp(-1.0, 1.0),
+            emit: held(Action::Emit),
+            abilities: Ability::ALL.map(|ability| held(Action::Ability(ability))),
+        }
+    }
+
+    fn new(gamepad: usize, [up, down, emit]: [KeyCode; 3], abilities: [KeyCode; 4]) -> Self {
+        let mut bindings = vec![
+            (Action::MoveUp, Binding::Key(up)),
+            (Action::MoveDown, Binding::Key(down)),
+            (Action::Emit, Binding::Key(emit)),
+            (Action::MoveUp, Binding::Button(GamepadButtonType::DPadUp)),
+            (Action::MoveDown, Binding::Button(GamepadButtonType::DPadDown)),
+            (Action::Emit, Binding::Button(GamepadButtonType::RightTrigger2)),
+        ];
+        let buttons = [
+            GamepadButtonType::West,
+            GamepadButtonType::South,
+            GamepadButtonType::East,
+            GamepadButtonType::North,
+        ];
+        for ((ability, key), button) in Ability::ALL.into_iter().zip(abilities).zip(buttons) {
+            bindings.push((Action::Ability(ability), Binding::Key(key)));
+            bindings.push((Action::Ability(ability), Binding::Button(button)));
+        }
+        Self { gamepad, move_axis: Some(GamepadAxisType::LeftStickY), bindings }
+    }
+}
+
+/// The controls of both paddles, saved as RON so that they can be edited by
+/// hand.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Controls {
+    /// The left paddle.
+    pub player1: PlayerControls,
+    /// The right paddle.
+    pub player2: PlayerControls,
+    /// How far a stick has to be pushed before it moves a paddle, from 0 to 1.
+    pub dead_zone: f32,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        use KeyCode::*;
+        Self {
+            player1: PlayerControls::new(
+                0,
+                [KeyW, KeyS, ShiftLeft],
+                [Digit1, Digit2, Digit3, Digit4],
+            ),
+            player2: PlayerControls::new(
+                1,
+                [ArrowUp, ArrowDown, ShiftRight],
+                [Digit7, Digit8, Digit9, Digit0],
+            ),
+            dead_zone: 0.15,
+        }
+    }
+}
+
+impl Controls {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ControlsError> {
+        let config = ron::ser::PrettyConfig::new();
+        let ron = ron::ser::to_string_pretty(self, config)
+            .map_err(|err| ControlsError::Format(err.to_string()))?;
+        Ok(std::fs::write(path, ron)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ControlsError> {
+        let bytes = std::fs::read(path)?;
+        ron::de::from_bytes(&bytes).map_err(|err| ControlsError::Format(err.to_string()))
+    }
+
+    /// Loads the controls from `path`, or writes the defaults there to be
+    /// edited if there is no file yet.
+    pub fn load_or_create(path: impl AsRef<Path>) -> Result<Self, ControlsError> {
+        if path.as_ref().exists() {
+            return Self::load(path);
+        }
+        let controls = Self::default();
+        controls.save(path)?;
+        Ok(controls)
+    }
+}
+
+#[derive(Debug)]
+pub enum ControlsError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+impl fmt::Display for ControlsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlsError::Io(err) => write!(f, "{err}"),
+            ControlsError::Format(message) => write!(f, "malformed controls: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ControlsError {}
+
+impl From<std::io::Error> for ControlsError {
+    fn from(err: std::io::Error) -> Self {
+        ControlsError::Io(err)
+    }
+}
+
+/// What a player is doing with their paddle this step, from their controls.
+#[derive(Component, Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct PaddleInput {
+    /// From -1 (down) to 1 (up), as a fraction of the paddle's top speed.
+    pub movement: f32,
+    pub emit: bool,
+    /// Whether each ability is held, in the order of `Ability::ALL`.
+    pub abilities: [bool; 4],
+}
+
+/// Where the paddles' input is set, from the controls or over the network.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ControlsSet;
+
+/// Reads the controls of the paddles the computer doesn't play.
+pub(crate) fn read_controls(
+    controls: Res<Controls>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut paddles: Query<(&mut PaddleInput, Has<Player1>), Without<AiController>>,
+) {
+    for (mut input, is_player1) in &mut paddles {
+        let player = if is_player1 { &controls.player1 } else { &controls.player2 };
+        *input = player.read(&keyboard_input, &gamepad_buttons, &gamepad_axes, controls.dead_zone);
+    }
+}
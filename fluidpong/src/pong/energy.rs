@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+
+use super::ai::AiController;
+use super::controls::PaddleInput;
+use super::Player1;
+use crate::SCREEN_WIDTH;
+
+/// How much of a full meter emitting drains per second.
+const DRAIN: f32 = 0.5;
+/// How much of a full meter comes back per second while not emitting.
+const REGEN: f32 = 0.2;
+/// How long emit is held for the strongest blast, in seconds.
+const FULL_CHARGE: f32 = 1.5;
+/// The strongest blast, as a multiple of one step of emitting.
+const MAX_BLAST: f32 = 20.0;
+
+const METER_WIDTH: f32 = 100.0;
+
+/// A paddle's fluid energy. Holding emit streams fluid from the paddle while
+/// there is energy left, and charges a blast that fires on release.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Energy {
+    /// From 0 to 1.
+    pub level: f32,
+    /// How long emit has been held with energy to spend, in seconds.
+    pub charge: f32,
+    /// Whether the paddle emits this step.
+    pub(crate) emit: bool,
+    /// The strength of the blast fired this step, 0 for none.
+    pub(crate) blast: f32,
+}
+
+impl Default for Energy {
+    fn default() -> Self {
+        Self { level: 1.0, charge: 0.0, emit: false, blast: 0.0 }
+    }
+}
+
+impl Energy {
+    /// Advances the meter by a step of `dt` seconds, with emit held or not.
+    pub fn step(&mut self, held: bool, dt: f32) {
+        self.emit = held && self.level > 0.0;
+        self.blast = 0.0;
+        if self.emit {
+            self.level = (self.level - DRAIN * dt).max(0.0);
+            self.charge = (self.charge + dt).min(FULL_CHARGE);
+        } else if !held {
+            self.blast = MAX_BLAST * self.charge / FULL_CHARGE;
+            self.charge = 0.0;
+            self.level = (self.level + REGEN * dt).min(1.0);
+        }
+    }
+}
+
+/// The fill of a paddle's energy meter.
+#[derive(Component)]
+pub(crate) struct EnergyMeter(Entity);
+
+/// The fill of a paddle's charge meter, under its energy meter.
+#[derive(Component)]
+pub(crate) struct ChargeMeter(Entity);
+
+pub(crate) fn update_energy(
+    time: Res<Time>,
+    mut paddles: Query<(&mut Energy, &PaddleInput, Option<&AiController>)>,
+) {
+    for (mut energy, input, ai) in &mut paddles {
+        let held = ai.map_or(input.emit, |ai| ai.emitting);
+        energy.step(held, time.delta_seconds());
+    }
+}
+
+/// Puts a meter beside the scoreboard for each paddle, on its side.
+pub(crate) fn spawn_energy_meters(
+    mut commands: Commands,
+    paddles: Query<(Entity, Has<Player1>), With<Energy>>,
+) {
+    for (paddle, is_player1) in &paddles {
+        let beside_score = Val::Px(SCREEN_WIDTH / 2.0 * 1.2 + 10.0);
+        let (left, right) =
+            if is_player1 { (Val::Auto, beside_score) } else { (beside_score, Val::Auto) };
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(35.0),
+                    left,
+                    right,
+                    width: Val::Px(METER_WIDTH),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(2.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            width: Val::Percent(100.0),
+                            height: Val::Px(10.0),
+                            ..default()
+                        },
+                        background_color: Color::DARK_GRAY.into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn((
+                            NodeBundle {
+                                style: Style { height: Val::Percent(100.0), ..default() },
+                                background_color: Color::WHITE.into(),
+                                ..default()
+                            },
+                            EnergyMeter(paddle),
+                        ));
+                    });
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(0.0),
+                            height: Val::Px(4.0),
+                            ..default()
+                        },
+                        background_color: Color::YELLOW.into(),
+                        ..default()
+                    },
+                    ChargeMeter(paddle),
+                ));
+            });
+    }
+}
+
+pub(crate) fn update_energy_meters(
+    paddles: Query<&Energy>,
+    mut energy_meters: Query<(&EnergyMeter, &mut Style), Without<ChargeMeter>>,
+    mut charge_meters: Query<(&ChargeMeter, &mut Style), Without<EnergyMeter>>,
+) {
+    for (meter, mut style) in &mut energy_meters {
+        if let Ok(energy) = paddles.get(meter.0) {
+            style.width = Val::Percent(energy.level * 100.0);
+        }
+    }
+    for (meter, mut style) in &mut charge_meters {
+        if let Ok(energy) = paddles.get(meter.0) {
+            style.width = Val::Percent(energy.charge / FULL_CHARGE * 100.0);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+/// How thick the walls beside the goals are, beyond the ends of the arena.
+const GOAL_WALL_THICKNESS: f32 = 40.0;
+
+/// What the arena holds: obstacles the ball bounces off and the fluid flows
+/// around, fans and vents that keep the fluid moving, and how big the goals
+/// are. Levels are written in RON and chosen with `--level`; anything left
+/// out is empty, so `()` is the plain arena.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Level {
+    pub obstacles: Vec<Obstacle>,
+    pub fans: Vec<Fan>,
+    pub vents: Vec<Vent>,
+    /// The height of each goal, centered on its end of the arena. The ball
+    /// bounces off the rest of the end.
+    pub goal_size: f32,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self { obstacles: Vec::new(), fans: Vec::new(), vents: Vec::new(), goal_size: GAME_HEIGHT }
+    }
+}
+
+impl Level {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LevelError> {
+        let bytes = std::fs::read(path)?;
+        ron::de::from_bytes(&bytes).map_err(|err| LevelError::Format(err.to_string()))
+    }
+
+    /// The walls at the ends of the arena on either side of each goal.
+    pub fn goal_walls(&self) -> Vec<Obstacle> {
+        let length = (GAME_HEIGHT - self.goal_size.max(0.0)) / 2.0;
+        if length <= 0.0 {
+            return Vec::new();
+        }
+        let x = GAME_WIDTH / 2.0 + GOAL_WALL_THICKNESS / 2.0;
+        let y = GAME_HEIGHT / 2.0 - length / 2.0;
+        let size = Vec2::new(GOAL_WALL_THICKNESS, length);
+        [(-x, -y), (-x, y), (x, -y), (x, y)]
+            .into_iter()
+            .map(|(x, y)| Obstacle::Rect { center: Vec2::new(x, y), size })
+            .collect()
+    }
+
+    /// Everything the ball bounces off besides the paddles and the top and
+    /// bottom walls.
+    pub fn ball_obstacles(&self) -> Vec<Obstacle> {
+        let mut obstacles = self.obstacles.clone();
+        obstacles.extend(self.goal_walls());
+        obstacles
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Io(err) => write!(f, "{err}"),
+            LevelError::Format(message) => write!(f, "malformed level: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl From<std::io::Error> for LevelError {
+    fn from(err: std::io::Error) -> Self {
+        LevelError::Io(err)
+    }
+}
+
+/// A solid shape in the arena, in game coordinates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Obstacle {
+    /// A rectangle with its sides along the axes.
+    Rect {
+        center: Vec2,
+        size: Vec2,
+    },
+    Circle {
+        center: Vec2,
+        radius: f32,
+    },
+    /// The polygon through `points`, in either order. It can be concave, but
+    /// its sides must not cross.
+    Polygon {
+        points: Vec<Vec2>,
+    },
+}
+
+impl Obstacle {
+    /// Returns how far `point` is outside the obstacle, negative inside, and
+    /// the direction out of the obstacle at the nearest point of its edge.
+    pub fn distance(&self, point: Vec2) -> (f32, Vec2) {
+        match self {
+            Obstacle::Rect { center, size } => {
+                let offset = point - *center;
+                let outside = offset.abs() - *size / 2.0;
+                if outside.x > 0.0 || outside.y > 0.0 {
+                    let outside = outside.max(Vec2::ZERO);
+                    (outside.length(), (outside * offset.signum()).normalize())
+                } else if outside.x > outside.y {
+                    (outside.x, Vec2::new(offset.x.signum(), 0.0))
+                } else {
+                    (outside.y, Vec2::new(0.0, offset.y.signum()))
+                }
+            }
+            Obstacle::Circle { center, radius } => {
+                let offset = point - *center;
+                (offset.length() - radius, offset.try_normalize().unwrap_or(Vec2::Y))
+            }
+            Obstacle::Polygon { points } => polygon_distance(points, point),
+        }
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        self.distance(point).0 < 0.0
+    }
+
+    /// Returns the nearest point `margin` outside the obstacle and the
+    /// direction out of it there, or `None` if `point` is already that far
+    /// out.
+    pub fn push_out(&self, point: Vec2, margin: f32) -> Option<(Vec2, Vec2)> {
+        let (distance, normal) = self.distance(point);
+        (distance < margin).then(|| (point + normal * (margin - distance), normal))
+    }
+
+    /// The lower and upper corners of a box around the obstacle.
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            Obstacle::Rect { center, size } => (*center - *size / 2.0, *center + *size / 2.0),
+            Obstacle::Circle { center, radius } => {
+                (*center - Vec2::splat(*radius), *center + Vec2::splat(*radius))
+            }
+            Obstacle::Polygon { points } => points.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), point| (min.min(*point), max.max(*point)),
+            ),
+        }
+    }
+}
+
+/// Pushes `point` `margin` outside every obstacle it is in or near. Returns
+/// the new point and the direction it was last pushed, if it was.
+pub fn push_out_of(obstacles: &[Obstacle], mut point: Vec2, margin: f32) -> (Vec2, Option<Vec2>) {
+    let mut pushed = None;
+    for obstacle in obstacles {
+        if let Some((position, normal)) = obstacle.push_out(point, margin) {
+            point = position;
+            pushed = Some(normal);
+        }
+    }
+    (point, pushed)
+}
+
+fn polygon_distance(points: &[Vec2], point: Vec2) -> (f32, Vec2) {
+    let mut nearest = (f32::INFINITY, Vec2::Y);
+    let mut inside = false;
+    // Twice the signed area, positive when the points go counterclockwise.
+    let mut area = 0.0;
+    for (i, &start) in points.iter().enumerate() {
+        let end = points[(i + 1) % points.len()];
+        area += start.perp_dot(end);
+        if (start.y > point.y) != (end.y > point.y)
+            && point.x < start.x + (point.y - start.y) / (end.y - start.y) * (end.x - start.x)
+        {
+            inside = !inside;
+        }
+        let along = end - start;
+        let t = if along == Vec2::ZERO {
+            0.0
+        } else {
+            ((point - start).dot(along) / along.length_squared()).clamp(0.0, 1.0)
+        };
+        let offset = point - (start + along * t);
+        let distance = offset.length();
+        if distance < nearest.0 {
+            // On the edge itself, out is away from the side the inside is on.
+            let side = Vec2::new(along.y, -along.x);
+            nearest = (distance, offset.try_normalize().unwrap_or(side));
+        }
+    }
+    let (distance, normal) = nearest;
+    if distance == 0.0 {
+        return (0.0, normal.normalize_or_zero() * area.signum());
+    }
+    if inside {
+        (-distance, -normal)
+    } else {
+        (distance, normal)
+    }
+}
+
+/// Blows the fluid along `direction` from `position` all the time. The length
+/// of `direction` is how strong, as a fraction of a paddle's emit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Fan {
+    pub position: Vec2,
+    pub direction: Vec2,
+}
+
+/// Blows like a fan, for `duration` seconds at the start of every `period`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Vent {
+    pub position: Vec2,
+    pub direction: Vec2,
+    pub period: f32,
+    pub duration: f32,
+}
+
+impl Vent {
+    /// Whether the vent is blowing `time` seconds into the game.
+    pub fn is_blowing(&self, time: f32) -> bool {
+        self.period > 0.0 && time.rem_euclid(self.period) < self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obstacles_push_points_out_of_the_nearest_side() {
+        let rect = Obstacle::Rect { center: Vec2::ZERO, size: Vec2::new(40.0, 20.0) };
+        assert_eq!(rect.push_out(Vec2::new(5.0, 8.0), 1.0), Some((Vec2::new(5.0, 11.0), Vec2::Y)));
+        assert_eq!(rect.push_out(Vec2::new(30.0, 0.0), 1.0), None);
+
+        // A triangle either way round has the same inside.
+        let triangle = [Vec2::new(0.0, 0.0), Vec2::new(30.0, 0.0), Vec2::new(0.0, 30.0)];
+        let mut reversed = triangle;
+        reversed.reverse();
+        for points in [triangle, reversed] {
+            let polygon = Obstacle::Polygon { points: points.to_vec() };
+            assert!(polygon.contains(Vec2::new(5.0, 10.0)));
+            assert!(!polygon.contains(Vec2::new(20.0, 20.0)));
+            let (position, normal) = polygon.push_out(Vec2::new(2.0, 10.0), 1.0).unwrap();
+            assert_eq!((position, normal), (Vec2::new(-1.0, 10.0), Vec2::NEG_X));
+            let (_, normal) = polygon.distance(Vec2::new(15.0, 0.0));
+            assert_eq!(normal, Vec2::NEG_Y);
+        }
+    }
+
+    #[test]
+    fn narrow_goals_are_walled_in() {
+        let level = Level { goal_size: GAME_HEIGHT / 2.0, ..default() };
+        let walls = level.goal_walls();
+        assert_eq!(walls.len(), 4);
+        let end = GAME_WIDTH / 2.0 + 1.0;
+        assert!(walls.iter().any(|wall| wall.contains(Vec2::new(end, GAME_HEIGHT * 0.4))));
+        assert!(!walls.iter().any(|wall| wall.contains(Vec2::new(-end, GAME_HEIGHT * 0.2))));
+        assert!(Level::default().goal_walls().is_empty());
+    }
+}
@@ -1,17 +1,36 @@
+pub mod ability;
+pub mod ai;
+pub mod collision;
+pub mod controls;
+pub mod energy;
+pub mod level;
 pub mod pongfluid;
+pub mod powerup;
+pub mod state;
 
 use bevy::prelude::*;
-use bevy::sprite::MaterialMesh2dBundle;
-use pongfluid::PongFluid;
-
-use crate::{GAME_HEIGHT, GAME_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH};
-
-const BALL_INITIAL_SPEED: f32 = 3.;
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+use ability::Abilities;
+use ai::{AiController, AiPlayers, AiSet};
+use collision::PaddleBody;
+use controls::{Controls, ControlsSet, PaddleInput};
+use energy::Energy;
+use level::{Level, Obstacle};
+use pongfluid::PongFluidSet;
+use powerup::{ExtraBall, PowerUps};
+use state::{GameState, MatchRules, ServeCountdown};
+
+use crate::{GAME_HEIGHT, GAME_WIDTH, SCREEN_WIDTH};
+
+/// The speed of the first serve of a match, in pixels per second.
+const FIRST_SERVE_SPEED: f32 = 60.;
+/// The speed of the serves after a point, in pixels per second.
+const BALL_INITIAL_SPEED: f32 = 180.;
 const BALL_SIZE: f32 = 5.;
-const PADDLE_SPEED: f32 = 6.;
+/// In pixels per second.
+const PADDLE_SPEED: f32 = 360.;
 const PADDLE_WIDTH: f32 = 10.;
 const PADDLE_HEIGHT: f32 = 50.;
-const GUTTER_HEIGHT: f32 = (SCREEN_HEIGHT - GAME_HEIGHT) / 2.0;
 
 #[derive(Component)]
 struct Player1Score;
@@ -20,9 +39,9 @@ struct Player1Score;
 struct Player2Score;
 
 #[derive(Resource, Default)]
-struct Score {
-    player1: u32,
-    player2: u32,
+pub struct Score {
+    pub(crate) player1: u32,
+    pub(crate) player2: u32,
 }
 
 enum Scorer {
@@ -31,10 +50,12 @@ enum Scorer {
 }
 
 #[derive(Event)]
-struct Scored(Scorer);
+pub(crate) struct Scored(Scorer);
 
+/// A ball in play. The one served is always there, and multi-ball adds
+/// `ExtraBall`s until the end of the point.
 #[derive(Component)]
-struct Ball;
+pub(crate) struct Ball;
 
 #[derive(Bundle)]
 struct BallBundle {
@@ -42,28 +63,42 @@ struct BallBundle {
     shape: Shape,
     velocity: Velocity,
     position: Position,
+    previous_position: PreviousPosition,
 }
 
 impl BallBundle {
-    fn new(x: f32, y: f32) -> Self {
+    fn new(position: Vec2, velocity: Vec2) -> Self {
         Self {
             ball: Ball,
             shape: Shape(Vec2::splat(BALL_SIZE)),
-            velocity: Velocity(Vec2::new(x, y)),
-            position: Position(Vec2::new(0., 0.)),
+            velocity: Velocity(velocity),
+            position: Position(position),
+            previous_position: PreviousPosition(position),
         }
     }
 }
 
+/// The mesh and material balls are drawn with, when they are drawn.
+#[derive(Resource, Clone)]
+pub(crate) struct BallMesh {
+    mesh: Mesh2dHandle,
+    material: Handle<ColorMaterial>,
+}
+
 #[derive(Component)]
-struct Paddle;
+pub(crate) struct Paddle;
 
 #[derive(Bundle)]
 struct PaddleBundle {
     paddle: Paddle,
     shape: Shape,
     position: Position,
+    previous_position: PreviousPosition,
     velocity: Velocity,
+    energy: Energy,
+    abilities: Abilities,
+    power_ups: PowerUps,
+    input: PaddleInput,
 }
 
 impl PaddleBundle {
@@ -72,52 +107,144 @@ impl PaddleBundle {
             paddle: Paddle,
             shape: Shape(Vec2::new(PADDLE_WIDTH, PADDLE_HEIGHT)),
             position: Position(Vec2::new(x, y)),
+            previous_position: PreviousPosition(Vec2::new(x, y)),
             velocity: Velocity(Vec2::new(0., 0.)),
+            energy: Energy::default(),
+            abilities: Abilities::default(),
+            power_ups: PowerUps::default(),
+            input: PaddleInput::default(),
         }
     }
 }
 
 #[derive(Component)]
-struct Position(Vec2);
+pub(crate) struct Position(pub(crate) Vec2);
 
+/// The position before the last fixed step, which frames in between steps
+/// are drawn from.
 #[derive(Component)]
-struct Velocity(Vec2);
+pub(crate) struct PreviousPosition(pub(crate) Vec2);
 
+/// In pixels per second.
 #[derive(Component)]
-struct Shape(Vec2);
+pub(crate) struct Velocity(pub(crate) Vec2);
 
+/// The radius of the ball, or the size of a paddle.
 #[derive(Component)]
-struct Player1;
+pub(crate) struct Shape(pub(crate) Vec2);
 
 #[derive(Component)]
-struct Player2;
+pub(crate) struct Player1;
 
-pub struct PongPlugin;
+#[derive(Component)]
+pub(crate) struct Player2;
+
+/// The game. The ball and paddles move in fixed steps in `FixedUpdate`, and
+/// are drawn in between them. In headless mode only the game logic runs,
+/// without the meshes, gizmos, scoreboard and banner.
+pub struct PongPlugin {
+    pub headless: bool,
+    /// The paddles the computer plays.
+    pub ai: AiPlayers,
+    pub rules: MatchRules,
+    /// The state the game starts in.
+    pub start: GameState,
+    pub controls: Controls,
+    /// Whether the paddles are played over the network, which then sets
+    /// their input instead of the controls.
+    pub online: bool,
+    pub level: Level,
+}
 
 impl Plugin for PongPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Score>()
+            .init_resource::<ServeCountdown>()
+            .init_resource::<powerup::PickupSpawner>()
+            .insert_resource(self.ai)
+            .insert_resource(self.rules)
+            .insert_resource(self.controls.clone())
+            .insert_resource(self.level.clone())
+            .insert_state(self.start)
             .add_event::<Scored>()
+            .add_systems(Startup, (spawn_ball, spawn_paddles))
+            .add_systems(FixedFirst, remember_positions)
+            .configure_sets(
+                FixedUpdate,
+                (
+                    AiSet
+                        .after(handle_player_input)
+                        .before(move_paddles)
+                        .before(energy::update_energy)
+                        .run_if(GameState::in_match),
+                    PongFluidSet
+                        .after(move_paddles)
+                        .before(handle_collisions)
+                        .run_if(GameState::in_match),
+                ),
+            )
             .add_systems(
-                Startup,
-                (configure_gizmos, spawn_ball, spawn_paddles, spawn_scoreboard).chain(),
+                FixedUpdate,
+                (
+                    (
+                        handle_player_input.after(ControlsSet),
+                        move_paddles.after(handle_player_input),
+                        energy::update_energy.after(ControlsSet).before(PongFluidSet),
+                        ability::update_abilities.after(ControlsSet).before(PongFluidSet),
+                        powerup::update_power_ups.before(move_paddles),
+                    )
+                        .run_if(GameState::in_match),
+                    (
+                        detect_scoring.after(handle_collisions),
+                        reset_ball.after(detect_scoring),
+                        update_score.after(detect_scoring),
+                        handle_collisions.after(move_paddles),
+                        state::end_point.after(update_score),
+                        powerup::spawn_pickups,
+                        powerup::collect_pickups.after(handle_collisions).before(detect_scoring),
+                    )
+                        .run_if(in_state(GameState::Playing)),
+                    state::count_down.run_if(in_state(GameState::Serve)),
+                ),
             )
+            // Key presses are only seen in the frame they happen, which may
+            // have no fixed step.
             .add_systems(
                 Update,
                 (
-                    draw_gizmos,
-                    move_ball.before(move_paddles),
-                    handle_player_input,
-                    detect_scoring,
-                    reset_ball.after(detect_scoring),
-                    update_score.after(detect_scoring),
-                    update_scoreboard.after(update_score),
-                    move_paddles.after(handle_player_input),
-                    project_positions.after(move_ball),
-                    handle_collisions.after(move_ball),
-                    handle_player_input_fluid.after(move_ball),
+                    state::start_match
+                        .run_if(in_state(GameState::Title).or_else(in_state(GameState::GameOver))),
+                    state::toggle_pause,
                 ),
+            )
+            .add_systems(
+                OnEnter(GameState::Serve),
+                (state::reset_countdown, powerup::clear_arena),
             );
+        if !self.online {
+            app.add_systems(
+                FixedUpdate,
+                controls::read_controls.in_set(ControlsSet).run_if(GameState::in_match),
+            );
+        }
+        if !self.headless {
+            app.add_systems(Startup, (configure_gizmos, spawn_scoreboard, state::spawn_banner))
+                .add_systems(
+                    PostStartup,
+                    (energy::spawn_energy_meters, ability::spawn_ability_icons),
+                )
+                .add_systems(
+                    Update,
+                    (
+                        draw_gizmos,
+                        update_scoreboard,
+                        energy::update_energy_meters,
+                        ability::update_ability_icons,
+                        project_positions,
+                        state::update_banner,
+                    ),
+                );
+        }
     }
 }
 
@@ -150,10 +277,33 @@ fn draw_dotted_line(
     }
 }
 
-fn draw_gizmos(mut gizmos: Gizmos) {
+fn draw_gizmos(mut gizmos: Gizmos, level: Res<Level>, time: Res<Time<Fixed>>) {
     let segment_length = 5.0;
     let gap_length = 2.5;
 
+    for obstacle in level.obstacles.iter().chain(&level.goal_walls()) {
+        match obstacle {
+            Obstacle::Rect { center, size } => gizmos.rect_2d(*center, 0.0, *size, Color::GRAY),
+            Obstacle::Circle { center, radius } => {
+                gizmos.circle_2d(*center, *radius, Color::GRAY);
+            }
+            Obstacle::Polygon { points } => {
+                gizmos.linestrip_2d(points.iter().chain(points.first()).copied(), Color::GRAY);
+            }
+        }
+    }
+    for fan in &level.fans {
+        gizmos.arrow_2d(fan.position, fan.position + fan.direction * 20.0, Color::TURQUOISE);
+    }
+    for vent in &level.vents {
+        let color = if vent.is_blowing(time.elapsed_seconds()) {
+            Color::ORANGE
+        } else {
+            Color::DARK_GRAY
+        };
+        gizmos.arrow_2d(vent.position, vent.position + vent.direction * 20.0, color);
+    }
+
     draw_dotted_line(
         &mut gizmos,
         Vec2::new(0.0, GAME_HEIGHT / 2.0),
@@ -237,8 +387,10 @@ fn update_score(mut score: ResMut<Score>, mut events: EventReader<Scored>) {
     }
 }
 
-fn detect_scoring(mut ball: Query<&mut Position, With<Ball>>, mut events: EventWriter<Scored>) {
-    if let Ok(ball) = ball.get_single_mut() {
+/// Scores a point for every ball that leaves the arena. The point ends with
+/// the first, and the extra balls are cleared for the next serve.
+fn detect_scoring(ball: Query<&Position, With<Ball>>, mut events: EventWriter<Scored>) {
+    for ball in &ball {
         if ball.0.x > GAME_WIDTH / 2. {
             events.send(Scored(Scorer::Player2));
         } else if ball.0.x < -GAME_WIDTH / 2. {
@@ -247,12 +399,18 @@ fn detect_scoring(mut ball: Query<&mut Position, With<Ball>>, mut events: EventW
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn reset_ball(
-    mut ball: Query<(&mut Position, &mut Velocity), With<Ball>>,
+    mut ball: Query<
+        (&mut Position, &mut PreviousPosition, &mut Velocity),
+        (With<Ball>, Without<ExtraBall>),
+    >,
     mut events: EventReader<Scored>,
 ) {
     for event in events.read() {
-        if let Ok((mut position, mut velocity)) = ball.get_single_mut() {
+        if let Ok((mut position, mut previous_position, mut velocity)) = ball.get_single_mut() {
+            // Jumps back to the middle instead of being drawn sliding there.
+            previous_position.0 = Vec2::new(0., 0.);
             match event.0 {
                 Scorer::Player2 => {
                     position.0 = Vec2::new(0., 0.);
@@ -267,213 +425,161 @@ fn reset_ball(
     }
 }
 
-fn handle_player_input_fluid(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut sphfluid_query: Query<&mut crate::sph::fluid::Fluid>,
-    mut nsfluid_query: Query<&mut crate::ns::fluid::Fluid>,
-    mut paddle1: Query<&Position, With<Player1>>,
-    mut paddle2: Query<&Position, (With<Player2>, Without<Player1>)>,
-) {
-    if let Ok(mut fluid) = sphfluid_query.get_single_mut() {
-        if keyboard_input.pressed(KeyCode::ShiftLeft) {
-            if let Ok(position) = paddle1.get_single_mut() {
-                fluid.apply_emit_force(position.0, Vec2::new(1.0, 0.0))
-            }
-        }
-        if keyboard_input.pressed(KeyCode::ShiftRight) {
-            if let Ok(position) = paddle2.get_single_mut() {
-                fluid.apply_emit_force(position.0, Vec2::new(-1.0, 0.0))
-            }
-        }
-    }
-    if let Ok(mut fluid) = nsfluid_query.get_single_mut() {
-        if keyboard_input.pressed(KeyCode::ShiftLeft) {
-            if let Ok(position) = paddle1.get_single_mut() {
-                fluid.apply_emit_force(position.0, Vec2::new(1.0, 0.0))
-            }
-        }
-        if keyboard_input.pressed(KeyCode::ShiftRight) {
-            if let Ok(position) = paddle2.get_single_mut() {
-                fluid.apply_emit_force(position.0, Vec2::new(-1.0, 0.0))
-            }
-        }
-    }
-}
-
+#[allow(clippy::type_complexity)]
 fn handle_player_input(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut paddle1: Query<&mut Velocity, With<Player1>>,
-    mut paddle2: Query<&mut Velocity, (With<Player2>, Without<Player1>)>,
+    mut paddles: Query<(&mut Velocity, &PaddleInput), Without<AiController>>,
 ) {
-    if let Ok(mut velocity) = paddle1.get_single_mut() {
-        if keyboard_input.pressed(KeyCode::KeyW) {
-            velocity.0.y = 1.;
-        } else if keyboard_input.pressed(KeyCode::KeyS) {
-            velocity.0.y = -1.;
-        } else {
-            velocity.0.y = 0.;
-        }
-    }
-    if let Ok(mut velocity) = paddle2.get_single_mut() {
-        if keyboard_input.pressed(KeyCode::ArrowUp) {
-            velocity.0.y = 1.;
-        } else if keyboard_input.pressed(KeyCode::ArrowDown) {
-            velocity.0.y = -1.;
-        } else {
-            velocity.0.y = 0.;
-        }
+    for (mut velocity, input) in &mut paddles {
+        velocity.0.y = input.movement * PADDLE_SPEED;
     }
 }
 
-fn project_positions(mut ball: Query<(&mut Transform, &Position)>) {
-    for (mut transform, position) in &mut ball {
-        transform.translation = position.0.extend(0.);
+fn remember_positions(mut query: Query<(&Position, &mut PreviousPosition)>) {
+    for (position, mut previous_position) in &mut query {
+        previous_position.0 = position.0;
     }
 }
 
-fn move_ball(
-    mut ball: Query<(&mut Position, &mut Velocity), With<Ball>>,
-    mut sphfluid_query: Query<&mut crate::sph::fluid::Fluid>,
-    mut nsfluid_query: Query<&mut crate::ns::fluid::Fluid>,
+/// Draws the ball and paddles between their positions before and after the
+/// last fixed step, by how far the frame is into the next one.
+fn project_positions(
+    time: Res<Time<Fixed>>,
+    mut ball: Query<(&mut Transform, &Position, &PreviousPosition)>,
 ) {
-    if let Ok((mut position, mut velocity)) = ball.get_single_mut() {
-        let vel = velocity.0;
-        let pos = position.0;
-        if let Ok(mut fluid) = sphfluid_query.get_single_mut() {
-            velocity.0 += fluid.get_fluid_force_at(pos, vel);
-            fluid.apply_ball_force(pos, vel);
-        }
-        if let Ok(mut fluid) = nsfluid_query.get_single_mut() {
-            velocity.0 += fluid.get_fluid_force_at(pos, vel);
-            fluid.apply_ball_force(pos, vel);
-        }
+    let overstep = time.overstep_fraction();
+    for (mut transform, position, previous_position) in &mut ball {
+        transform.translation = previous_position.0.lerp(position.0, overstep).extend(0.);
     }
 }
 
 fn move_paddles(
-    mut paddle: Query<(&mut Position, &Velocity), With<Paddle>>,
-    mut sphfluid_query: Query<&mut crate::sph::fluid::Fluid>,
-    mut nsfluid_query: Query<&mut crate::ns::fluid::Fluid>,
+    time: Res<Time>,
+    mut paddle: Query<(&mut Position, &mut Velocity, &Shape), With<Paddle>>,
 ) {
-    for (mut position, velocity) in &mut paddle {
-        let vel = velocity.0 * PADDLE_SPEED;
-        let new_position = position.0 + vel;
-        if new_position.y.abs() < GAME_HEIGHT / 2. - PADDLE_HEIGHT / 2. {
+    for (mut position, mut velocity, shape) in &mut paddle {
+        let new_position = position.0 + velocity.0 * time.delta_seconds();
+        if new_position.y.abs() < GAME_HEIGHT / 2. - shape.0.y / 2. {
             position.0 = new_position;
-            if let Ok(mut fluid) = sphfluid_query.get_single_mut() {
-                fluid.apply_paddle_force(position.0, vel);
-            }
-            if let Ok(mut fluid) = nsfluid_query.get_single_mut() {
-                fluid.apply_paddle_force(position.0, vel);
-            }
+        } else {
+            // A paddle against the wall doesn't push the fluid.
+            velocity.0 = Vec2::ZERO;
         }
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn handle_collisions(
-    mut ball: Query<(&mut Velocity, &mut Position, &Shape), With<Ball>>,
-    paddles: Query<(&Position, &Shape), (With<Paddle>, Without<Ball>)>,
+    time: Res<Time>,
+    level: Res<Level>,
+    mut balls: Query<(&mut Velocity, &mut Position), With<Ball>>,
+    paddles: Query<(&Position, &Shape, &Velocity), (With<Paddle>, Without<Ball>)>,
 ) {
-    if let Ok((mut ball_velocity, mut ball_position, ball_shape)) = ball.get_single_mut() {
-        let bp = ball_position.0;
-        if bp.y > GAME_HEIGHT / 2.0 {
-            ball_velocity.0.y *= -1.0;
-            ball_position.0.y = GAME_HEIGHT / 2.0;
-        }
-        if bp.y < -GAME_HEIGHT / 2.0 {
-            ball_velocity.0.y *= -1.0;
-            ball_position.0.y = -GAME_HEIGHT / 2.0;
-        }
-
-        for (paddle_position, paddle_shape) in paddles.iter() {
-            let pp = paddle_position.0;
-            let ps = paddle_shape.0;
-            if pp.x < 0.0
-                && bp.x - BALL_SIZE < pp.x + ps.x / 2.0
-                && bp.x + BALL_SIZE > pp.x - ps.x
-                && bp.y < pp.y + ps.y / 2.0
-                && bp.y > pp.y - ps.y / 2.0
-            {
-                ball_velocity.0.x *= -1.0;
-                ball_position.0.x = pp.x + ps.x / 2.0 + BALL_SIZE;
-            }
-            if pp.x > 0.0
-                && bp.x + BALL_SIZE > pp.x - ps.x / 2.0
-                && bp.x - BALL_SIZE < pp.x + ps.x
-                && bp.y < pp.y + ps.y / 2.0
-                && bp.y > pp.y - ps.y / 2.0
-            {
-                ball_velocity.0.x *= -1.0;
-                ball_position.0.x = pp.x - ps.x / 2.0 - BALL_SIZE;
-            }
+    let paddles: Vec<PaddleBody> = paddles
+        .iter()
+        .map(|(position, shape, velocity)| PaddleBody {
+            position: position.0,
+            size: shape.0,
+            velocity: velocity.0,
+        })
+        .collect();
+    let obstacles = level.ball_obstacles();
+    for (mut ball_velocity, mut ball_position) in &mut balls {
+        (ball_position.0, ball_velocity.0) = collision::move_ball(
+            ball_position.0,
+            ball_velocity.0,
+            time.delta_seconds(),
+            &paddles,
+            &obstacles,
+        );
+    }
+    let mut pairs = balls.iter_combinations_mut();
+    while let Some([(mut a_velocity, a), (mut b_velocity, b)]) = pairs.fetch_next() {
+        if let Some(velocities) = collision::collide_balls(a.0, a_velocity.0, b.0, b_velocity.0)
+        {
+            (a_velocity.0, b_velocity.0) = velocities;
         }
-        ball_position.0 += ball_velocity.0;
     }
 }
 
 fn spawn_paddles(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    window: Query<&Window>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
+    ai: Res<AiPlayers>,
 ) {
-    if let Ok(window) = window.get_single() {
-        let window_width = window.resolution.width();
-        let padding = 50.;
-        let right_paddle_x = window_width / 2. - padding;
-        let left_paddle_x = -window_width / 2. + padding;
-
-        let mesh = Mesh::from(Rectangle::new(PADDLE_WIDTH, PADDLE_HEIGHT));
-
-        let mesh_handle = meshes.add(mesh);
-
-        commands.spawn((
-            Player1,
-            PaddleBundle::new(left_paddle_x, 0.),
-            MaterialMesh2dBundle {
-                mesh: mesh_handle.clone().into(),
-                material: materials.add(ColorMaterial::from(Color::WHITE)),
-                transform: Transform {
-                    translation: Vec3::new(0.0, 0.0, 2.0), // z index?
-                    ..default()
-                },
-                ..default()
-            },
-        ));
-
-        commands.spawn((
-            Player2,
-            PaddleBundle::new(right_paddle_x, 0.),
-            MaterialMesh2dBundle {
-                mesh: mesh_handle.clone().into(),
-                material: materials.add(ColorMaterial::from(Color::WHITE)),
-                ..default()
-            },
-        ));
+    let padding = 50.;
+    let right_paddle_x = GAME_WIDTH / 2. - padding;
+    let left_paddle_x = -GAME_WIDTH / 2. + padding;
+
+    let player1 = commands.spawn((Player1, PaddleBundle::new(left_paddle_x, 0.))).id();
+    let player2 = commands.spawn((Player2, PaddleBundle::new(right_paddle_x, 0.))).id();
+    if let Some(difficulty) = ai.player1 {
+        commands.entity(player1).insert(AiController::new(difficulty));
     }
+    if let Some(difficulty) = ai.player2 {
+        commands.entity(player2).insert(AiController::new(difficulty));
+    }
+
+    let (Some(mut meshes), Some(mut materials)) = (meshes, materials) else {
+        return;
+    };
+    let mesh = Mesh::from(Rectangle::new(PADDLE_WIDTH, PADDLE_HEIGHT));
+
+    let mesh_handle = meshes.add(mesh);
+
+    commands.entity(player1).insert(MaterialMesh2dBundle {
+        mesh: mesh_handle.clone().into(),
+        material: materials.add(ColorMaterial::from(Color::WHITE)),
+        transform: Transform {
+            translation: Vec3::new(0.0, 0.0, 2.0), // z index?
+            ..default()
+        },
+        ..default()
+    });
+
+    commands.entity(player2).insert(MaterialMesh2dBundle {
+        mesh: mesh_handle.clone().into(),
+        material: materials.add(ColorMaterial::from(Color::WHITE)),
+        ..default()
+    });
 }
 
 fn spawn_ball(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
 ) {
-    let shape = Mesh::from(Circle::new(BALL_SIZE));
-    let color = ColorMaterial::from(Color::WHITE);
-
-    let mesh_handle = meshes.add(shape);
-    let material_handle = materials.add(color);
+    let mesh = if let (Some(mut meshes), Some(mut materials)) = (meshes, materials) {
+        let mesh = BallMesh {
+            mesh: meshes.add(Mesh::from(Circle::new(BALL_SIZE))).into(),
+            material: materials.add(ColorMaterial::from(Color::WHITE)),
+        };
+        commands.insert_resource(mesh.clone());
+        Some(mesh)
+    } else {
+        None
+    };
+    add_ball(&mut commands, Vec2::ZERO, Vec2::splat(FIRST_SERVE_SPEED), mesh.as_ref());
+}
 
-    commands.spawn((
-        BallBundle::new(1., 1.),
-        MaterialMesh2dBundle {
-            mesh: mesh_handle.into(),
-            material: material_handle,
+/// Spawns a ball, drawn with `mesh` if there is one.
+pub(crate) fn add_ball(
+    commands: &mut Commands,
+    position: Vec2,
+    velocity: Vec2,
+    mesh: Option<&BallMesh>,
+) -> Entity {
+    let mut ball = commands.spawn(BallBundle::new(position, velocity));
+    if let Some(mesh) = mesh {
+        ball.insert(MaterialMesh2dBundle {
+            mesh: mesh.mesh.clone(),
+            material: mesh.material.clone(),
             transform: Transform {
-                translation: Vec3::new(0.0, 0.0, 1.0), // z index?
+                translation: position.extend(1.0), // z index?
                 ..default()
             },
             ..default()
-        },
-    ));
+        });
+    }
+    ball.id()
 }
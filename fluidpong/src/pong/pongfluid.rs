@@ -1,8 +1,227 @@
+use std::f32::consts::TAU;
+use std::marker::PhantomData;
+
 use bevy::prelude::*;
 
+use super::ability::use_abilities;
+use super::ai::{drive_ai_paddles, AiSet};
+use super::energy::Energy;
+use super::level::{Level, Obstacle};
+use super::powerup::PowerUps;
+use super::state::GameState;
+use super::{Ball, Paddle, Player1, Position, Velocity};
+use crate::GAME_HEIGHT;
+
+/// The abilities act on the fluid at points around or along where they are,
+/// this far apart.
+const RING_RADIUS: f32 = 30.0;
+const RING_POINTS: usize = 8;
+const BAND_SPACING: f32 = 20.0;
+/// How far a wall reaches to either side of its middle.
+const WALL_HALF_LENGTH: f32 = 60.0;
+
+/// How the ball and paddles push a fluid and are pushed by it, once per fixed
+/// step. Velocities are in pixels per step, and `get_fluid_force_at` returns
+/// the change in the ball's velocity over the step. The velocity of an emit
+/// points away from the paddle, and its length is the strength: 1 for a step
+/// of emitting, more for a blast.
+///
+/// The abilities have the same strengths. By default they are made of emits,
+/// which fluids override where they have a better way to do them. The default
+/// wall drives the fluid together from both sides, which only packs it denser
+/// in a fluid that can be compressed.
+///
+/// `apply_obstacles` is called every step, in and out of a match, with the
+/// level's obstacles, which the fluid treats as solid until the next call.
+///
+/// `apply_paddle_force` is called every step for every paddle, moving or not,
+/// because the fluids that treat the paddles as solids need to know where they
+/// are. Fluids that only push with them skip a paddle at rest.
 pub trait PongFluid {
     fn apply_emit_force(&mut self, position: Vec2, velocity: Vec2);
     fn apply_paddle_force(&mut self, position: Vec2, velocity: Vec2);
     fn apply_ball_force(&mut self, position: Vec2, velocity: Vec2);
+    fn apply_obstacles(&mut self, obstacles: &[Obstacle]);
     fn get_fluid_force_at(&self, position: Vec2, velocity: Vec2) -> Vec2;
-}
\ No newline at end of file
+
+    /// Spins the fluid around `position`, counterclockwise for a positive
+    /// `strength`.
+    fn apply_vortex(&mut self, position: Vec2, strength: f32) {
+        for i in 0..RING_POINTS {
+            let offset = Vec2::from_angle(TAU * i as f32 / RING_POINTS as f32) * RING_RADIUS;
+            let push = offset.perp().normalize() * strength / RING_POINTS as f32;
+            self.apply_emit_force(position + offset, push);
+        }
+    }
+    /// Draws the fluid in toward `position` for a step.
+    fn apply_suction(&mut self, position: Vec2, strength: f32) {
+        for i in 0..RING_POINTS {
+            let inward = -Vec2::from_angle(TAU * i as f32 / RING_POINTS as f32);
+            let push = inward * strength / RING_POINTS as f32;
+            self.apply_emit_force(position - inward * RING_RADIUS, push);
+        }
+    }
+    /// Packs the fluid into a band through `position`, across `direction`,
+    /// which points away from the paddle.
+    fn apply_wall(&mut self, position: Vec2, direction: Vec2) {
+        let behind = direction.normalize_or_zero() * BAND_SPACING;
+        for point in wall_points(position, direction) {
+            self.apply_emit_force(point - behind, direction);
+            self.apply_emit_force(point + behind, -direction);
+        }
+    }
+    /// Pushes the fluid along `direction` from a front through `position`,
+    /// from wall to wall.
+    fn apply_wave(&mut self, position: Vec2, direction: Vec2) {
+        for point in wave_points(position.x) {
+            self.apply_emit_force(point, direction);
+        }
+    }
+}
+
+/// Points along a wall through `position`, across `direction`.
+pub fn wall_points(position: Vec2, direction: Vec2) -> impl Iterator<Item = Vec2> {
+    let along = direction.perp().normalize_or_zero();
+    let points = (WALL_HALF_LENGTH / BAND_SPACING) as i32;
+    (-points..=points).map(move |i| position + along * i as f32 * BAND_SPACING)
+}
+
+/// Whether `point` is in the band a wall through `position`, across
+/// `direction`, packs the fluid into.
+pub fn is_in_wall(point: Vec2, position: Vec2, direction: Vec2) -> bool {
+    let (across, along) = wall_coordinates(point, position, direction);
+    across.abs() <= BAND_SPACING / 2.0 && along.abs() <= WALL_HALF_LENGTH
+}
+
+/// Where a wall through `position`, across `direction`, moves fluid at
+/// `point`. Fluid within a band spacing of the wall is drawn toward it, into
+/// a band thinner by the wall's strength plus one.
+pub fn pack_into_wall(point: Vec2, position: Vec2, direction: Vec2) -> Vec2 {
+    let (across, along) = wall_coordinates(point, position, direction);
+    if across.abs() > BAND_SPACING || along.abs() > WALL_HALF_LENGTH {
+        return point;
+    }
+    let packed = across / (1.0 + direction.length());
+    point + direction.normalize_or_zero() * (packed - across)
+}
+
+/// How far `point` is in front of a wall through `position`, across
+/// `direction`, and how far along it.
+fn wall_coordinates(point: Vec2, position: Vec2, direction: Vec2) -> (f32, f32) {
+    let normal = direction.normalize_or_zero();
+    let offset = point - position;
+    (offset.dot(normal), offset.dot(normal.perp()))
+}
+
+/// Points along a wave front at `x`, from wall to wall.
+pub fn wave_points(x: f32) -> impl Iterator<Item = Vec2> {
+    let points = (GAME_HEIGHT / 2.0 / BAND_SPACING) as i32;
+    (-points..=points).map(move |i| Vec2::new(x, i as f32 * BAND_SPACING))
+}
+
+/// The systems coupling the ball and paddles to the fluid. They run after the
+/// paddles move and before the ball does.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PongFluidSet;
+
+/// Couples the ball and paddles to fluids of type `F`.
+pub struct PongFluidPlugin<F>(PhantomData<F>);
+
+impl<F> Default for PongFluidPlugin<F> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<F: PongFluid + Component> Plugin for PongFluidPlugin<F> {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                // The ball waits for the serve, whatever the fluid does.
+                push_fluid_with_ball::<F>.run_if(in_state(GameState::Playing)),
+                push_fluid_with_paddles::<F>,
+                emit_from_paddles::<F>.after(push_fluid_with_ball::<F>),
+                use_abilities::<F>.after(emit_from_paddles::<F>),
+                blow_fans::<F>,
+            )
+                .in_set(PongFluidSet),
+        )
+        // Before the set rather than in it, so that the obstacles are there
+        // between matches too.
+        .add_systems(FixedUpdate, apply_obstacles::<F>.before(PongFluidSet))
+        .add_systems(FixedUpdate, drive_ai_paddles::<F>.in_set(AiSet));
+    }
+}
+
+fn push_fluid_with_ball<F: PongFluid + Component>(
+    time: Res<Time>,
+    mut ball: Query<(&Position, &mut Velocity), With<Ball>>,
+    mut fluid_query: Query<&mut F>,
+) {
+    let dt = time.delta_seconds();
+    if let Ok(mut fluid) = fluid_query.get_single_mut() {
+        for (position, mut velocity) in &mut ball {
+            let vel = velocity.0 * dt;
+            let pos = position.0;
+            velocity.0 += fluid.get_fluid_force_at(pos, vel) / dt;
+            fluid.apply_ball_force(pos, vel);
+        }
+    }
+}
+
+fn apply_obstacles<F: PongFluid + Component>(level: Res<Level>, mut fluid_query: Query<&mut F>) {
+    if let Ok(mut fluid) = fluid_query.get_single_mut() {
+        fluid.apply_obstacles(&level.obstacles);
+    }
+}
+
+/// Blows the fluid with the level's fans, and with its vents while they are
+/// on.
+fn blow_fans<F: PongFluid + Component>(
+    time: Res<Time>,
+    level: Res<Level>,
+    mut fluid_query: Query<&mut F>,
+) {
+    let Ok(mut fluid) = fluid_query.get_single_mut() else {
+        return;
+    };
+    for fan in &level.fans {
+        fluid.apply_emit_force(fan.position, fan.direction);
+    }
+    for vent in &level.vents {
+        if vent.is_blowing(time.elapsed_seconds()) {
+            fluid.apply_emit_force(vent.position, vent.direction);
+        }
+    }
+}
+
+fn push_fluid_with_paddles<F: PongFluid + Component>(
+    time: Res<Time>,
+    paddle: Query<(&Position, &Velocity), With<Paddle>>,
+    mut fluid_query: Query<&mut F>,
+) {
+    if let Ok(mut fluid) = fluid_query.get_single_mut() {
+        for (position, velocity) in &paddle {
+            fluid.apply_paddle_force(position.0, velocity.0 * time.delta_seconds());
+        }
+    }
+}
+
+fn emit_from_paddles<F: PongFluid + Component>(
+    mut fluid_query: Query<&mut F>,
+    paddles: Query<(&Position, &Energy, &PowerUps, Has<Player1>)>,
+) {
+    if let Ok(mut fluid) = fluid_query.get_single_mut() {
+        for (position, energy, power_ups, is_player1) in &paddles {
+            let side = if is_player1 { Vec2::X } else { Vec2::NEG_X };
+            let direction = side * power_ups.emit_strength();
+            if energy.emit {
+                fluid.apply_emit_force(position.0, direction);
+            }
+            if energy.blast > 0.0 {
+                fluid.apply_emit_force(position.0, direction * energy.blast);
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use bevy::prelude::*;
+use bevy::sprite::MaterialMesh2dBundle;
+
+use super::energy::Energy;
+use super::level::Level;
+use super::{
+    add_ball, Ball, BallMesh, Paddle, Player1, Position, PreviousPosition, Shape, Velocity,
+    BALL_SIZE, PADDLE_HEIGHT,
+};
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+/// Seconds of play between pickups appearing.
+const SPAWN_EVERY: f32 = 6.0;
+/// The most pickups in the arena at once.
+const MAX_PICKUPS: usize = 2;
+const PICKUP_RADIUS: f32 = 12.0;
+/// How far from the middle pickups appear, in x, so that they are in the
+/// ball's way rather than by a paddle.
+const SPAWN_HALF_WIDTH: f32 = GAME_WIDTH / 4.0;
+/// The most balls in play at once.
+const MAX_BALLS: usize = 4;
+/// How far the extra ball of a multi-ball turns away from the ball that
+/// collected it.
+const SPLIT_ANGLE: f32 = std::f32::consts::FRAC_PI_6;
+const BIG_PADDLE_SECONDS: f32 = 10.0;
+const BIG_PADDLE_SCALE: f32 = 1.6;
+const FLUID_BOOST_SECONDS: f32 = 8.0;
+/// How much stronger a boosted paddle emits.
+const FLUID_BOOST: f32 = 2.0;
+/// What the balls' speed is multiplied by when a slow-down is collected.
+const SLOW_FACTOR: f32 = 0.5;
+
+/// Something a ball collects by passing through it, for the paddle that sent
+/// the ball, which is the one it is moving away from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerUp {
+    /// Splits the ball in two.
+    MultiBall,
+    /// Makes the paddle taller for a while.
+    BigPaddle,
+    /// Refills the paddle's energy, and doubles the strength of its emits for
+    /// a while.
+    FluidBoost,
+    /// Halves the speed of every ball.
+    SlowBall,
+}
+
+impl PowerUp {
+    pub const ALL: [PowerUp; 4] =
+        [PowerUp::MultiBall, PowerUp::BigPaddle, PowerUp::FluidBoost, PowerUp::SlowBall];
+
+    fn color(self) -> Color {
+        match self {
+            PowerUp::MultiBall => Color::YELLOW,
+            PowerUp::BigPaddle => Color::GREEN,
+            PowerUp::FluidBoost => Color::TURQUOISE,
+            PowerUp::SlowBall => Color::PURPLE,
+        }
+    }
+}
+
+/// A power-up waiting in the arena.
+#[derive(Component)]
+pub(crate) struct Pickup(PowerUp);
+
+/// A ball added by a multi-ball, removed at the end of the point.
+#[derive(Component)]
+pub(crate) struct ExtraBall;
+
+/// The power-ups acting on a paddle, as seconds left of each.
+#[derive(Component, Debug, Default, Clone, PartialEq)]
+pub struct PowerUps {
+    pub big_paddle: f32,
+    pub fluid_boost: f32,
+}
+
+impl PowerUps {
+    /// What the paddle's emits and blasts are multiplied by.
+    pub fn emit_strength(&self) -> f32 {
+        if self.fluid_boost > 0.0 {
+            FLUID_BOOST
+        } else {
+            1.0
+        }
+    }
+}
+
+/// When the next pickup appears, and where. Pickups are placed by a hash of
+/// how many came before, so that replays and network games see the same ones.
+#[derive(Resource, Default)]
+pub(crate) struct PickupSpawner {
+    next_in: f32,
+    spawned: u32,
+}
+
+/// Mixes the bits of `n`, for numbers that look random but are the same on
+/// every run.
+fn scramble(mut n: u32) -> u32 {
+    n = (n ^ (n >> 16)).wrapping_mul(0x7feb_352d);
+    n = (n ^ (n >> 15)).wrapping_mul(0x846c_a68b);
+    n ^ (n >> 16)
+}
+
+/// Maps `n` to a number from -1 to 1.
+fn unit(n: u32) -> f32 {
+    n as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+pub(crate) fn spawn_pickups(
+    mut commands: Commands,
+    time: Res<Time>,
+    level: Res<Level>,
+    mut spawner: ResMut<PickupSpawner>,
+    pickups: Query<(), With<Pickup>>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<ColorMaterial>>>,
+) {
+    spawner.next_in -= time.delta_seconds();
+    if spawner.next_in > 0.0 {
+        return;
+    }
+    spawner.next_in = SPAWN_EVERY;
+    if pickups.iter().count() >= MAX_PICKUPS {
+        return;
+    }
+
+    let seed = scramble(spawner.spawned);
+    spawner.spawned += 1;
+    let power_up = PowerUp::ALL[seed as usize % PowerUp::ALL.len()];
+    let position = Vec2::new(
+        unit(scramble(seed ^ 1)) * SPAWN_HALF_WIDTH,
+        unit(scramble(seed ^ 2)) * (GAME_HEIGHT / 2.0 - 2.0 * PICKUP_RADIUS),
+    );
+    // One the ball can't reach is skipped, for the next to land elsewhere.
+    let reach = PICKUP_RADIUS + BALL_SIZE;
+    if level.obstacles.iter().any(|obstacle| obstacle.distance(position).0 < reach) {
+        return;
+    }
+    let mut pickup = commands.spawn((Pickup(power_up), Position(position)));
+    if let (Some(mut meshes), Some(mut materials)) = (meshes, materials) {
+        pickup.insert(MaterialMesh2dBundle {
+            mesh: meshes.add(Mesh::from(Circle::new(PICKUP_RADIUS))).into(),
+            material: materials.add(ColorMaterial::from(power_up.color().with_a(0.7))),
+            transform: Transform::from_translation(position.extend(0.5)),
+            ..default()
+        });
+    }
+}
+
+/// The distance from `point` to the segment from `start` to `end`.
+fn distance_to_segment(point: Vec2, start: Vec2, end: Vec2) -> f32 {
+    let along = end - start;
+    let t = if along == Vec2::ZERO {
+        0.0
+    } else {
+        ((point - start).dot(along) / along.length_squared()).clamp(0.0, 1.0)
+    };
+    point.distance(start + along * t)
+}
+
+/// Gives the pickups the balls passed through this step to the paddles that
+/// sent them.
+pub(crate) fn collect_pickups(
+    mut commands: Commands,
+    ball_mesh: Option<Res<BallMesh>>,
+    pickups: Query<(Entity, &Pickup, &Position)>,
+    mut balls: Query<(&Position, &PreviousPosition, &mut Velocity), With<Ball>>,
+    mut paddles: Query<(&mut PowerUps, &mut Energy, Has<Player1>), With<Paddle>>,
+) {
+    let mut collected: Vec<(Entity, PowerUp, Vec2, Vec2)> = Vec::new();
+    for (ball, previous, velocity) in &balls {
+        for (entity, pickup, position) in &pickups {
+            let reach = PICKUP_RADIUS + BALL_SIZE;
+            if !collected.iter().any(|(collected, ..)| *collected == entity)
+                && distance_to_segment(position.0, previous.0, ball.0) <= reach
+            {
+                collected.push((entity, pickup.0, ball.0, velocity.0));
+            }
+        }
+    }
+
+    let mut ball_count = balls.iter().count();
+    for (entity, power_up, position, velocity) in collected {
+        commands.entity(entity).despawn();
+        // A ball moving right was sent by the left paddle.
+        let by_player1 = velocity.x > 0.0;
+        let paddle = paddles.iter_mut().find(|(.., is_player1)| *is_player1 == by_player1);
+        let Some((mut power_ups, mut energy, _)) = paddle else {
+            continue;
+        };
+        match power_up {
+            PowerUp::MultiBall if ball_count < MAX_BALLS => {
+                let velocity = Vec2::from_angle(SPLIT_ANGLE).rotate(velocity);
+                let ball = add_ball(&mut commands, position, velocity, ball_mesh.as_deref());
+                commands.entity(ball).insert(ExtraBall);
+                ball_count += 1;
+            }
+            PowerUp::MultiBall => {}
+            PowerUp::BigPaddle => power_ups.big_paddle = BIG_PADDLE_SECONDS,
+            PowerUp::FluidBoost => {
+                power_ups.fluid_boost = FLUID_BOOST_SECONDS;
+                energy.level = 1.0;
+            }
+            PowerUp::SlowBall => {
+                for (.., mut velocity) in &mut balls {
+                    velocity.0 *= SLOW_FACTOR;
+                }
+            }
+        }
+    }
+}
+
+/// Counts down the paddles' power-ups, and sizes them to match.
+pub(crate) fn update_power_ups(
+    time: Res<Time>,
+    mut paddles: Query<(&mut PowerUps, &mut Shape, &mut Position, Option<&mut Transform>)>,
+) {
+    let dt = time.delta_seconds();
+    for (mut power_ups, mut shape, mut position, transform) in &mut paddles {
+        power_ups.big_paddle = (power_ups.big_paddle - dt).max(0.0);
+        power_ups.fluid_boost = (power_ups.fluid_boost - dt).max(0.0);
+        let scale = if power_ups.big_paddle > 0.0 { BIG_PADDLE_SCALE } else { 1.0 };
+        shape.0.y = PADDLE_HEIGHT * scale;
+        // A paddle that grew against a wall is pushed back inside.
+        let reach = GAME_HEIGHT / 2.0 - shape.0.y / 2.0;
+        position.0.y = position.0.y.clamp(-reach, reach);
+        if let Some(mut transform) = transform {
+            transform.scale.y = scale;
+        }
+    }
+}
+
+/// Clears the extra balls and the pickups for a serve.
+#[allow(clippy::type_complexity)]
+pub(crate) fn clear_arena(
+    mut commands: Commands,
+    mut spawner: ResMut<PickupSpawner>,
+    leftovers: Query<Entity, Or<(With<ExtraBall>, With<Pickup>)>>,
+) {
+    for entity in &leftovers {
+        commands.entity(entity).despawn();
+    }
+    spawner.next_in = SPAWN_EVERY;
+}
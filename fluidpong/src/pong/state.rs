@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::ability::Abilities;
+use super::energy::Energy;
+use super::powerup::{ExtraBall, PowerUps};
+use super::{Ball, Position, PreviousPosition, Score, Scored, Velocity, FIRST_SERVE_SPEED};
+
+/// How many seconds the countdown before a serve lasts.
+pub const SERVE_SECONDS: f32 = 3.0;
+
+const PAUSE_KEY: KeyCode = KeyCode::KeyP;
+const START_KEY: KeyCode = KeyCode::Space;
+
+/// Where the match is. The ball only moves while playing, and the fluid stops
+/// while paused.
+#[derive(States, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum GameState {
+    #[default]
+    Title,
+    /// Counting down to a serve. The paddles can move, but the ball waits.
+    Serve,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+impl GameState {
+    /// Whether the paddles are in play.
+    pub fn in_match(state: Res<State<GameState>>) -> bool {
+        matches!(state.get(), GameState::Serve | GameState::Playing)
+    }
+}
+
+/// When a match ends.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct MatchRules {
+    pub win_score: u32,
+    /// Whether the winner needs a lead of two points.
+    pub win_by_two: bool,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self { win_score: 11, win_by_two: false }
+    }
+}
+
+impl MatchRules {
+    pub fn is_over(&self, score: &Score) -> bool {
+        let (high, low) = if score.player1 > score.player2 {
+            (score.player1, score.player2)
+        } else {
+            (score.player2, score.player1)
+        };
+        high >= self.win_score && (!self.win_by_two || high - low >= 2)
+    }
+}
+
+/// Seconds left until the ball is served.
+#[derive(Resource, Default)]
+pub(crate) struct ServeCountdown(pub(crate) f32);
+
+/// The state to go back to after a pause.
+#[derive(Resource)]
+pub(crate) struct Resume(GameState);
+
+/// The text over the arena, saying what to press or who won.
+#[derive(Component)]
+pub(crate) struct Banner;
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn start_match(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut score: ResMut<Score>,
+    mut ball: Query<
+        (&mut Position, &mut PreviousPosition, &mut Velocity),
+        (With<Ball>, Without<ExtraBall>),
+    >,
+    mut paddles: Query<(&mut Energy, &mut Abilities, &mut PowerUps)>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !keyboard_input.just_pressed(START_KEY) {
+        return;
+    }
+    *score = Score::default();
+    for (mut energy, mut abilities, mut power_ups) in &mut paddles {
+        *energy = Energy::default();
+        *abilities = Abilities::default();
+        *power_ups = PowerUps::default();
+    }
+    if let Ok((mut position, mut previous_position, mut velocity)) = ball.get_single_mut() {
+        position.0 = Vec2::ZERO;
+        previous_position.0 = Vec2::ZERO;
+        velocity.0 = Vec2::splat(FIRST_SERVE_SPEED);
+    }
+    next_state.set(GameState::Serve);
+}
+
+pub(crate) fn reset_countdown(mut countdown: ResMut<ServeCountdown>) {
+    countdown.0 = SERVE_SECONDS;
+}
+
+pub(crate) fn count_down(
+    time: Res<Time>,
+    mut countdown: ResMut<ServeCountdown>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    countdown.0 -= time.delta_seconds();
+    if countdown.0 <= 0.0 {
+        next_state.set(GameState::Playing);
+    }
+}
+
+pub(crate) fn toggle_pause(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    state: Res<State<GameState>>,
+    resume: Option<Res<Resume>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !keyboard_input.just_pressed(PAUSE_KEY) {
+        return;
+    }
+    match state.get() {
+        GameState::Serve | GameState::Playing => {
+            commands.insert_resource(Resume(*state.get()));
+            next_state.set(GameState::Paused);
+        }
+        GameState::Paused => {
+            next_state.set(resume.map_or(GameState::Playing, |resume| resume.0));
+        }
+        GameState::Title | GameState::GameOver => {}
+    }
+}
+
+/// Serves again after a point, unless the point won the match.
+pub(crate) fn end_point(
+    score: Res<Score>,
+    rules: Res<MatchRules>,
+    mut events: EventReader<Scored>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+    if rules.is_over(&score) {
+        next_state.set(GameState::GameOver);
+    } else {
+        next_state.set(GameState::Serve);
+    }
+}
+
+pub(crate) fn spawn_banner(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle { font_size: 36.0, color: Color::WHITE, ..default() },
+                )
+                .with_text_justify(JustifyText::Center),
+                Banner,
+            ));
+        });
+}
+
+pub(crate) fn update_banner(
+    state: Res<State<GameState>>,
+    countdown: Res<ServeCountdown>,
+    score: Res<Score>,
+    mut banner: Query<&mut Text, With<Banner>>,
+) {
+    let Ok(mut text) = banner.get_single_mut() else {
+        return;
+    };
+    let message = match state.get() {
+        GameState::Title => "FLUIDPONG\nPress Space to start".to_string(),
+        GameState::Serve => countdown.0.max(0.0).ceil().to_string(),
+        GameState::Playing => String::new(),
+        GameState::Paused => "Paused\nPress P to resume".to_string(),
+        GameState::GameOver => {
+            // The scoreboard shows `player1` on the right.
+            let winner = if score.player1 > score.player2 { "Right" } else { "Left" };
+            format!("{winner} player wins\nPress Space to play again")
+        }
+    };
+    if text.sections[0].value != message {
+        text.sections[0].value = message;
+    }
+}
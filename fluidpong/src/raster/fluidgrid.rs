@@ -0,0 +1,47 @@
+//! `fluidgrid.wgsl`: the bilinearly filtered grid, with density as opacity and
+//! speed as hue.
+
+use bevy::math::{Vec2, Vec3, Vec4};
+
+use super::{hsv_to_rgb, mix, Frame, SoftwareMaterial};
+use crate::ns::FluidGridMaterial;
+
+const HUE_MIN: f32 = 0.67;
+const HUE_MAX: f32 = 0.50;
+const MAX_VEL_MAGNITUDE: f32 = 10000.0;
+const MAX_OPACITY: f32 = 0.7;
+
+fn bilinear(v0: f32, v1: f32, v2: f32, v3: f32, frac_x: f32, frac_y: f32) -> f32 {
+    mix(mix(v0, v1, frac_x), mix(v2, v3, frac_x), frac_y)
+}
+
+fn mag(cell: Vec4) -> f32 {
+    Vec2::new(cell.x, cell.y).length()
+}
+
+impl SoftwareMaterial for FluidGridMaterial {
+    fn draw(&self, frame: &mut Frame) {
+        let (screen_size, grid_size) = (self.screen_size, self.grid_size);
+        let (gx, gy) = (grid_size.x as u32, grid_size.y as u32);
+        // Out of range reads are clamped to the last cell, as on the GPU.
+        let cell = |i: u32| self.cells[(i as usize).min(self.cells.len() - 1)];
+        frame.shade(|pos| {
+            let cell_x = (pos.x + screen_size.x / 2.0) / screen_size.x * grid_size.x;
+            let cell_y = (pos.y + screen_size.y / 2.0) / screen_size.y * grid_size.y;
+
+            let (ix, iy) = (cell_x as u32, cell_y as u32);
+            let (frac_x, frac_y) = (cell_x.fract(), cell_y.fract());
+
+            let c0 = cell(iy * gx + ix);
+            let c1 = cell(iy * gx + (ix + 1).min(gx - 1));
+            let c2 = cell((iy + 1).min(gy - 1) * gx + ix);
+            let c3 = cell((iy + 1).min(gy - 1) * gx + (ix + 1).min(gx - 1));
+
+            let d = bilinear(c0.z, c1.z, c2.z, c3.z, frac_x, frac_y).clamp(0.0, MAX_OPACITY);
+            let m = bilinear(mag(c0), mag(c1), mag(c2), mag(c3), frac_x, frac_y);
+
+            let hue = (HUE_MIN - (m / MAX_VEL_MAGNITUDE) * HUE_MAX).clamp(HUE_MAX, HUE_MIN);
+            hsv_to_rgb(Vec3::new(hue, 1.0, 1.0)).extend(d)
+        });
+    }
+}
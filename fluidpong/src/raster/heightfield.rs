@@ -0,0 +1,63 @@
+//! `heightfield.wgsl`: the water surface, lit through normals from the height
+//! gradient, deeper where it is lower and foaming where it is fast.
+
+use bevy::math::{Vec2, Vec3};
+
+use super::{mix, Frame, SoftwareMaterial};
+use crate::swe::HeightFieldMaterial;
+
+const DEEP_COLOR: Vec3 = Vec3::new(0.02, 0.12, 0.35);
+const SHALLOW_COLOR: Vec3 = Vec3::new(0.10, 0.45, 0.70);
+const FOAM_COLOR: Vec3 = Vec3::new(0.85, 0.92, 1.0);
+const LIGHT_DIR: Vec3 = Vec3::new(-0.4, 0.5, 0.77);
+const NORMAL_STRENGTH: f32 = 4.0;
+const MAX_HEIGHT: f32 = 5.0;
+const MAX_SPEED: f32 = 200.0;
+const OPACITY: f32 = 0.8;
+
+impl HeightFieldMaterial {
+    fn height_at(&self, cell: Vec2) -> f32 {
+        let grid_size = self.grid_size;
+        let c = (cell - Vec2::splat(0.5)).clamp(Vec2::ZERO, grid_size - Vec2::ONE);
+        let (gx, gy) = (grid_size.x as usize, grid_size.y as usize);
+        let (ix, iy) = (c.x as usize, c.y as usize);
+        let (ix1, iy1) = ((ix + 1).min(gx - 1), (iy + 1).min(gy - 1));
+        let f = c.fract();
+
+        let h0 = mix(self.cells[iy * gx + ix].x, self.cells[iy * gx + ix1].x, f.x);
+        let h1 = mix(self.cells[iy1 * gx + ix].x, self.cells[iy1 * gx + ix1].x, f.x);
+        mix(h0, h1, f.y)
+    }
+}
+
+impl SoftwareMaterial for HeightFieldMaterial {
+    fn draw(&self, frame: &mut Frame) {
+        let (screen_size, grid_size) = (self.screen_size, self.grid_size);
+        frame.shade(|pos| {
+            let cell = (pos + screen_size / 2.0) / screen_size * grid_size;
+
+            let h = self.height_at(cell);
+            let dhdx = (self.height_at(cell + Vec2::X) - self.height_at(cell - Vec2::X)) / 2.0;
+            let dhdy = (self.height_at(cell + Vec2::Y) - self.height_at(cell - Vec2::Y)) / 2.0;
+            let normal =
+                Vec3::new(-dhdx * NORMAL_STRENGTH, -dhdy * NORMAL_STRENGTH, 1.0).normalize();
+
+            let light = LIGHT_DIR.normalize();
+            let diffuse = normal.dot(light).max(0.0);
+            let half_dir = (light + Vec3::Z).normalize();
+            let specular = normal.dot(half_dir).max(0.0).powf(64.0);
+
+            let depth = (h / MAX_HEIGHT * 0.5 + 0.5).clamp(0.0, 1.0);
+            let base = DEEP_COLOR.lerp(SHALLOW_COLOR, depth);
+
+            let ix = cell.x.clamp(0.0, grid_size.x - 1.0) as usize;
+            let iy = cell.y.clamp(0.0, grid_size.y - 1.0) as usize;
+            let c = self.cells[iy * grid_size.x as usize + ix];
+            let foam = (Vec2::new(c.y, c.z).length() / MAX_SPEED).clamp(0.0, 1.0) * 0.5;
+
+            let color =
+                (base * (0.4 + 0.6 * diffuse)).lerp(FOAM_COLOR, foam) + Vec3::splat(specular);
+            color.extend(OPACITY)
+        });
+    }
+}
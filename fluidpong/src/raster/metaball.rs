@@ -0,0 +1,63 @@
+//! `metaball.wgsl`: particles drawn as metaballs above an influence
+//! threshold, with their density as opacity and speed as hue.
+
+use bevy::math::{IVec2, Vec2, Vec3, Vec4};
+use bevy::utils::HashMap;
+
+use super::{hsv_to_rgb, Frame, SoftwareMaterial};
+use crate::sph::MetaballMaterial;
+
+const HUE_MIN: f32 = 0.67;
+const HUE_MAX: f32 = 0.50;
+const MAX_DENSITY: f32 = 10.0;
+const MAX_SPEED: f32 = 3000.0;
+const RADIUS: f32 = 3.0;
+const THRESHOLD: f32 = 0.2;
+const MIN_OPACITY: f32 = 0.2;
+const MAX_OPACITY: f32 = 0.8;
+const DIST_THRESHOLD: f32 = 30.0;
+
+fn bin(position: Vec2) -> IVec2 {
+    (position / DIST_THRESHOLD).floor().as_ivec2()
+}
+
+impl SoftwareMaterial for MetaballMaterial {
+    fn draw(&self, frame: &mut Frame) {
+        // The shader tries every ball for every pixel. Only balls closer than
+        // `DIST_THRESHOLD` count, so binning them by that distance finds the
+        // same ones much faster.
+        let mut bins: HashMap<IVec2, Vec<Vec4>> = HashMap::default();
+        for ball in &self.balls {
+            bins.entry(bin(Vec2::new(ball.x, ball.y))).or_default().push(*ball);
+        }
+        frame.shade(|pos| {
+            let mut sum = 0.0;
+            let mut density_sum = 0.0;
+            let mut speed_sum = 0.0;
+
+            let center = bin(pos);
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    for ball in bins.get(&(center + IVec2::new(x, y))).into_iter().flatten() {
+                        let dist = pos.distance(Vec2::new(ball.x, ball.y));
+                        if dist < DIST_THRESHOLD {
+                            let influence = RADIUS / (dist * dist + 1.0);
+                            sum += influence;
+                            density_sum += ball.z / (dist + 1.0) / 6.0;
+                            speed_sum += ball.w / (dist + 1.0);
+                        }
+                    }
+                }
+            }
+
+            let opacity = (density_sum / MAX_DENSITY).clamp(MIN_OPACITY, MAX_OPACITY);
+            let hue = (HUE_MIN - (speed_sum / MAX_SPEED) * HUE_MAX).clamp(0.0, 1.0);
+
+            if sum > THRESHOLD {
+                hsv_to_rgb(Vec3::new(hue, 1.0, 0.5)).extend(opacity)
+            } else {
+                Vec4::ZERO
+            }
+        });
+    }
+}
@@ -0,0 +1,204 @@
+mod fluidgrid;
+mod heightfield;
+mod metaball;
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use bevy::math::{Vec2, Vec3, Vec4};
+use rayon::prelude::*;
+
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+/// A material that can also be drawn on the CPU, for machines without a GPU.
+///
+/// Implementations follow their WGSL shader line by line, so that frames drawn
+/// here look like the game and can serve as reference images.
+pub trait SoftwareMaterial: Send + Sync {
+    /// Draws the material over the whole arena.
+    fn draw(&self, frame: &mut Frame);
+}
+
+/// An image of the arena drawn on the CPU, with a pixel per world unit.
+///
+/// Like the game's render target, colors are blended in linear space and
+/// stored as sRGB.
+pub struct Frame {
+    width: u32,
+    height: u32,
+    /// Linear RGB, row by row from the top left.
+    pixels: Vec<Vec3>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    /// Returns a black frame the size of the arena.
+    pub fn new() -> Self {
+        let (width, height) = (GAME_WIDTH as u32, GAME_HEIGHT as u32);
+        Self { width, height, pixels: vec![Vec3::ZERO; (width * height) as usize] }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Blends `color_at` the center of every pixel over the frame, as a
+    /// fragment shader with alpha blending would. Colors are linear RGB with
+    /// straight alpha.
+    pub fn shade(&mut self, color_at: impl Fn(Vec2) -> Vec4 + Sync) {
+        let (width, height) = (self.width, self.height);
+        self.pixels.par_chunks_mut(width as usize).enumerate().for_each(|(y, row)| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let position = Vec2::new(
+                    x as f32 + 0.5 - width as f32 / 2.0,
+                    height as f32 / 2.0 - (y as f32 + 0.5),
+                );
+                let color = color_at(position).clamp(Vec4::ZERO, Vec4::ONE);
+                *pixel = pixel.lerp(color.truncate(), color.w);
+            }
+        });
+    }
+
+    /// Fills the pixels whose centers fall in an axis-aligned rectangle.
+    pub fn fill_rect(&mut self, center: Vec2, size: Vec2, color: Vec4) {
+        self.shade(|position| {
+            let inside = ((position - center).abs() - size / 2.0).max_element() <= 0.0;
+            if inside {
+                color
+            } else {
+                Vec4::ZERO
+            }
+        });
+    }
+
+    /// Fills the pixels whose centers fall in a circle.
+    pub fn fill_circle(&mut self, center: Vec2, radius: f32, color: Vec4) {
+        self.shade(|position| if position.distance(center) <= radius { color } else { Vec4::ZERO });
+    }
+
+    /// Returns the frame as 8-bit sRGB, row by row from the top left.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_array().map(|c| (linear_to_srgb(c) * 255.0).round() as u8))
+            .collect()
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&self.to_rgb8()).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// `hsv2rgb` from the shaders.
+fn hsv_to_rgb(c: Vec3) -> Vec3 {
+    let k = Vec4::new(1.0, 2.0 / 3.0, 1.0 / 3.0, 3.0);
+    let p = ((Vec3::splat(c.x) + k.truncate()).fract() * 6.0 - Vec3::splat(k.w)).abs();
+    c.z * Vec3::splat(k.x).lerp((p - Vec3::splat(k.x)).clamp(Vec3::ZERO, Vec3::ONE), c.y)
+}
+
+/// WGSL's `mix`.
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::backend::{FluidBackend, FIXED_TIMESTEP};
+    use crate::simui::FluidSimVars;
+
+    /// Set to regenerate the reference images after an intended change.
+    const BLESS_VAR: &str = "FLUIDPONG_BLESS";
+
+    /// Runs a fluid for a few frames with a paddle emitting into it and the
+    /// mouse stirring it, and draws it.
+    fn draw_scene<B: FluidBackend>() -> Frame {
+        let simvars = FluidSimVars::new(
+            B::simvars().into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+        );
+        let mut fluid = <B as FluidBackend>::new(&simvars);
+        for frame in 0..20 {
+            fluid.apply_emit_force(Vec2::new(-270.0, 0.0), Vec2::new(1.0, 0.0));
+            fluid.interact(Vec2::new(60.0, 40.0), Vec2::new(3.0, -2.0), frame % 2 == 0, &simvars);
+            FluidBackend::step(&mut fluid, FIXED_TIMESTEP, &simvars);
+        }
+        let mut frame = Frame::new();
+        fluid.material().draw(&mut frame);
+        frame
+    }
+
+    /// Compares the frame with `tests/reference/<name>.png`, allowing for
+    /// rounding, or writes the reference if `BLESS_VAR` is set.
+    fn assert_matches_reference(frame: &Frame, name: &str) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "reference", name]
+            .iter()
+            .collect::<PathBuf>()
+            .with_extension("png");
+        if std::env::var_os(BLESS_VAR).is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            frame.save_png(&path).unwrap();
+            return;
+        }
+        let file = File::open(&path).unwrap_or_else(|err| {
+            panic!("{}: {err}; set {BLESS_VAR}=1 to create it", path.display())
+        });
+        let decoder = png::Decoder::new(file);
+        let mut reader = decoder.read_info().unwrap();
+        let mut reference = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut reference).unwrap();
+        assert_eq!((info.width, info.height), (frame.width(), frame.height()), "{name}");
+
+        let pixels = frame.to_rgb8();
+        let differing = (pixels.iter().zip(&reference[..info.buffer_size()]))
+            .filter(|(a, b)| a.abs_diff(**b) > 2)
+            .count();
+        assert!(
+            differing == 0,
+            "{name}: {differing} channels differ from {}; set {BLESS_VAR}=1 to update it",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn metaball_matches_reference() {
+        assert_matches_reference(&draw_scene::<crate::sph::fluid::Fluid>(), "metaball");
+    }
+
+    #[test]
+    fn fluid_grid_matches_reference() {
+        assert_matches_reference(&draw_scene::<crate::ns::fluid::Fluid>(), "fluidgrid");
+    }
+
+    #[test]
+    fn height_field_matches_reference() {
+        assert_matches_reference(&draw_scene::<crate::swe::fluid::Fluid>(), "heightfield");
+    }
+}
@@ -0,0 +1,357 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use bevy::app::AppExit;
+use bevy::core::{update_frame_count, FrameCount};
+use bevy::input::gamepad::{GamepadAxis, GamepadAxisChangedEvent, GamepadButton};
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{read_pointer, registry, ActiveFluid, FluidPointer};
+use crate::pong::ai::AiPlayers;
+use crate::pong::controls::Controls;
+use crate::pong::level::Level;
+use crate::pong::state::{GameState, MatchRules};
+use crate::simui::SimVarEdit;
+
+/// The version of the replay format. Bump it whenever the layout of a replay
+/// or the meaning of its inputs changes.
+pub const REPLAY_VERSION: u32 = 8;
+
+/// Every input to a game, tagged with the frame it happened on.
+///
+/// Replays are written as RON so that they can be read and trimmed by hand
+/// when attached to a bug report. They always run in deterministic mode, so
+/// playing one back reproduces the recorded game exactly.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Replay {
+    pub version: u32,
+    /// The fluid the game started on.
+    pub fluid: String,
+    /// Whether the game ran in debug mode, which mouse and simulation
+    /// variable inputs need.
+    pub debug: bool,
+    /// The paddles the computer played. Missing from replays before version 3,
+    /// which had no AI.
+    #[serde(default)]
+    pub ai: AiPlayers,
+    /// The state the game started in. Replays before version 4 started
+    /// playing straight away.
+    #[serde(default = "started_playing")]
+    pub start: GameState,
+    /// When the match ended. Missing before version 4.
+    #[serde(default)]
+    pub rules: MatchRules,
+    /// How the players' keys and gamepads mapped to their paddles. Replays
+    /// before version 5 had the default controls.
+    #[serde(default)]
+    pub controls: Controls,
+    /// The level the game was played in. Replays before version 6 were
+    /// played in the empty arena.
+    #[serde(default)]
+    pub level: Level,
+    /// How many frames the recording ran for.
+    pub frames: u32,
+    pub inputs: Vec<FrameInput>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FrameInput {
+    pub frame: u32,
+    pub input: Input,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Input {
+    KeyPressed(KeyCode),
+    KeyReleased(KeyCode),
+    GamepadPressed(GamepadButton),
+    GamepadReleased(GamepadButton),
+    GamepadAxis(GamepadAxis, f32),
+    Pointer(FluidPointer),
+    SimVar(SimVarEdit),
+    /// The fluid was swapped, by any means.
+    Fluid(String),
+}
+
+impl Replay {
+    pub fn new(
+        fluid: &str,
+        debug: bool,
+        ai: AiPlayers,
+        start: GameState,
+        rules: MatchRules,
+        controls: Controls,
+        level: Level,
+    ) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            fluid: fluid.to_string(),
+            debug,
+            ai,
+            start,
+            rules,
+            controls,
+            level,
+            frames: 0,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        let config = ron::ser::PrettyConfig::new();
+        let ron = ron::ser::to_string_pretty(self, config)
+            .map_err(|err| ReplayError::Format(err.to_string()))?;
+        Ok(std::fs::write(path, ron)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let bytes = std::fs::read(path)?;
+        let replay: Replay =
+            ron::de::from_bytes(&bytes).map_err(|err| ReplayError::Format(err.to_string()))?;
+        if replay.version > REPLAY_VERSION {
+            return Err(ReplayError::Version(replay.version));
+        }
+        if registry::get(&replay.fluid).is_none() {
+            return Err(ReplayError::UnknownFluid(replay.fluid));
+        }
+        Ok(replay)
+    }
+}
+
+fn started_playing() -> GameState {
+    GameState::Playing
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Format(String),
+    Version(u32),
+    UnknownFluid(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "{err}"),
+            ReplayError::Format(message) => write!(f, "malformed replay: {message}"),
+            ReplayError::Version(version) => write!(
+                f,
+                "replay version {version} is newer than the supported version {REPLAY_VERSION}"
+            ),
+            ReplayError::UnknownFluid(fluid) => write!(f, "unknown fluid `{fluid}`"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(err: std::io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+/// Records a replay, or plays one back instead of the players.
+pub enum ReplayPlugin {
+    /// Records every input and writes the replay to `path` on exit.
+    Record {
+        path: PathBuf,
+        fluid: &'static str,
+        debug: bool,
+        ai: AiPlayers,
+        start: GameState,
+        rules: MatchRules,
+        controls: Controls,
+        level: Level,
+    },
+    Play(Replay),
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match self {
+            ReplayPlugin::Record { path, fluid, debug, ai, start, rules, controls, level } => {
+                app.insert_resource(Recorder {
+                    path: path.clone(),
+                    replay: Replay::new(
+                        fluid,
+                        *debug,
+                        *ai,
+                        *start,
+                        *rules,
+                        controls.clone(),
+                        level.clone(),
+                    ),
+                    pointer: FluidPointer::default(),
+                })
+                .add_systems(PostUpdate, record_inputs)
+                // Exits are sent as late as `PostUpdate`, by headless runs.
+                .add_systems(Last, save_replay.before(update_frame_count));
+            }
+            ReplayPlugin::Play(replay) => {
+                app.insert_resource(Player {
+                    inputs: replay.inputs.clone(),
+                    frames: replay.frames,
+                    next: 0,
+                    keys: ButtonInput::default(),
+                    buttons: ButtonInput::default(),
+                    axes: Vec::new(),
+                    pointer: FluidPointer::default(),
+                    finished: false,
+                })
+                .add_systems(PreUpdate, play_inputs.after(InputSystem).after(read_pointer));
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+    pointer: FluidPointer,
+}
+
+#[derive(Resource)]
+struct Player {
+    inputs: Vec<FrameInput>,
+    frames: u32,
+    next: usize,
+    keys: ButtonInput<KeyCode>,
+    buttons: ButtonInput<GamepadButton>,
+    /// The last recorded position of every gamepad axis that moved.
+    axes: Vec<(GamepadAxis, f32)>,
+    pointer: FluidPointer,
+    finished: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn record_inputs(
+    frame: Res<FrameCount>,
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    mut axis_events: EventReader<GamepadAxisChangedEvent>,
+    pointer: Res<FluidPointer>,
+    active: Res<ActiveFluid>,
+    mut edits: EventReader<SimVarEdit>,
+    mut recorder: ResMut<Recorder>,
+) {
+    let mut inputs = Vec::new();
+    // A key can be released and pressed again within a frame, so releases of
+    // keys that end up held go first.
+    let (repressed, released): (Vec<KeyCode>, Vec<KeyCode>) =
+        keys.get_just_released().partition(|key| keys.pressed(**key));
+    inputs.extend(repressed.into_iter().map(Input::KeyReleased));
+    inputs.extend(keys.get_just_pressed().copied().map(Input::KeyPressed));
+    inputs.extend(released.into_iter().map(Input::KeyReleased));
+    // Swaps are recorded as they happen instead, since the fluid buttons
+    // aren't keys.
+    inputs.retain(|input| {
+        !matches!(input, Input::KeyPressed(KeyCode::Tab) | Input::KeyReleased(KeyCode::Tab))
+    });
+    let (repressed, released): (Vec<GamepadButton>, Vec<GamepadButton>) =
+        buttons.get_just_released().partition(|button| buttons.pressed(**button));
+    inputs.extend(repressed.into_iter().map(Input::GamepadReleased));
+    inputs.extend(buttons.get_just_pressed().copied().map(Input::GamepadPressed));
+    inputs.extend(released.into_iter().map(Input::GamepadReleased));
+    inputs.extend(axis_events.read().map(|event| {
+        Input::GamepadAxis(GamepadAxis::new(event.gamepad, event.axis_type), event.value)
+    }));
+
+    if *pointer != recorder.pointer {
+        recorder.pointer = *pointer;
+        inputs.push(Input::Pointer(*pointer));
+    }
+    inputs.extend(edits.read().cloned().map(Input::SimVar));
+    if active.is_changed() && frame.0 > 0 {
+        inputs.push(Input::Fluid(active.0.to_string()));
+    }
+
+    let inputs = inputs.into_iter().map(|input| FrameInput { frame: frame.0, input });
+    recorder.replay.inputs.extend(inputs);
+}
+
+fn save_replay(exit: EventReader<AppExit>, frame: Res<FrameCount>, mut recorder: ResMut<Recorder>) {
+    if exit.is_empty() {
+        return;
+    }
+    recorder.replay.frames = frame.0 + 1;
+    match recorder.replay.save(&recorder.path) {
+        Ok(()) => println!("saved replay to {}", recorder.path.display()),
+        Err(err) => println!("failed to save replay to {}: {err}", recorder.path.display()),
+    }
+}
+
+/// Replaces this frame's keyboard, gamepad and mouse input with the recorded
+/// one, and sends the recorded simulation variable edits.
+#[allow(clippy::too_many_arguments)]
+fn play_inputs(
+    frame: Res<FrameCount>,
+    mut player: ResMut<Player>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut buttons: ResMut<ButtonInput<GamepadButton>>,
+    mut axes: ResMut<Axis<GamepadAxis>>,
+    mut keyboard_events: ResMut<Events<KeyboardInput>>,
+    mut pointer: ResMut<FluidPointer>,
+    mut active: ResMut<ActiveFluid>,
+    mut edits: EventWriter<SimVarEdit>,
+) {
+    if player.finished {
+        return;
+    }
+    if frame.0 >= player.frames {
+        // Hand the game back to the players.
+        player.finished = true;
+        keys.release_all();
+        buttons.release_all();
+        for (axis, _) in &player.axes {
+            axes.remove(*axis);
+        }
+        println!("replay finished");
+        return;
+    }
+    let player = &mut *player;
+    player.keys.clear();
+    player.buttons.clear();
+    while let Some(FrameInput { frame: input_frame, input }) = player.inputs.get(player.next) {
+        if *input_frame > frame.0 {
+            break;
+        }
+        match input {
+            Input::KeyPressed(key) => player.keys.press(*key),
+            Input::KeyReleased(key) => player.keys.release(*key),
+            Input::GamepadPressed(button) => player.buttons.press(*button),
+            Input::GamepadReleased(button) => player.buttons.release(*button),
+            Input::GamepadAxis(axis, value) => {
+                match player.axes.iter_mut().find(|(moved, _)| moved == axis) {
+                    Some((_, position)) => *position = *value,
+                    None => player.axes.push((*axis, *value)),
+                }
+            }
+            Input::Pointer(recorded) => player.pointer = *recorded,
+            Input::SimVar(edit) => {
+                edits.send(edit.clone());
+            }
+            Input::Fluid(name) => {
+                if let Some(backend) = registry::get(name) {
+                    if active.0 != backend.name {
+                        active.0 = backend.name;
+                    }
+                }
+            }
+        }
+        player.next += 1;
+    }
+    // Live typing would otherwise reach the simulation UI.
+    keyboard_events.clear();
+    *keys = player.keys.clone();
+    *buttons = player.buttons.clone();
+    for (axis, position) in &player.axes {
+        axes.set(*axis, *position);
+    }
+    *pointer = player.pointer;
+}
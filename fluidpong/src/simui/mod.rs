@@ -2,28 +2,49 @@ pub mod text_input;
 
 use bevy::{input::{keyboard::KeyboardInput, ButtonState}, prelude::*, utils::HashMap};
 
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{registry, ActiveFluid, FluidSwapSet};
+use crate::snapshot::SnapshotLoaded;
+
 const BORDER_COLOR_ACTIVE: Color = Color::rgb(0.75, 0.52, 0.99);
 const BORDER_COLOR_INACTIVE: Color = Color::rgb(0.25, 0.25, 0.25);
 const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
 const BACKGROUND_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
 
-pub struct SimUIPlugin {
-    pub fluid_type: String,
-}
+pub struct SimUIPlugin;
 
 impl Plugin for SimUIPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(text_input::TextInputPlugin)
-            .add_systems(Startup, if self.fluid_type == "sph" { sph_setup } else { ns_setup })
-            .add_systems(Update, (update_simvars, focus));
+            .add_systems(Startup, setup_fluid_buttons)
+            .add_systems(
+                Update,
+                (
+                    setup.run_if(resource_changed::<ActiveFluid>),
+                    update_simvars.before(apply_simvar_edits),
+                    show_loaded_simvars,
+                    focus,
+                    select_fluid.before(FluidSwapSet::Despawn),
+                    update_fluid_buttons,
+                ),
+            );
     }
 }
 
+/// Marks the panel of simulation variables, rebuilt whenever the fluid is
+/// swapped.
+#[derive(Component)]
+struct SimVarPanel;
+
+/// A button that swaps to the named fluid.
+#[derive(Component)]
+struct FluidButton(&'static str);
+
 #[derive(Component, Clone)]
 pub struct FluidSimVars {
     pub map: HashMap<String, f32>,
     pub initialized: bool,
-    pub paused: bool,
     pub debug: bool,
     pub interact_mode: bool,
     pub do_reset: bool,
@@ -32,9 +53,8 @@ pub struct FluidSimVars {
 impl FluidSimVars {
     pub fn new(map: HashMap<String, f32>) -> Self {
         FluidSimVars {
-            map: map,
+            map,
             initialized: false,
-            paused: false,
             debug: false,
             interact_mode: false,
             do_reset: false,
@@ -42,16 +62,26 @@ impl FluidSimVars {
     }
 
     pub fn get(&self, key: &str) -> f32 {
-        if let Some(value) = self.map.get(key) {
-            return *value;
-        }
-        return 0.0;
+        self.map.get(key).copied().unwrap_or(0.0)
     }
     pub fn set(&mut self, key: String, value: f32) {
         self.map.insert(key, value);
     }
 }
 
+/// A change to the simulation variables, made from the simulation UI or
+/// replayed from a recording.
+#[derive(Event, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SimVarEdit {
+    Set(String, f32),
+    ToggleDebug,
+    /// Paused the fluid, before the game could pause. Kept so that older
+    /// replays load, and ignored; P pauses the game now.
+    TogglePaused,
+    ToggleInteractMode,
+    Reset,
+}
+
 #[derive(Component, Clone)]
 pub struct SimVariable {
     pub name: String,
@@ -61,40 +91,25 @@ pub struct SimVariable {
 
 impl SimVariable {
     fn new(name: &str, value: f32) -> Self {
-        Self { name: String::from(name), value: value, initial: value }
+        Self { name: String::from(name), value, initial: value }
     }
 }
 
-fn sph_setup(commands: Commands) {
-    let simvars = vec![
-        SimVariable::new("kernel_radius", 8.0),
-        SimVariable::new("particle_mass", 100.0),
-        SimVariable::new("rest_dens", 0.0),
-        SimVariable::new("gas_const", 1000.0),
-        SimVariable::new("visc_const", 300.0),
-        SimVariable::new("bound_damping", 0.5),
-        SimVariable::new("gravity", 1.0),
-        SimVariable::new("interact_force", 3000.0),
-    ];
-    setup(commands, simvars);
-}
-
-fn ns_setup(commands: Commands) {
-    let simvars = vec![
-        SimVariable::new("dt", 0.00001),
-        SimVariable::new("iter", 4.),
-        SimVariable::new("viscosity", 0.0),
-        SimVariable::new("diffusion", 0.2),
-        SimVariable::new("interact_force", 10.0),
-        SimVariable::new("interact_velocity", 1000.0),
-        SimVariable::new("dissipation", 0.1),
-    ];
-    setup(commands, simvars);
-}
+fn setup(
+    mut commands: Commands,
+    active: Res<ActiveFluid>,
+    panel_query: Query<Entity, With<SimVarPanel>>,
+) {
+    for panel in panel_query.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    let simvars = (registry::find(active.0).debug_simvars)()
+        .into_iter()
+        .map(|(name, value)| SimVariable::new(name, value));
 
-fn setup(mut commands: Commands, simvars: Vec<SimVariable>) {
     commands
         .spawn((
+            SimVarPanel,
             NodeBundle {
                 style: Style {
                     flex_direction: FlexDirection::Column,
@@ -159,42 +174,102 @@ fn setup(mut commands: Commands, simvars: Vec<SimVariable>) {
         });
 }
 
+fn setup_fluid_buttons(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(5.0),
+                right: Val::Px(5.0),
+                flex_direction: FlexDirection::Row,
+                column_gap: Val::Px(4.0),
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for backend in registry::BACKENDS {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                border: UiRect::all(Val::Px(1.0)),
+                                padding: UiRect::all(Val::Px(2.0)),
+                                ..default()
+                            },
+                            border_color: BORDER_COLOR_INACTIVE.into(),
+                            background_color: BACKGROUND_COLOR.into(),
+                            ..default()
+                        },
+                        FluidButton(backend.name),
+                    ))
+                    .with_children(|button| {
+                        button.spawn(TextBundle::from_section(
+                            backend.name,
+                            TextStyle { font_size: 12.0, color: TEXT_COLOR, ..default() },
+                        ));
+                    });
+            }
+        });
+}
+
+fn select_fluid(
+    query: Query<(&Interaction, &FluidButton), Changed<Interaction>>,
+    mut active: ResMut<ActiveFluid>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction == Interaction::Pressed && active.0 != button.0 {
+            active.0 = button.0;
+            println!("fluid: {}", active.0);
+        }
+    }
+}
+
+fn update_fluid_buttons(
+    active: Res<ActiveFluid>,
+    mut query: Query<(&FluidButton, &mut BorderColor)>,
+) {
+    if active.is_changed() {
+        for (button, mut border_color) in query.iter_mut() {
+            *border_color = if button.0 == active.0 {
+                BORDER_COLOR_ACTIVE.into()
+            } else {
+                BORDER_COLOR_INACTIVE.into()
+            };
+        }
+    }
+}
 
 fn update_simvars(
     mut key_evr: EventReader<KeyboardInput>,
+    mut edits: EventWriter<SimVarEdit>,
     mut simvars_query: Query<&mut FluidSimVars>,
     query: Query<(&SimVariable, &text_input::TextInputValue)>,
 ) {
-    let mut simvars = simvars_query.single_mut();
-    let mut do_update = false;
+    let Ok(mut simvars) = simvars_query.get_single_mut() else {
+        return;
+    };
     for ev in key_evr.read() {
         if ev.state == ButtonState::Released {
             if ev.key_code == KeyCode::Enter {
-                do_update = true;
+                for (simvar, input) in query.iter() {
+                    let value = input.0.parse::<f32>().unwrap_or(0.0);
+                    edits.send(SimVarEdit::Set(simvar.name.clone(), value));
+                }
             }
             if ev.key_code == KeyCode::KeyD {
-                simvars.debug = !simvars.debug;
-                println!("debug: {}", simvars.debug);
-            }
-            if ev.key_code == KeyCode::KeyP {
-                simvars.paused = !simvars.paused;
-                println!("paused: {}", simvars.paused);
+                edits.send(SimVarEdit::ToggleDebug);
             }
             if ev.key_code == KeyCode::KeyI {
-                simvars.interact_mode = !simvars.interact_mode;
-                println!("interact mode: {}", simvars.interact_mode);
+                edits.send(SimVarEdit::ToggleInteractMode);
             }
             if ev.key_code == KeyCode::KeyR {
-                simvars.do_reset = !simvars.do_reset;
-                println!("resetting")
+                edits.send(SimVarEdit::Reset);
             }
         }
     }
     if !simvars.initialized {
-        do_update = true;
         simvars.initialized = true;
-    }
-    if do_update {
         for (simvar, input) in query.iter() {
             let value = input.0.parse::<f32>().unwrap_or(0.0);
             simvars.set(simvar.name.clone(), value);
@@ -203,6 +278,56 @@ fn update_simvars(
     }
 }
 
+/// Applies the edits made this frame to the fluid.
+pub(crate) fn apply_simvar_edits(
+    mut edits: EventReader<SimVarEdit>,
+    mut simvars_query: Query<&mut FluidSimVars>,
+) {
+    let Ok(mut simvars) = simvars_query.get_single_mut() else {
+        return;
+    };
+    for edit in edits.read() {
+        match edit {
+            SimVarEdit::Set(name, value) => {
+                simvars.set(name.clone(), *value);
+                println!("updating {} to {}", name, value);
+            }
+            SimVarEdit::ToggleDebug => {
+                simvars.debug = !simvars.debug;
+                println!("debug: {}", simvars.debug);
+            }
+            SimVarEdit::TogglePaused => {}
+            SimVarEdit::ToggleInteractMode => {
+                simvars.interact_mode = !simvars.interact_mode;
+                println!("interact mode: {}", simvars.interact_mode);
+            }
+            SimVarEdit::Reset => {
+                simvars.do_reset = !simvars.do_reset;
+                println!("resetting")
+            }
+        }
+    }
+}
+
+/// Shows the simulation variables of a loaded snapshot in the panel.
+fn show_loaded_simvars(
+    mut loaded_evr: EventReader<SnapshotLoaded>,
+    simvars_query: Query<&FluidSimVars>,
+    mut query: Query<(&SimVariable, &mut text_input::TextInputValue)>,
+) {
+    if loaded_evr.read().count() == 0 {
+        return;
+    }
+    let Ok(simvars) = simvars_query.get_single() else {
+        return;
+    };
+    for (simvar, mut input) in query.iter_mut() {
+        if let Some(value) = simvars.map.get(&simvar.name) {
+            input.0 = value.to_string();
+        }
+    }
+}
+
 fn focus(
     query: Query<(Entity, &Interaction), Changed<Interaction>>,
     mut text_input_query: Query<(Entity, &mut text_input::TextInputInactive, &mut BorderColor)>,
@@ -6,7 +6,7 @@
 //!
 //! ```no_run
 //! use bevy::prelude::*;
-//! use bevy_simple_text_input::{TextInputBundle, TextInputPlugin};
+//! use fluidpong::simui::text_input::{TextInputBundle, TextInputPlugin};
 //!
 //! fn main() {
 //!     App::new()
@@ -74,7 +74,7 @@ const CURSOR_HANDLE: Handle<Font> = Handle::weak_from_u128(10482756907980398621)
 ///
 /// ```rust
 /// # use bevy::prelude::*;
-/// use bevy_simple_text_input::TextInputBundle;
+/// use fluidpong::simui::text_input::TextInputBundle;
 /// fn setup(mut commands: Commands) {
 ///     commands.spawn((NodeBundle::default(), TextInputBundle::default()));
 /// }
@@ -196,6 +196,7 @@ impl<'w, 's> InnerText<'w, 's> {
     }
 }
 
+#[allow(clippy::collapsible_match)]
 fn keyboard(
     mut events: EventReader<KeyboardInput>,
     mut text_input_query: Query<(
@@ -305,6 +306,7 @@ fn keyboard(
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn update_value(
     mut input_query: Query<
         (Entity, Ref<TextInputValue>, &mut TextInputCursorPos),
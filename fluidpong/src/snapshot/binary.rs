@@ -0,0 +1,109 @@
+//! The binary snapshot format. Everything is little endian:
+//!
+//! ```text
+//! magic    b"FPSN"
+//! version  u32
+//! fluid    string
+//! simvars  u32 count, then (string name, f32 value) per variable
+//! arrays   u32 count, then (string name, u32 len, len f32 values) per array
+//! ```
+//!
+//! where a string is its u32 byte length followed by UTF-8.
+
+use std::collections::BTreeMap;
+
+use super::{FluidState, Snapshot, SnapshotError};
+
+pub const MAGIC: &[u8] = b"FPSN";
+
+pub fn encode(snapshot: &Snapshot) -> Vec<u8> {
+    let len: usize = snapshot.state.arrays.values().map(|values| values.len() * 4 + 64).sum();
+    let mut bytes = Vec::with_capacity(len + 1024);
+    bytes.extend_from_slice(MAGIC);
+    write_u32(&mut bytes, snapshot.version);
+    write_str(&mut bytes, &snapshot.fluid);
+    write_u32(&mut bytes, snapshot.simvars.len() as u32);
+    for (name, value) in &snapshot.simvars {
+        write_str(&mut bytes, name);
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    write_u32(&mut bytes, snapshot.state.arrays.len() as u32);
+    for (name, values) in &snapshot.state.arrays {
+        write_str(&mut bytes, name);
+        write_u32(&mut bytes, values.len() as u32);
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(SnapshotError::Format("not a snapshot".to_string()));
+    }
+    let version = reader.u32()?;
+    let fluid = reader.string()?;
+    let mut simvars = BTreeMap::new();
+    for _ in 0..reader.u32()? {
+        let name = reader.string()?;
+        simvars.insert(name, reader.f32()?);
+    }
+    let mut arrays = BTreeMap::new();
+    for _ in 0..reader.u32()? {
+        let name = reader.string()?;
+        let len = reader.u32()? as usize;
+        let values = reader.take(len.checked_mul(4).ok_or_else(truncated)?)?;
+        arrays.insert(
+            name,
+            values.chunks(4).map(|v| f32::from_le_bytes(v.try_into().unwrap())).collect(),
+        );
+    }
+    if !reader.bytes.is_empty() {
+        return Err(SnapshotError::Format("trailing bytes".to_string()));
+    }
+    Ok(Snapshot { version, fluid, simvars, state: FluidState { arrays } })
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, value: &str) {
+    write_u32(bytes, value.len() as u32);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn truncated() -> SnapshotError {
+    SnapshotError::Format("unexpected end of file".to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(truncated());
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, SnapshotError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| SnapshotError::Format("string is not UTF-8".to_string()))
+    }
+}
@@ -0,0 +1,279 @@
+mod binary;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::registry::{self, Backend};
+use crate::backend::{ActiveFluid, FluidBackend};
+use crate::simui::FluidSimVars;
+
+/// The version of the snapshot format. Bump it whenever the layout of a
+/// snapshot or the state saved by a backend changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Where F5 saves and F9 loads a binary snapshot in debug mode.
+pub const SNAPSHOT_PATH: &str = "snapshot.fpsnap";
+/// Where F6 saves and F10 loads a RON snapshot in debug mode.
+pub const SNAPSHOT_RON_PATH: &str = "snapshot.ron";
+
+/// The state of a fluid and its simulation variables, so that it can be
+/// restored later or on another machine.
+///
+/// Snapshots are written as a compact binary file, or as RON when the path
+/// ends in `.ron`. Loading tells the two apart by the binary magic bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub version: u32,
+    pub fluid: String,
+    pub simvars: BTreeMap<String, f32>,
+    pub state: FluidState,
+}
+
+impl Snapshot {
+    pub fn new(fluid: &str, simvars: &FluidSimVars, state: FluidState) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            fluid: fluid.to_string(),
+            simvars: simvars.map.iter().map(|(name, value)| (name.clone(), *value)).collect(),
+            state,
+        }
+    }
+
+    /// Returns the registered backend the snapshot was taken from.
+    pub fn backend(&self) -> Result<&'static Backend, SnapshotError> {
+        registry::get(&self.fluid).ok_or_else(|| SnapshotError::UnknownFluid(self.fluid.clone()))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        binary::encode(self)
+    }
+
+    pub fn to_ron(&self) -> Result<String, SnapshotError> {
+        let config = ron::ser::PrettyConfig::new().compact_arrays(true);
+        ron::ser::to_string_pretty(self, config)
+            .map_err(|err| SnapshotError::Format(err.to_string()))
+    }
+
+    /// Reads a snapshot from either format.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let snapshot = if bytes.starts_with(binary::MAGIC) {
+            binary::decode(bytes)?
+        } else {
+            ron::de::from_bytes(bytes).map_err(|err| SnapshotError::Format(err.to_string()))?
+        };
+        if snapshot.version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(snapshot.version));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        let bytes = if path.extension().is_some_and(|extension| extension == "ron") {
+            self.to_ron()?.into_bytes()
+        } else {
+            self.to_bytes()
+        };
+        Ok(std::fs::write(path, bytes)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// The arrays that make up the state of a fluid, such as grid fields or
+/// particle attributes, by name. Vectors are stored with their components
+/// interleaved.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FluidState {
+    pub arrays: BTreeMap<String, Vec<f32>>,
+}
+
+impl FluidState {
+    pub fn with(mut self, name: &str, values: impl IntoIterator<Item = f32>) -> Self {
+        self.arrays.insert(name.to_string(), values.into_iter().collect());
+        self
+    }
+
+    pub fn with_vec2s(self, name: &str, values: impl IntoIterator<Item = Vec2>) -> Self {
+        self.with(name, values.into_iter().flat_map(|value| [value.x, value.y]))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&[f32], SnapshotError> {
+        (self.arrays.get(name).map(Vec::as_slice))
+            .ok_or_else(|| SnapshotError::MissingArray(name.to_string()))
+    }
+
+    /// Returns the array with the given name, which must hold `len` values.
+    pub fn get_len(&self, name: &str, len: usize) -> Result<&[f32], SnapshotError> {
+        let values = self.get(name)?;
+        if values.len() != len {
+            return Err(SnapshotError::Length {
+                name: name.to_string(),
+                expected: len,
+                found: values.len(),
+            });
+        }
+        Ok(values)
+    }
+
+    /// Returns the array with the given name as `len` vectors.
+    pub fn get_vec2s(&self, name: &str, len: usize) -> Result<Vec<Vec2>, SnapshotError> {
+        let values = self.get_len(name, len * 2)?;
+        Ok(values.chunks(2).map(|xy| Vec2::new(xy[0], xy[1])).collect())
+    }
+
+    /// Returns a hash of the exact bits of every array, to check that two
+    /// states are identical.
+    pub fn checksum(&self) -> u64 {
+        // FNV-1a, which unlike `DefaultHasher` is the same on every run.
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+            }
+        };
+        for (name, values) in &self.arrays {
+            write(name.as_bytes());
+            for value in values {
+                write(&value.to_bits().to_le_bytes());
+            }
+        }
+        hash
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Format(String),
+    Version(u32),
+    UnknownFluid(String),
+    MissingArray(String),
+    Length { name: String, expected: usize, found: usize },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{err}"),
+            SnapshotError::Format(message) => write!(f, "malformed snapshot: {message}"),
+            SnapshotError::Version(version) => write!(
+                f,
+                "snapshot version {version} is newer than the supported version {SNAPSHOT_VERSION}"
+            ),
+            SnapshotError::UnknownFluid(fluid) => write!(f, "unknown fluid `{fluid}`"),
+            SnapshotError::MissingArray(name) => write!(f, "missing array `{name}`"),
+            SnapshotError::Length { name, expected, found } => {
+                write!(f, "array `{name}` has {found} values instead of {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(err: std::io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+/// A snapshot waiting to be applied once its fluid is spawned.
+#[derive(Resource, Default)]
+pub struct PendingSnapshot(pub Option<Snapshot>);
+
+/// Sent after a snapshot has been applied to the fluid.
+#[derive(Event)]
+pub struct SnapshotLoaded;
+
+/// Applies snapshots, starting with `load` if given. In debug mode, F5 and F6
+/// save the fluid to `SNAPSHOT_PATH` and `SNAPSHOT_RON_PATH`, and F9 and F10
+/// load it back.
+pub struct SnapshotPlugin {
+    pub load: Option<Snapshot>,
+    pub debug: bool,
+}
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PendingSnapshot(self.load.clone())).add_event::<SnapshotLoaded>();
+        if self.debug {
+            app.add_systems(
+                Update,
+                load_snapshot_on_key.before(crate::backend::FluidSwapSet::Despawn),
+            );
+        }
+    }
+}
+
+fn load_snapshot_on_key(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut pending: ResMut<PendingSnapshot>,
+    mut active: ResMut<ActiveFluid>,
+) {
+    let path = if keyboard_input.just_pressed(KeyCode::F9) {
+        SNAPSHOT_PATH
+    } else if keyboard_input.just_pressed(KeyCode::F10) {
+        SNAPSHOT_RON_PATH
+    } else {
+        return;
+    };
+    let loaded = Snapshot::load(path).and_then(|snapshot| Ok((snapshot.backend()?, snapshot)));
+    match loaded {
+        Ok((backend, snapshot)) => {
+            if active.0 != backend.name {
+                active.0 = backend.name;
+            }
+            pending.0 = Some(snapshot);
+            println!("loading snapshot from {path}");
+        }
+        Err(err) => println!("failed to load snapshot from {path}: {err}"),
+    }
+}
+
+pub(crate) fn save_snapshot<B: FluidBackend>(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    query: Query<(&B, &FluidSimVars)>,
+) {
+    let path = if keyboard_input.just_pressed(KeyCode::F5) {
+        SNAPSHOT_PATH
+    } else if keyboard_input.just_pressed(KeyCode::F6) {
+        SNAPSHOT_RON_PATH
+    } else {
+        return;
+    };
+    let Ok((fluid, simvars)) = query.get_single() else {
+        return;
+    };
+    match Snapshot::new(B::NAME, simvars, fluid.save_state()).save(path) {
+        Ok(()) => println!("saved snapshot to {path}"),
+        Err(err) => println!("failed to save snapshot to {path}: {err}"),
+    }
+}
+
+pub(crate) fn apply_snapshot<B: FluidBackend>(
+    mut pending: ResMut<PendingSnapshot>,
+    mut loaded: EventWriter<SnapshotLoaded>,
+    mut query: Query<(&mut B, &mut FluidSimVars)>,
+) {
+    let Ok((mut fluid, mut simvars)) = query.get_single_mut() else {
+        return;
+    };
+    let Some(snapshot) = pending.0.take_if(|snapshot| snapshot.fluid == B::NAME) else {
+        return;
+    };
+    simvars.map = snapshot.simvars.into_iter().collect();
+    simvars.initialized = true;
+    fluid.reset(&simvars);
+    if let Err(err) = fluid.load_state(&snapshot.state) {
+        println!("failed to apply snapshot: {err}");
+        fluid.reset(&simvars);
+    }
+    loaded.send(SnapshotLoaded);
+}
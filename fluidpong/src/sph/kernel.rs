@@ -98,7 +98,7 @@ impl KernelFunction for SpikyKernel {
     fn gradient(&self, r: Vec2) -> Vec2 {
         if r.length() <= self.h {
             let h5 = self.h.powi(5);
-            -10.0 / (PI * h5) * (self.h - r.length()).powi(2) * r.normalize()
+            -10.0 / (PI * h5) * (self.h - r.length()).powi(2) * r.normalize_or_zero()
         } else {
             Vec2::ZERO
         }
@@ -4,142 +4,117 @@ pub mod particle;
 mod pongfluid;
 pub mod spatial_grid;
 
-use bevy::app::{App, Plugin, Update};
-use bevy::ecs::event::EventReader;
-use bevy::ecs::query::With;
-use bevy::ecs::system::{Commands, Query, Res};
 use bevy::gizmos::gizmos::Gizmos;
-use bevy::input::mouse::MouseMotion;
 use bevy::math::Vec2;
 use bevy::prelude::*;
-use bevy::render::camera::Camera;
 use bevy::render::color::Color;
 use bevy::render::render_resource::{AsBindGroup, ShaderRef};
-use bevy::sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, Mesh2dHandle};
-use bevy::time::Time;
-use bevy::transform::components::GlobalTransform;
-use bevy::utils::HashMap;
-use bevy::window::{PrimaryWindow, Window};
+use bevy::sprite::Material2d;
 
+use crate::backend::field::VelocityField;
+use crate::backend::FluidBackend;
+use crate::export::FluidExport;
 use crate::simui::FluidSimVars;
+use crate::snapshot::{FluidState, SnapshotError};
+use crate::sph::particle::Particle;
+
+impl FluidBackend for fluid::Fluid {
+    const NAME: &'static str = "sph";
+    type Material = MetaballMaterial;
+
+    fn simvars() -> Vec<(&'static str, f32)> {
+        vec![
+            ("kernel_radius", 8.0),
+            ("particle_mass", 100.0),
+            ("rest_dens", 1.0),
+            ("gas_const", 1000.0),
+            ("visc_const", 300.0),
+            ("bound_damping", 0.5),
+            ("gravity", 0.0),
+        ]
+    }
 
-pub struct FluidPlugin {
-    pub debug: bool,
-}
+    fn debug_simvars() -> Vec<(&'static str, f32)> {
+        vec![
+            ("kernel_radius", 8.0),
+            ("particle_mass", 100.0),
+            ("rest_dens", 0.0),
+            ("gas_const", 1000.0),
+            ("visc_const", 300.0),
+            ("bound_damping", 0.5),
+            ("gravity", 1.0),
+            ("interact_force", 3000.0),
+        ]
+    }
 
-impl Plugin for FluidPlugin {
-    fn build(&self, app: &mut App) {
-        app.add_plugins(Material2dPlugin::<MetaballMaterial>::default())
-            .add_systems(PostStartup, startup)
-            .add_systems(Update, (update_fluid, update_shader));
-        if self.debug {
-            app.add_systems(Update, (draw_gizmos, update_interactive, update_debug));
-        }
+    fn new(simvars: &FluidSimVars) -> Self {
+        Self::new(simvars.get("kernel_radius"), simvars.get("particle_mass"))
     }
-}
 
-fn startup(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<MetaballMaterial>>,
-) {
-    let simvars = FluidSimVars::new(HashMap::from([
-        ("kernel_radius".to_string(), 8.0),
-        ("particle_mass".to_string(), 100.0),
-        ("rest_dens".to_string(), 1.0),
-        ("gas_const".to_string(), 1000.0),
-        ("visc_const".to_string(), 300.0),
-        ("bound_damping".to_string(), 0.5),
-        ("gravity".to_string(), 0.0),
-    ]));
-    let fluid = fluid::Fluid::new(simvars.get("kernel_radius"), simvars.get("particle_mass"));
-    let balls = fluid.get_balls();
-
-    commands.spawn((
-        fluid,
-        simvars,
-        MaterialMesh2dBundle {
-            mesh: Mesh2dHandle(
-                meshes.add(Rectangle::new(fluid::WALL_X * 2.0, fluid::WALL_Y * 2.0)),
-            ),
-            material: materials.add(MetaballMaterial { color: Color::BLUE, balls: balls }),
-            transform: Transform::from_translation(Vec3::ZERO),
-            ..default()
-        },
-    ));
-}
+    fn reset(&mut self, simvars: &FluidSimVars) {
+        self.reset(simvars.get("kernel_radius"), simvars.get("particle_mass"));
+    }
 
-fn update_fluid(
-    time: Res<Time>,
-    mut fluid_query: Query<&mut fluid::Fluid>,
-    simvar_query: Query<&FluidSimVars>,
-) {
-    let simvars = simvar_query.single();
-    if !simvars.paused {
-        let mut fluid = fluid_query.single_mut();
-        fluid.compute_density_pressure(simvars.get("gas_const"), simvars.get("rest_dens"));
-        fluid.compute_forces(simvars.get("visc_const"), simvars.get("gravity"));
-        fluid.integrate(time.delta_seconds(), simvars.get("bound_damping"));
+    fn step(&mut self, dt: f32, simvars: &FluidSimVars) {
+        self.compute_density_pressure(simvars.get("gas_const"), simvars.get("rest_dens"));
+        self.compute_forces(simvars.get("visc_const"), simvars.get("gravity"));
+        self.integrate(dt, simvars.get("bound_damping"));
     }
-}
 
-fn update_shader(
-    mut query: Query<(&fluid::Fluid, &Handle<MetaballMaterial>)>,
-    mut materials: ResMut<Assets<MetaballMaterial>>,
-) {
-    let (fluid, handle) = query.single_mut();
-    if let Some(material) = materials.get_mut(&*handle) {
-        material.balls = fluid.get_balls();
+    fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        self.get_velocity_at(position)
     }
-}
 
-fn update_interactive(
-    camera_query: Query<(&Camera, &GlobalTransform)>,
-    window_query: Query<&Window, With<PrimaryWindow>>,
-    mut motion_er: EventReader<MouseMotion>,
-    mut query: Query<&mut fluid::Fluid>,
-    mut gizmos: Gizmos,
-    simvar_query: Query<&FluidSimVars>,
-) {
-    let simvars = simvar_query.single();
-    let (camera, camera_transform) = camera_query.single();
-
-    let mut fluid = query.single_mut();
-    fluid.set_external_force(Vec2::ZERO, Vec2::ZERO, 0.0);
-
-    for motion in motion_er.read() {
-        let window: &Window = window_query.single();
-
-        if let Some(cursor_position) = window.cursor_position() {
-            if let Some(world_position) =
-                camera.viewport_to_world_2d(camera_transform, cursor_position)
-            {
-                gizmos.circle_2d(world_position, 10., Color::WHITE);
-
-                let point = Vec2::new(world_position.x, world_position.y);
-                let force = Vec2::new(motion.delta.x, -motion.delta.y);
-                fluid.set_external_force(point, force * simvars.get("interact_force"), 10.0);
-            }
+    fn interact(&mut self, position: Vec2, drag: Vec2, _pressed: bool, simvars: &FluidSimVars) {
+        self.set_external_force(Vec2::ZERO, Vec2::ZERO, 0.0);
+        if drag != Vec2::ZERO {
+            self.set_external_force(position, drag * simvars.get("interact_force"), 10.0);
         }
     }
-}
 
-fn draw_gizmos(mut gizmos: Gizmos, fluids: Query<&fluid::Fluid>) {
-    for fluid in fluids.iter() {
-        for particle in fluid.particles() {
-            // Draw a circle at the particle's position
-            gizmos.circle_2d(particle.position, 2.0, Color::rgba(0.6, 0.8, 1.0, 0.3));
-        }
+    fn seed_velocity(&mut self, field: &VelocityField) {
+        self.set_velocities(|position| field.sample(position));
+    }
+
+    fn save_state(&self) -> FluidState {
+        let particles: Vec<&Particle> = self.particles().iter().collect();
+        FluidState::default()
+            .with("mass", particles.iter().map(|particle| particle.mass))
+            .with_vec2s("position", particles.iter().map(|particle| particle.position))
+            .with_vec2s("velocity", particles.iter().map(|particle| particle.velocity))
+    }
+
+    fn export(&self) -> FluidExport {
+        let particles: Vec<&Particle> = self.particles().iter().collect();
+        FluidExport::particles(particles.iter().map(|particle| particle.position))
+            .with_vectors("velocity", particles.iter().map(|particle| particle.velocity))
+            .with_scalars("density", particles.iter().map(|particle| particle.density))
+            .with_scalars("pressure", particles.iter().map(|particle| particle.pressure))
+            .with_vectors("force", particles.iter().map(|particle| particle.force))
+    }
+
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError> {
+        let mass = state.get("mass")?;
+        let position = state.get_vec2s("position", mass.len())?;
+        let velocity = state.get_vec2s("velocity", mass.len())?;
+        let particles = (0..mass.len())
+            .map(|i| Particle { velocity: velocity[i], ..Particle::new(position[i], mass[i]) });
+        self.set_particles(particles);
+        Ok(())
     }
-}
 
-fn update_debug(mut simvar_query: Query<&mut FluidSimVars>, mut fluid_query: Query<&mut fluid::Fluid>) {
-    if let Ok(mut simvars) = simvar_query.get_single_mut() {
-        if simvars.do_reset {
-            if let Ok(mut fluid) = fluid_query.get_single_mut() {
-                fluid.reset(simvars.get("kernel_radius"), simvars.get("particle_mass"));
-                simvars.do_reset = false;
-            }
+    fn material(&self) -> MetaballMaterial {
+        MetaballMaterial { color: Color::BLUE, balls: self.get_balls() }
+    }
+
+    fn update_material(&self, material: &mut MetaballMaterial, _simvars: &FluidSimVars) {
+        material.balls = self.get_balls();
+    }
+
+    fn draw_gizmos(&self, gizmos: &mut Gizmos, _simvars: &FluidSimVars) {
+        for particle in self.particles() {
+            // Draw a circle at the particle's position
+            gizmos.circle_2d(particle.position, 2.0, Color::rgba(0.6, 0.8, 1.0, 0.3));
         }
     }
 }
@@ -147,9 +122,9 @@ fn update_debug(mut simvar_query: Query<&mut FluidSimVars>, mut fluid_query: Que
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
 pub struct MetaballMaterial {
     #[uniform(0)]
-    color: Color,
+    pub(crate) color: Color,
     #[uniform(1)]
-    balls: [Vec4; fluid::NUM_PARTICLES],
+    pub(crate) balls: [Vec4; fluid::NUM_PARTICLES],
 }
 
 impl Material2d for MetaballMaterial {
@@ -1,5 +1,6 @@
+use std::collections::BTreeMap;
+
 use bevy::math::Vec2;
-use bevy::utils::HashMap;
 
 const NEIGHBOR_OFFSETS: [(i32, i32); 9] =
     [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 0), (0, 1), (1, -1), (1, 0), (1, 1)];
@@ -13,21 +14,25 @@ pub trait Position {
 /// The grid is divided into cells of a fixed size, and each cell stores a list
 /// of entities that are within that cell. This allows for fast queries of
 /// entities within a certain radius.
+///
+/// Cells are kept in key order and entities in the order they were inserted,
+/// so iteration and queries visit entities in the same order on every run and
+/// sums over them are reproducible.
 #[derive(Debug, Clone)]
 pub struct SpatialGrid2D<T: Position> {
     radius: f32,
-    inner: HashMap<(i32, i32), Vec<T>>,
+    inner: BTreeMap<(i32, i32), Vec<T>>,
 }
 
 impl<T: Position> SpatialGrid2D<T> {
     /// Creates a new grid supporting queries with the given radius.
     pub fn new(radius: f32) -> Self {
-        Self { radius, inner: HashMap::new() }
+        Self { radius, inner: BTreeMap::new() }
     }
 
     /// Inserts an entity into the grid.
     pub fn insert(&mut self, entity: T) {
-        self.inner.entry(self.get_key(entity.position())).or_insert_with(Vec::new).push(entity);
+        self.inner.entry(self.get_key(entity.position())).or_default().push(entity);
     }
 
     /// Retrieves entities within the radius of the given position.
@@ -0,0 +1,312 @@
+use bevy::ecs::component::Component;
+use bevy::math::{Vec2, Vec4};
+
+use crate::pong::level::Obstacle;
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+pub const GRID_X: usize = 96;
+pub const GRID_Y: usize = 48;
+pub const NUM_CELLS: usize = GRID_X * GRID_Y;
+pub const WIDTH: f32 = GAME_WIDTH;
+pub const HEIGHT: f32 = GAME_HEIGHT;
+pub const CELL_SIZE: f32 = WIDTH / GRID_X as f32;
+
+/// A shallow pool described by the shallow water equations.
+///
+/// Water heights live at cell centers and the depth-averaged velocity on a
+/// staggered grid, with `u` on the vertical faces and `v` on the horizontal
+/// ones. Each step advects the velocities, moves water between cells with
+/// upwind fluxes and then accelerates the water down the height gradient. The
+/// arena edges are reflective walls, and the mean depth is held at the rest
+/// depth so that swells raised by the players spread out instead of flooding
+/// the pool. Cells inside obstacles are walls too, with no water crossing
+/// their faces.
+#[derive(Component)]
+pub struct Fluid {
+    pub height: Vec<f32>,
+    pub u: Vec<f32>,
+    pub v: Vec<f32>,
+    rest_depth: f32,
+    solid: Vec<bool>,
+    obstacles: Vec<Obstacle>,
+}
+
+impl Fluid {
+    pub fn new(rest_depth: f32) -> Fluid {
+        Fluid {
+            height: vec![rest_depth; NUM_CELLS],
+            u: vec![0.0; (GRID_X + 1) * GRID_Y],
+            v: vec![0.0; GRID_X * (GRID_Y + 1)],
+            rest_depth,
+            solid: vec![false; NUM_CELLS],
+            obstacles: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self, rest_depth: f32) {
+        *self = Fluid::new(rest_depth);
+    }
+
+    /// Advances the simulation by one time step.
+    pub fn step(&mut self, dt: f32, gravity: f32, damping: f32) {
+        self.advect_velocity(dt);
+        self.close_solid_faces();
+        self.update_height(dt);
+        self.update_velocity(dt, gravity, damping);
+        self.close_solid_faces();
+        self.conserve_volume();
+    }
+
+    pub fn set_obstacles(&mut self, obstacles: &[Obstacle]) {
+        if self.obstacles == obstacles {
+            return;
+        }
+        self.obstacles = obstacles.to_vec();
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                let center = (Vec2::new(i as f32, j as f32) + CENTER_OFFSET) * CELL_SIZE
+                    - Vec2::new(WIDTH, HEIGHT) / 2.0;
+                self.solid[cell_index(i, j)] = obstacles.iter().any(|o| o.contains(center));
+            }
+        }
+    }
+
+    /// Stops the water on the faces of cells inside obstacles.
+    fn close_solid_faces(&mut self) {
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                if self.solid[cell_index(i, j)] {
+                    self.u[u_index(i, j)] = 0.0;
+                    self.u[u_index(i + 1, j)] = 0.0;
+                    self.v[v_index(i, j)] = 0.0;
+                    self.v[v_index(i, j + 1)] = 0.0;
+                }
+            }
+        }
+    }
+
+    /// Moves the velocity field along itself with semi-Lagrangian advection.
+    fn advect_velocity(&mut self, dt: f32) {
+        let mut u = self.u.clone();
+        for j in 0..GRID_Y {
+            for i in 1..GRID_X {
+                let position = Vec2::new(i as f32, j as f32 + 0.5) * CELL_SIZE;
+                let back = position - dt * self.velocity_local(position);
+                u[u_index(i, j)] = sample(&self.u, GRID_X + 1, GRID_Y, U_OFFSET, back);
+            }
+        }
+        let mut v = self.v.clone();
+        for j in 1..GRID_Y {
+            for i in 0..GRID_X {
+                let position = Vec2::new(i as f32 + 0.5, j as f32) * CELL_SIZE;
+                let back = position - dt * self.velocity_local(position);
+                v[v_index(i, j)] = sample(&self.v, GRID_X, GRID_Y + 1, V_OFFSET, back);
+            }
+        }
+        self.u = u;
+        self.v = v;
+    }
+
+    /// Moves water between cells, taking the height upwind of each face.
+    fn update_height(&mut self, dt: f32) {
+        let flux_u: Vec<f32> = (0..self.u.len())
+            .map(|idx| {
+                let (i, j) = (idx % (GRID_X + 1), idx / (GRID_X + 1));
+                if i == 0 || i == GRID_X {
+                    return 0.0;
+                }
+                let upwind = if self.u[idx] > 0.0 { i - 1 } else { i };
+                self.u[idx] * self.height[cell_index(upwind, j)]
+            })
+            .collect();
+        let flux_v: Vec<f32> = (0..self.v.len())
+            .map(|idx| {
+                let (i, j) = (idx % GRID_X, idx / GRID_X);
+                if j == 0 || j == GRID_Y {
+                    return 0.0;
+                }
+                let upwind = if self.v[idx] > 0.0 { j - 1 } else { j };
+                self.v[idx] * self.height[cell_index(i, upwind)]
+            })
+            .collect();
+
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                let divergence = flux_u[u_index(i + 1, j)] - flux_u[u_index(i, j)]
+                    + flux_v[v_index(i, j + 1)]
+                    - flux_v[v_index(i, j)];
+                let c = cell_index(i, j);
+                self.height[c] = (self.height[c] - dt / CELL_SIZE * divergence).max(0.0);
+            }
+        }
+    }
+
+    /// Accelerates the water down the height gradient and damps it.
+    fn update_velocity(&mut self, dt: f32, gravity: f32, damping: f32) {
+        let decay = (1.0 - damping * dt).max(0.0);
+        for j in 0..GRID_Y {
+            for i in 0..=GRID_X {
+                let idx = u_index(i, j);
+                if i == 0 || i == GRID_X {
+                    self.u[idx] = 0.0;
+                } else {
+                    let slope = self.height[cell_index(i, j)] - self.height[cell_index(i - 1, j)];
+                    self.u[idx] = (self.u[idx] - gravity * dt * slope / CELL_SIZE) * decay;
+                }
+            }
+        }
+        for j in 0..=GRID_Y {
+            for i in 0..GRID_X {
+                let idx = v_index(i, j);
+                if j == 0 || j == GRID_Y {
+                    self.v[idx] = 0.0;
+                } else {
+                    let slope = self.height[cell_index(i, j)] - self.height[cell_index(i, j - 1)];
+                    self.v[idx] = (self.v[idx] - gravity * dt * slope / CELL_SIZE) * decay;
+                }
+            }
+        }
+    }
+
+    /// Shifts the whole surface so that the mean depth stays at rest depth.
+    fn conserve_volume(&mut self) {
+        let mean = self.height.iter().sum::<f32>() / NUM_CELLS as f32;
+        let shift = self.rest_depth - mean;
+        for h in self.height.iter_mut() {
+            *h = (*h + shift).max(0.0);
+        }
+    }
+
+    /// Raises the surface by `amount` at the given position, with a smooth
+    /// falloff to zero at the given radius.
+    pub fn add_height(&mut self, position: Vec2, radius: f32, amount: f32) {
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                let center = (Vec2::new(i as f32, j as f32) + 0.5) * CELL_SIZE;
+                let c = cell_index(i, j);
+                self.height[c] =
+                    (self.height[c] + amount * falloff(to_local(position), center, radius)).max(0.0);
+            }
+        }
+    }
+
+    /// Adds velocity to the water around the given position, with a smooth
+    /// falloff to zero at the given radius.
+    pub fn add_velocity(&mut self, position: Vec2, radius: f32, velocity: Vec2) {
+        let local = to_local(position);
+        for j in 0..GRID_Y {
+            for i in 1..GRID_X {
+                let face = Vec2::new(i as f32, j as f32 + 0.5) * CELL_SIZE;
+                self.u[u_index(i, j)] += velocity.x * falloff(local, face, radius);
+            }
+        }
+        for j in 1..GRID_Y {
+            for i in 0..GRID_X {
+                let face = Vec2::new(i as f32 + 0.5, j as f32) * CELL_SIZE;
+                self.v[v_index(i, j)] += velocity.y * falloff(local, face, radius);
+            }
+        }
+    }
+
+    /// Sets the water velocity from a velocity field, sampled on the faces.
+    pub fn set_velocities(&mut self, velocity_at: impl Fn(Vec2) -> Vec2) {
+        let origin = Vec2::new(WIDTH, HEIGHT) / 2.0;
+        for j in 0..GRID_Y {
+            for i in 1..GRID_X {
+                let face = Vec2::new(i as f32, j as f32 + 0.5) * CELL_SIZE;
+                self.u[u_index(i, j)] = velocity_at(face - origin).x;
+            }
+        }
+        for j in 1..GRID_Y {
+            for i in 0..GRID_X {
+                let face = Vec2::new(i as f32 + 0.5, j as f32) * CELL_SIZE;
+                self.v[v_index(i, j)] = velocity_at(face - origin).y;
+            }
+        }
+    }
+
+    /// Returns the water height at the given position.
+    pub fn get_height_at(&self, position: Vec2) -> f32 {
+        sample(&self.height, GRID_X, GRID_Y, CENTER_OFFSET, to_local(position))
+    }
+
+    /// Returns the gradient of the water surface at the given position.
+    pub fn get_slope_at(&self, position: Vec2) -> Vec2 {
+        let dx = Vec2::new(CELL_SIZE, 0.0);
+        let dy = Vec2::new(0.0, CELL_SIZE);
+        Vec2::new(
+            self.get_height_at(position + dx) - self.get_height_at(position - dx),
+            self.get_height_at(position + dy) - self.get_height_at(position - dy),
+        ) / (2.0 * CELL_SIZE)
+    }
+
+    /// Returns the depth-averaged velocity at the given position.
+    pub fn get_velocity_at(&self, position: Vec2) -> Vec2 {
+        self.velocity_local(to_local(position))
+    }
+
+    fn velocity_local(&self, local: Vec2) -> Vec2 {
+        Vec2::new(
+            sample(&self.u, GRID_X + 1, GRID_Y, U_OFFSET, local),
+            sample(&self.v, GRID_X, GRID_Y + 1, V_OFFSET, local),
+        )
+    }
+
+    /// Returns the total volume of water in the pool.
+    pub fn volume(&self) -> f32 {
+        self.height.iter().sum::<f32>() * CELL_SIZE * CELL_SIZE
+    }
+
+    /// Returns the cell buffer for the height field shader. `x` is the height
+    /// above rest depth, and `y`/`z` the cell-centered velocity.
+    pub fn get_cells(&self) -> [Vec4; NUM_CELLS] {
+        let mut cells = [Vec4::ZERO; NUM_CELLS];
+        for j in 0..GRID_Y {
+            for i in 0..GRID_X {
+                let c = cell_index(i, j);
+                let u = 0.5 * (self.u[u_index(i, j)] + self.u[u_index(i + 1, j)]);
+                let v = 0.5 * (self.v[v_index(i, j)] + self.v[v_index(i, j + 1)]);
+                cells[c] = Vec4::new(self.height[c] - self.rest_depth, u, v, 0.0);
+            }
+        }
+        cells
+    }
+}
+
+const U_OFFSET: Vec2 = Vec2::new(0.0, 0.5);
+const V_OFFSET: Vec2 = Vec2::new(0.5, 0.0);
+const CENTER_OFFSET: Vec2 = Vec2::new(0.5, 0.5);
+
+fn cell_index(i: usize, j: usize) -> usize {
+    i + j * GRID_X
+}
+
+fn u_index(i: usize, j: usize) -> usize {
+    i + j * (GRID_X + 1)
+}
+
+fn v_index(i: usize, j: usize) -> usize {
+    i + j * GRID_X
+}
+
+fn to_local(position: Vec2) -> Vec2 {
+    position + Vec2::new(WIDTH, HEIGHT) / 2.0
+}
+
+fn falloff(center: Vec2, point: Vec2, radius: f32) -> f32 {
+    let t = (center.distance(point) / radius).min(1.0);
+    0.5 * (1.0 + (t * std::f32::consts::PI).cos())
+}
+
+/// Bilinearly samples a field of `nx` by `ny` values whose first sample sits
+/// `offset` cells from the origin.
+fn sample(field: &[f32], nx: usize, ny: usize, offset: Vec2, local: Vec2) -> f32 {
+    let g = local / CELL_SIZE - offset;
+    let i0 = (g.x.floor() as i32).clamp(0, nx as i32 - 2) as usize;
+    let j0 = (g.y.floor() as i32).clamp(0, ny as i32 - 2) as usize;
+    let fx = (g.x - i0 as f32).clamp(0.0, 1.0);
+    let fy = (g.y - j0 as f32).clamp(0.0, 1.0);
+    let at = |i: usize, j: usize| field[i + j * nx];
+    (1.0 - fy) * ((1.0 - fx) * at(i0, j0) + fx * at(i0 + 1, j0))
+        + fy * ((1.0 - fx) * at(i0, j0 + 1) + fx * at(i0 + 1, j0 + 1))
+}
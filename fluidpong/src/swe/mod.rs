@@ -0,0 +1,103 @@
+pub mod fluid;
+mod pongfluid;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{AsBindGroup, ShaderRef},
+    sprite::Material2d,
+};
+
+use crate::{
+    backend::{field::VelocityField, FluidBackend},
+    simui::FluidSimVars,
+    snapshot::{FluidState, SnapshotError},
+    swe::fluid::*,
+};
+
+impl FluidBackend for Fluid {
+    const NAME: &'static str = "swe";
+    type Material = HeightFieldMaterial;
+
+    fn simvars() -> Vec<(&'static str, f32)> {
+        vec![
+            ("rest_depth", 10.0),
+            ("gravity", 1000.0),
+            ("damping", 0.2),
+            ("substeps", 2.0),
+            ("interact_force", 0.5),
+        ]
+    }
+
+    fn new(simvars: &FluidSimVars) -> Self {
+        Self::new(simvars.get("rest_depth"))
+    }
+
+    fn reset(&mut self, simvars: &FluidSimVars) {
+        self.reset(simvars.get("rest_depth"));
+    }
+
+    fn step(&mut self, dt: f32, simvars: &FluidSimVars) {
+        let substeps = (simvars.get("substeps") as u32).max(1);
+        let dt = dt / substeps as f32;
+        for _ in 0..substeps {
+            self.step(dt, simvars.get("gravity"), simvars.get("damping"));
+        }
+    }
+
+    fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        self.get_velocity_at(position)
+    }
+
+    fn interact(&mut self, position: Vec2, drag: Vec2, pressed: bool, simvars: &FluidSimVars) {
+        if pressed {
+            self.add_height(position, 20.0, simvars.get("interact_force"));
+            self.add_velocity(position, 20.0, drag);
+        }
+    }
+
+    fn seed_velocity(&mut self, field: &VelocityField) {
+        self.set_velocities(|position| field.sample(position));
+    }
+
+    fn save_state(&self) -> FluidState {
+        FluidState::default()
+            .with("height", self.height.iter().copied())
+            .with("u", self.u.iter().copied())
+            .with("v", self.v.iter().copied())
+    }
+
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError> {
+        self.height.copy_from_slice(state.get_len("height", NUM_CELLS)?);
+        self.u.copy_from_slice(state.get_len("u", (GRID_X + 1) * GRID_Y)?);
+        self.v.copy_from_slice(state.get_len("v", GRID_X * (GRID_Y + 1))?);
+        Ok(())
+    }
+
+    fn material(&self) -> HeightFieldMaterial {
+        HeightFieldMaterial {
+            screen_size: Vec2::new(WIDTH, HEIGHT),
+            grid_size: Vec2::new(GRID_X as f32, GRID_Y as f32),
+            cells: self.get_cells(),
+        }
+    }
+
+    fn update_material(&self, material: &mut HeightFieldMaterial, _simvars: &FluidSimVars) {
+        material.cells = self.get_cells();
+    }
+}
+
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct HeightFieldMaterial {
+    #[uniform(0)]
+    pub(crate) screen_size: Vec2,
+    #[uniform(1)]
+    pub(crate) grid_size: Vec2,
+    #[uniform(2)]
+    pub(crate) cells: [Vec4; NUM_CELLS],
+}
+
+impl Material2d for HeightFieldMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/heightfield.wgsl".into()
+    }
+}
@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+
+use crate::backend::FIXED_TIMESTEP;
+use crate::pong::level::Obstacle;
+use crate::pong::pongfluid::{wall_points, wave_points, PongFluid};
+
+const BALL_VELOCITY: f32 = 0.05;
+const BALL_RADIUS: f32 = 10.0;
+const PADDLE_VELOCITY: f32 = 0.05;
+const PADDLE_RADIUS: f32 = 25.0;
+const EMIT_SWELL: f32 = 0.3;
+const EMIT_VELOCITY: f32 = 20.0;
+const EMIT_RADIUS: f32 = 30.0;
+const SUCTION_DEPTH: f32 = 0.1;
+const SUCTION_RADIUS: f32 = 40.0;
+const WALL_SWELL: f32 = 1.0;
+const WAVE_SWELL: f32 = 0.3;
+const FLUID_ON_BALL_VELOCITY: f32 = 0.001;
+const FLUID_ON_BALL_SLOPE: f32 = 1.0;
+
+impl PongFluid for crate::swe::fluid::Fluid {
+    fn apply_emit_force(&mut self, position: Vec2, velocity: Vec2) {
+        let direction = velocity.normalize_or_zero();
+        self.add_height(position + direction * EMIT_RADIUS, EMIT_RADIUS, EMIT_SWELL * velocity.length());
+        self.add_velocity(position, EMIT_RADIUS, velocity * EMIT_VELOCITY);
+    }
+    fn apply_paddle_force(&mut self, position: Vec2, velocity: Vec2) {
+        if velocity == Vec2::ZERO {
+            return;
+        }
+        self.add_velocity(position, PADDLE_RADIUS, velocity / FIXED_TIMESTEP * PADDLE_VELOCITY);
+    }
+    fn apply_ball_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_velocity(position, BALL_RADIUS, velocity / FIXED_TIMESTEP * BALL_VELOCITY);
+    }
+    fn apply_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.set_obstacles(obstacles);
+    }
+    // The ball rolls on the surface, so the abilities shape it.
+    fn apply_suction(&mut self, position: Vec2, strength: f32) {
+        self.add_height(position, SUCTION_RADIUS, -SUCTION_DEPTH * strength);
+    }
+    fn apply_wall(&mut self, position: Vec2, direction: Vec2) {
+        for point in wall_points(position, direction) {
+            self.add_height(point, EMIT_RADIUS, WALL_SWELL * direction.length());
+        }
+    }
+    fn apply_wave(&mut self, position: Vec2, direction: Vec2) {
+        for point in wave_points(position.x) {
+            self.add_height(point, EMIT_RADIUS, WAVE_SWELL * direction.length());
+            self.add_velocity(point, EMIT_RADIUS, direction * EMIT_VELOCITY);
+        }
+    }
+    fn get_fluid_force_at(&self, position: Vec2, _velocity: Vec2) -> Vec2 {
+        // The ball is carried by the current and rolls down the slopes of waves.
+        self.get_velocity_at(position) * FLUID_ON_BALL_VELOCITY
+            - self.get_slope_at(position) * FLUID_ON_BALL_SLOPE
+    }
+}
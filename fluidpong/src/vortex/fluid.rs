@@ -0,0 +1,559 @@
+use std::f32::consts::PI;
+
+use bevy::ecs::component::Component;
+use bevy::math::{Vec2, Vec4};
+use rayon::prelude::*;
+
+use crate::pong::level::{self, Obstacle};
+use crate::{GAME_HEIGHT, GAME_WIDTH};
+
+pub const WALL_X: f32 = GAME_WIDTH / 2.0;
+pub const WALL_Y: f32 = GAME_HEIGHT / 2.0;
+
+/// The most blobs alive at once. New blobs replace the oldest ones.
+pub const MAX_BLOBS: usize = 1024;
+
+/// Resolution of the coarse grid used to approximate far-away blobs.
+const FAR_GRID_X: usize = 16;
+const FAR_GRID_Y: usize = 8;
+
+/// Resolution of the velocity field handed to `ns::FluidGridMaterial`.
+pub const RENDER_GRID_X: usize = 64;
+pub const RENDER_GRID_Y: usize = 32;
+
+/// How long the panels lining the obstacles are at most, and how many of its
+/// lengths away a panel is treated as a single source.
+const PANEL_LENGTH: f32 = 8.0;
+const FAR_PANELS: f32 = 4.0;
+/// How near a wall a panel has to be to be mirrored across it. Farther away,
+/// the image of a whole obstacle's sources nearly cancels out.
+const IMAGE_RANGE: f32 = 64.0;
+
+const CELL_VELOCITY_SCALE: f32 = 20.0;
+const CELL_MAX_SPEED: f32 = 300.0;
+const CELL_MAX_OPACITY: f32 = 0.7;
+
+/// A Lagrangian vortex blob: a point of circulation smoothed over a core.
+#[derive(Clone, Copy, Debug)]
+pub struct Blob {
+    pub position: Vec2,
+    pub circulation: f32,
+}
+
+/// The blobs in one cell of the far-field grid, lumped into a single blob at
+/// their circulation-weighted centroid.
+#[derive(Clone, Copy, Debug, Default)]
+struct Cluster {
+    position: Vec2,
+    circulation: f32,
+}
+
+/// A piece of an obstacle's edge, covered in sources whose strength keeps
+/// the flow from crossing the middle of the piece.
+#[derive(Clone, Debug)]
+struct Panel {
+    midpoint: Vec2,
+    /// The direction out of the obstacle.
+    normal: Vec2,
+    /// The panel and its mirror images across the walls it is near.
+    images: Vec<Sheet>,
+}
+
+/// A straight line of sources.
+#[derive(Clone, Copy, Debug)]
+struct Sheet {
+    start: Vec2,
+    middle: Vec2,
+    along: Vec2,
+    length: f32,
+}
+
+impl Sheet {
+    fn new(start: Vec2, end: Vec2) -> Self {
+        let along = end - start;
+        let middle = (start + end) / 2.0;
+        Sheet { start, middle, along: along.normalize(), length: along.length() }
+    }
+}
+
+impl Panel {
+    fn new(start: Vec2, end: Vec2, normal: Vec2) -> Self {
+        let midpoint = (start + end) / 2.0;
+        let mut images = vec![Sheet::new(start, end)];
+        for wall in [WALL_X, -WALL_X] {
+            if (wall - midpoint.x).abs() < IMAGE_RANGE {
+                let mirror = |p: Vec2| Vec2::new(2.0 * wall - p.x, p.y);
+                images.push(Sheet::new(mirror(start), mirror(end)));
+            }
+        }
+        for wall in [WALL_Y, -WALL_Y] {
+            if (wall - midpoint.y).abs() < IMAGE_RANGE {
+                let mirror = |p: Vec2| Vec2::new(p.x, 2.0 * wall - p.y);
+                images.push(Sheet::new(mirror(start), mirror(end)));
+            }
+        }
+        Panel { midpoint, normal, images }
+    }
+
+    /// Where the flow across the panel is cancelled, just off its middle so
+    /// that it is on the outside of the sources.
+    fn collocation(&self) -> Vec2 {
+        self.midpoint + self.normal * 0.01
+    }
+}
+
+/// An inviscid fluid represented by vortex blobs.
+///
+/// Velocity is not stored but induced by the blobs through the regularized
+/// Biot-Savart law, and the blobs are carried along by that velocity. Since
+/// nothing is resampled onto a grid, swirls keep their strength until they are
+/// pushed out by newer blobs. The arena walls are modelled with one mirror
+/// image per wall. Blobs more than a cell away are approximated by the
+/// clusters of a coarse grid, which keeps the cost of a step close to linear.
+///
+/// Obstacles are lined with panels of sources. Around every step, the
+/// sources' strengths are solved for so that no flow crosses the middle of any
+/// panel, which turns the flow around the obstacles. Blobs are also kept out
+/// of them.
+#[derive(Component)]
+pub struct Fluid {
+    blobs: Vec<Blob>,
+    next: usize,
+    core_radius: f32,
+    clusters: Vec<Cluster>,
+    cluster_blobs: Vec<Vec<usize>>,
+    obstacles: Vec<Obstacle>,
+    panels: Vec<Panel>,
+    /// The inverse of how strongly each panel's sources push across every
+    /// panel, row-major, which turns the flow across the panels into the
+    /// sources' strengths.
+    panel_inverse: Vec<f32>,
+    strengths: Vec<f32>,
+}
+
+impl Fluid {
+    pub fn new(core_radius: f32) -> Fluid {
+        Fluid {
+            blobs: Vec::with_capacity(MAX_BLOBS),
+            next: 0,
+            core_radius,
+            clusters: vec![Cluster::default(); FAR_GRID_X * FAR_GRID_Y],
+            cluster_blobs: vec![Vec::new(); FAR_GRID_X * FAR_GRID_Y],
+            obstacles: Vec::new(),
+            panels: Vec::new(),
+            panel_inverse: Vec::new(),
+            strengths: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self, core_radius: f32) {
+        *self = Fluid::new(core_radius);
+    }
+
+    /// Adds a blob, replacing the oldest one once `MAX_BLOBS` are alive. The
+    /// obstacles turn its flow from the next step.
+    pub fn add_blob(&mut self, blob: Blob) {
+        let position = level::push_out_of(&self.obstacles, blob.position, 0.0).0;
+        let blob = Blob { position: clamp_to_walls(position), ..blob };
+        if self.blobs.len() < MAX_BLOBS {
+            self.blobs.push(blob);
+        } else {
+            self.blobs[self.next] = blob;
+        }
+        self.next = (self.next + 1) % MAX_BLOBS;
+        self.rebuild_clusters();
+    }
+
+    /// Adds a counter-rotating pair of blobs `separation` apart that pushes
+    /// fluid through `position` along `direction`.
+    pub fn add_vortex_pair(
+        &mut self,
+        position: Vec2,
+        direction: Vec2,
+        circulation: f32,
+        separation: f32,
+    ) {
+        let normal = direction.normalize_or_zero().perp() * separation / 2.0;
+        self.add_blob(Blob { position: position + normal, circulation });
+        self.add_blob(Blob { position: position - normal, circulation: -circulation });
+    }
+
+    /// Replaces the blobs with ones sampled from a vorticity field, given as
+    /// row-major cell centers and their vorticity. Neighboring cells are
+    /// merged until the blobs fit in `MAX_BLOBS`.
+    pub fn set_vorticity(&mut self, vorticity: &[(Vec2, f32)], cell_area: f32) {
+        let mut blobs: Vec<Blob> = (vorticity.iter())
+            .map(|(position, w)| Blob { position: *position, circulation: w * cell_area })
+            .collect();
+        while blobs.len() > MAX_BLOBS {
+            blobs = (blobs.chunks(2))
+                .map(|pair| Blob {
+                    position: pair.iter().map(|b| b.position).sum::<Vec2>() / pair.len() as f32,
+                    circulation: pair.iter().map(|b| b.circulation).sum(),
+                })
+                .collect();
+        }
+        blobs.retain(|blob| blob.circulation != 0.0);
+        self.next = blobs.len() % MAX_BLOBS;
+        self.blobs = blobs;
+        self.rebuild_clusters();
+        self.solve_sources();
+    }
+
+    /// Sets the obstacles the flow goes around and the blobs are kept out of.
+    pub fn set_obstacles(&mut self, obstacles: &[Obstacle]) {
+        if self.obstacles == obstacles {
+            return;
+        }
+        self.obstacles = obstacles.to_vec();
+        // Panels along the walls or inside another obstacle have nothing to
+        // hold back.
+        let in_arena = |point: Vec2| point.x.abs() < WALL_X - 0.5 && point.y.abs() < WALL_Y - 0.5;
+        let in_other = |panel: &Panel, own: usize| {
+            (obstacles.iter().enumerate())
+                .any(|(o, other)| o != own && other.contains(panel.midpoint))
+        };
+        self.panels = (obstacles.iter().enumerate())
+            .flat_map(|(o, obstacle)| line_with_panels(obstacle).into_iter().map(move |p| (o, p)))
+            .filter(|(o, panel)| in_arena(panel.midpoint) && !in_other(panel, *o))
+            .map(|(_, panel)| panel)
+            .collect();
+        let n = self.panels.len();
+        let mut influence = vec![0.0; n * n];
+        for (i, panel) in self.panels.iter().enumerate() {
+            for (j, other) in self.panels.iter().enumerate() {
+                let velocity = sheet_induced(panel.collocation(), other, 1.0);
+                influence[i * n + j] = panel.normal.dot(velocity) as f64;
+            }
+        }
+        self.panel_inverse = invert(influence, n);
+        self.solve_sources();
+    }
+
+    /// Advances the blobs with the midpoint method.
+    pub fn step(&mut self, dt: f32) {
+        // Blobs added since the last step change what the sources hold back.
+        // They are only solved for here, since blobs are added many at a time.
+        self.solve_sources();
+        let midpoints: Vec<Vec2> = (self.blobs.par_iter())
+            .map(|blob| clamp_to_walls(blob.position + 0.5 * dt * self.velocity_at(blob.position)))
+            .collect();
+        let velocities: Vec<Vec2> =
+            midpoints.par_iter().map(|position| self.velocity_at(*position)).collect();
+        for (blob, velocity) in self.blobs.iter_mut().zip(velocities) {
+            let position = level::push_out_of(&self.obstacles, blob.position + dt * velocity, 0.0);
+            blob.position = clamp_to_walls(position.0);
+        }
+        self.rebuild_clusters();
+        self.solve_sources();
+    }
+
+    /// Returns the velocity at the given point, summing nearby blobs directly
+    /// and far ones through their clusters.
+    pub fn velocity_at(&self, point: Vec2) -> Vec2 {
+        self.blob_velocity_at(point) + self.source_velocity_at(point)
+    }
+
+    /// Returns the velocity at the given point, summing every blob directly.
+    pub fn velocity_at_exact(&self, point: Vec2) -> Vec2 {
+        let blobs = (self.blobs.iter())
+            .map(|blob| self.induced(point, blob.position, blob.circulation))
+            .sum::<Vec2>();
+        blobs + self.source_velocity_at(point)
+    }
+
+    fn blob_velocity_at(&self, point: Vec2) -> Vec2 {
+        let (ci, cj) = cluster_coords(point);
+        let mut velocity = Vec2::ZERO;
+        for j in 0..FAR_GRID_Y {
+            for i in 0..FAR_GRID_X {
+                let c = i + j * FAR_GRID_X;
+                if ci.abs_diff(i) <= 1 && cj.abs_diff(j) <= 1 {
+                    for &b in &self.cluster_blobs[c] {
+                        let blob = self.blobs[b];
+                        velocity += self.induced(point, blob.position, blob.circulation);
+                    }
+                } else if self.clusters[c].circulation != 0.0 {
+                    let cluster = self.clusters[c];
+                    velocity += self.induced(point, cluster.position, cluster.circulation);
+                }
+            }
+        }
+        velocity
+    }
+
+    fn source_velocity_at(&self, point: Vec2) -> Vec2 {
+        if self.panels.is_empty() {
+            return Vec2::ZERO;
+        }
+        (self.panels.iter().zip(&self.strengths))
+            .map(|(panel, strength)| sheet_induced(point, panel, *strength))
+            .sum()
+    }
+
+    /// Sets the sources' strengths to cancel the blobs' flow across the
+    /// panels.
+    fn solve_sources(&mut self) {
+        let n = self.panels.len();
+        let across: Vec<f32> = (self.panels.par_iter())
+            .map(|panel| -panel.normal.dot(self.blob_velocity_at(panel.collocation())))
+            .collect();
+        self.strengths = (self.panel_inverse.chunks(n.max(1)))
+            .take(n)
+            .map(|row| row.iter().zip(&across).map(|(a, b)| a * b).sum())
+            .collect();
+    }
+
+    /// Returns the velocity induced at `point` by a blob and its mirror images
+    /// across the four walls.
+    fn induced(&self, point: Vec2, position: Vec2, circulation: f32) -> Vec2 {
+        let images = [
+            (position, circulation),
+            (Vec2::new(2.0 * WALL_X - position.x, position.y), -circulation),
+            (Vec2::new(-2.0 * WALL_X - position.x, position.y), -circulation),
+            (Vec2::new(position.x, 2.0 * WALL_Y - position.y), -circulation),
+            (Vec2::new(position.x, -2.0 * WALL_Y - position.y), -circulation),
+        ];
+        let sigma2 = self.core_radius * self.core_radius;
+        (images.iter())
+            .map(|(position, circulation)| {
+                let r = point - *position;
+                circulation / (2.0 * PI) * r.perp() / (r.length_squared() + sigma2)
+            })
+            .sum()
+    }
+
+    fn rebuild_clusters(&mut self) {
+        for blobs in self.cluster_blobs.iter_mut() {
+            blobs.clear();
+        }
+        let mut weights = vec![0.0; self.clusters.len()];
+        let mut centroids = vec![Vec2::ZERO; self.clusters.len()];
+        let mut circulations = vec![0.0; self.clusters.len()];
+        for (b, blob) in self.blobs.iter().enumerate() {
+            let (i, j) = cluster_coords(blob.position);
+            let c = i + j * FAR_GRID_X;
+            self.cluster_blobs[c].push(b);
+            weights[c] += blob.circulation.abs();
+            centroids[c] += blob.circulation.abs() * blob.position;
+            circulations[c] += blob.circulation;
+        }
+        for (c, cluster) in self.clusters.iter_mut().enumerate() {
+            cluster.circulation = circulations[c];
+            cluster.position = if weights[c] > 0.0 { centroids[c] / weights[c] } else { Vec2::ZERO };
+        }
+    }
+
+    /// Returns the total circulation of the blobs, which the method conserves.
+    pub fn total_circulation(&self) -> f32 {
+        self.blobs.iter().map(|blob| blob.circulation).sum()
+    }
+
+    /// Returns a reference to the blobs in the fluid.
+    pub fn blobs(&self) -> &[Blob] {
+        &self.blobs
+    }
+
+    /// Returns the blobs from oldest to newest.
+    pub fn blobs_by_age(&self) -> impl Iterator<Item = &Blob> {
+        self.blobs[self.next..].iter().chain(&self.blobs[..self.next])
+    }
+
+    /// Replaces the blobs with the given ones, oldest first. Only the newest
+    /// `MAX_BLOBS` are kept.
+    pub fn set_blobs(&mut self, blobs: &[Blob]) {
+        let blobs = &blobs[blobs.len().saturating_sub(MAX_BLOBS)..];
+        self.blobs = (blobs.iter())
+            .map(|blob| Blob { position: clamp_to_walls(blob.position), ..*blob })
+            .collect();
+        self.next = self.blobs.len() % MAX_BLOBS;
+        self.rebuild_clusters();
+        self.solve_sources();
+    }
+
+    /// Returns the velocity field sampled on the render grid, padded to the
+    /// size of `ns::FluidGridMaterial`. Opacity grows with speed.
+    pub fn get_cells(&self) -> [Vec4; crate::ns::fluid::NUM_CELLS] {
+        let size = Vec2::new(2.0 * WALL_X / RENDER_GRID_X as f32, 2.0 * WALL_Y / RENDER_GRID_Y as f32);
+        let velocities: Vec<Vec2> = (0..RENDER_GRID_X * RENDER_GRID_Y)
+            .into_par_iter()
+            .map(|c| {
+                let cell = Vec2::new((c % RENDER_GRID_X) as f32, (c / RENDER_GRID_X) as f32);
+                self.velocity_at((cell + 0.5) * size - Vec2::new(WALL_X, WALL_Y))
+            })
+            .collect();
+
+        let mut cells = [Vec4::ZERO; crate::ns::fluid::NUM_CELLS];
+        for (cell, velocity) in cells.iter_mut().zip(velocities) {
+            let opacity = (velocity.length() / CELL_MAX_SPEED).min(1.0) * CELL_MAX_OPACITY;
+            let velocity = velocity * CELL_VELOCITY_SCALE;
+            *cell = Vec4::new(velocity.x, velocity.y, opacity, 0.0);
+        }
+        cells
+    }
+}
+
+fn cluster_coords(position: Vec2) -> (usize, usize) {
+    let i = ((position.x + WALL_X) / (2.0 * WALL_X) * FAR_GRID_X as f32) as i32;
+    let j = ((position.y + WALL_Y) / (2.0 * WALL_Y) * FAR_GRID_Y as f32) as i32;
+    (i.clamp(0, FAR_GRID_X as i32 - 1) as usize, j.clamp(0, FAR_GRID_Y as i32 - 1) as usize)
+}
+
+/// Returns the velocity induced at `point` by a panel of sources of the given
+/// strength per unit length, and by its mirror images.
+fn sheet_induced(point: Vec2, panel: &Panel, strength: f32) -> Vec2 {
+    (panel.images.iter())
+        .map(|sheet| {
+            // From afar, the panel is as good as one source in its middle.
+            let r = point - sheet.middle;
+            let r2 = r.length_squared();
+            if r2 > FAR_PANELS * FAR_PANELS * sheet.length * sheet.length {
+                return strength * sheet.length / (2.0 * PI) * r / r2;
+            }
+            // In the panel's frame, with x along it from the start.
+            let to_start = point - sheet.start;
+            let x1 = to_start.dot(sheet.along);
+            let x2 = x1 - sheet.length;
+            let y = sheet.along.perp_dot(to_start);
+            // Kept off the ends, where the flow along the panel is infinite.
+            let (r1, r2) = (x1 * x1 + y * y + 1e-2, x2 * x2 + y * y + 1e-2);
+            let u = strength / (4.0 * PI) * (r1 / r2).ln();
+            let v = strength / (2.0 * PI) * (y.atan2(x2) - y.atan2(x1));
+            u * sheet.along + v * sheet.along.perp()
+        })
+        .sum()
+}
+
+/// Splits the edge of an obstacle into panels at most `PANEL_LENGTH` long.
+fn line_with_panels(obstacle: &Obstacle) -> Vec<Panel> {
+    let corners = match obstacle {
+        Obstacle::Rect { center, size } => {
+            let half = *size / 2.0;
+            [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                .map(|(x, y)| *center + Vec2::new(x, y) * half)
+                .to_vec()
+        }
+        Obstacle::Circle { center, radius } => {
+            let n = ((2.0 * PI * radius / PANEL_LENGTH).ceil() as usize).max(8);
+            (0..n)
+                .map(|k| *center + *radius * Vec2::from_angle(2.0 * PI * k as f32 / n as f32))
+                .collect()
+        }
+        Obstacle::Polygon { points } => points.clone(),
+    };
+    let mut panels = Vec::new();
+    for (k, &start) in corners.iter().enumerate() {
+        let along = corners[(k + 1) % corners.len()] - start;
+        let Some(mut normal) = along.perp().try_normalize() else {
+            continue;
+        };
+        // Out is the way away from the obstacle.
+        let middle = start + along / 2.0;
+        if obstacle.distance(middle + normal).0 < obstacle.distance(middle - normal).0 {
+            normal = -normal;
+        }
+        let count = (along.length() / PANEL_LENGTH).ceil() as usize;
+        panels.extend((0..count).map(|p| {
+            let (from, to) = (p as f32 / count as f32, (p + 1) as f32 / count as f32);
+            Panel::new(start + along * from, start + along * to, normal)
+        }));
+    }
+    panels
+}
+
+/// Inverts an `n` by `n` row-major matrix by Gauss-Jordan elimination. The
+/// columns of a singular matrix that have no pivot are left out, so that
+/// their sources stay off.
+fn invert(mut matrix: Vec<f64>, n: usize) -> Vec<f32> {
+    let mut inverse = vec![0.0; n * n];
+    for i in 0..n {
+        inverse[i * n + i] = 1.0;
+    }
+    let mut singular = Vec::new();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|a, b| matrix[a * n + col].abs().total_cmp(&matrix[b * n + col].abs()))
+            .unwrap_or(col);
+        if matrix[pivot * n + col].abs() < 1e-12 {
+            singular.push(col);
+            continue;
+        }
+        for k in 0..n {
+            matrix.swap(col * n + k, pivot * n + k);
+            inverse.swap(col * n + k, pivot * n + k);
+        }
+        let scale = 1.0 / matrix[col * n + col];
+        for k in 0..n {
+            matrix[col * n + k] *= scale;
+            inverse[col * n + k] *= scale;
+        }
+        for row in (0..n).filter(|row| *row != col) {
+            let factor = matrix[row * n + col];
+            if factor == 0.0 {
+                continue;
+            }
+            for k in 0..n {
+                matrix[row * n + k] -= factor * matrix[col * n + k];
+                inverse[row * n + k] -= factor * inverse[col * n + k];
+            }
+        }
+    }
+    for col in singular {
+        inverse[col * n..(col + 1) * n].fill(0.0);
+    }
+    inverse.into_iter().map(|value| value as f32).collect()
+}
+
+fn clamp_to_walls(position: Vec2) -> Vec2 {
+    Vec2::new(position.x.clamp(-WALL_X, WALL_X), position.y.clamp(-WALL_Y, WALL_Y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The average flow across the edge of `obstacle` in the arena, sampled
+    /// just outside it between the middles of the panels as well as on them.
+    fn flow_across(fluid: &Fluid, obstacle: &Obstacle) -> f32 {
+        let mut samples = Vec::new();
+        for panel in line_with_panels(obstacle) {
+            for offset in [-0.25, 0.0, 0.25] {
+                let along = panel.normal.perp() * offset * PANEL_LENGTH;
+                let point = panel.midpoint + along + panel.normal;
+                if point.x.abs() < WALL_X && point.y.abs() < WALL_Y {
+                    samples.push(panel.normal.dot(fluid.velocity_at_exact(point)).abs());
+                }
+            }
+        }
+        samples.iter().sum::<f32>() / samples.len() as f32
+    }
+
+    #[test]
+    fn flow_goes_around_obstacles() {
+        let obstacles = [
+            Obstacle::Circle { center: Vec2::new(0.0, 30.0), radius: 30.0 },
+            Obstacle::Rect { center: Vec2::new(0.0, -50.0), size: Vec2::new(16.0, 50.0) },
+            Obstacle::Polygon {
+                points: vec![
+                    Vec2::new(-40.0, -160.0),
+                    Vec2::new(40.0, -160.0),
+                    Vec2::new(0.0, -125.0),
+                ],
+            },
+        ];
+        let mut open = Fluid::new(8.0);
+        // A jet pushing straight at the obstacles.
+        open.add_vortex_pair(Vec2::new(-60.0, 0.0), Vec2::X, 20000.0, 40.0);
+        open.add_vortex_pair(Vec2::new(-60.0, -120.0), Vec2::X, 20000.0, 40.0);
+        let mut blocked = Fluid::new(8.0);
+        blocked.set_blobs(open.blobs());
+        blocked.set_obstacles(&obstacles);
+        for obstacle in &obstacles {
+            let before = flow_across(&open, obstacle);
+            let after = flow_across(&blocked, obstacle);
+            // What is left crosses near the corners, around which the flow
+            // turns too sharply for the panels to follow.
+            assert!(after < before / 3.0, "{obstacle:?} let {after} of {before} through");
+        }
+    }
+}
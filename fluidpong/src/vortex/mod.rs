@@ -0,0 +1,91 @@
+pub mod fluid;
+mod pongfluid;
+
+use bevy::prelude::*;
+
+use crate::{
+    backend::{
+        field::{VelocityField, CELL_SIZE},
+        FluidBackend,
+    },
+    ns::FluidGridMaterial,
+    simui::FluidSimVars,
+    snapshot::{FluidState, SnapshotError},
+    vortex::fluid::{Blob, Fluid, RENDER_GRID_X, RENDER_GRID_Y, WALL_X, WALL_Y},
+};
+
+impl FluidBackend for Fluid {
+    const NAME: &'static str = "vortex";
+    type Material = FluidGridMaterial;
+
+    fn simvars() -> Vec<(&'static str, f32)> {
+        vec![("core_radius", 8.0), ("interact_force", 50.0)]
+    }
+
+    fn new(simvars: &FluidSimVars) -> Self {
+        Self::new(simvars.get("core_radius"))
+    }
+
+    fn reset(&mut self, simvars: &FluidSimVars) {
+        self.reset(simvars.get("core_radius"));
+    }
+
+    fn step(&mut self, dt: f32, _simvars: &FluidSimVars) {
+        self.step(dt);
+    }
+
+    fn sample_velocity(&self, position: Vec2) -> Vec2 {
+        self.velocity_at(position)
+    }
+
+    fn interact(&mut self, position: Vec2, drag: Vec2, pressed: bool, simvars: &FluidSimVars) {
+        if pressed && drag != Vec2::ZERO {
+            let strength = drag.length() * simvars.get("interact_force");
+            self.add_vortex_pair(position, drag, strength, 20.0);
+        }
+    }
+
+    fn seed_velocity(&mut self, field: &VelocityField) {
+        self.set_vorticity(&field.curl(), CELL_SIZE * CELL_SIZE);
+    }
+
+    fn save_state(&self) -> FluidState {
+        let blobs: Vec<&Blob> = self.blobs_by_age().collect();
+        FluidState::default()
+            .with_vec2s("position", blobs.iter().map(|blob| blob.position))
+            .with("circulation", blobs.iter().map(|blob| blob.circulation))
+    }
+
+    fn load_state(&mut self, state: &FluidState) -> Result<(), SnapshotError> {
+        let circulation = state.get("circulation")?;
+        let position = state.get_vec2s("position", circulation.len())?;
+        let blobs: Vec<Blob> = (position.into_iter().zip(circulation))
+            .map(|(position, circulation)| Blob { position, circulation: *circulation })
+            .collect();
+        self.set_blobs(&blobs);
+        Ok(())
+    }
+
+    fn material(&self) -> FluidGridMaterial {
+        FluidGridMaterial {
+            screen_size: Vec2::new(WALL_X * 2.0, WALL_Y * 2.0),
+            grid_size: Vec2::new(RENDER_GRID_X as f32, RENDER_GRID_Y as f32),
+            cells: self.get_cells(),
+        }
+    }
+
+    fn update_material(&self, material: &mut FluidGridMaterial, _simvars: &FluidSimVars) {
+        material.cells = self.get_cells();
+    }
+
+    fn draw_gizmos(&self, gizmos: &mut Gizmos, _simvars: &FluidSimVars) {
+        for blob in self.blobs() {
+            let color = if blob.circulation > 0.0 {
+                Color::rgba(1.0, 0.5, 0.5, 0.5)
+            } else {
+                Color::rgba(0.5, 0.5, 1.0, 0.5)
+            };
+            gizmos.circle_2d(blob.position, 2.0, color);
+        }
+    }
+}
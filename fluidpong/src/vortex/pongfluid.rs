@@ -0,0 +1,40 @@
+use bevy::prelude::*;
+
+use crate::backend::FIXED_TIMESTEP;
+use crate::pong::level::Obstacle;
+use crate::pong::pongfluid::PongFluid;
+use crate::vortex::fluid::Blob;
+
+const EMIT_CIRCULATION: f32 = 2000.0;
+const EMIT_SEPARATION: f32 = 20.0;
+const PADDLE_CIRCULATION: f32 = 20.0;
+const PADDLE_HALF_WIDTH: f32 = 5.0;
+const FLUID_ON_BALL_VELOCITY: f32 = 0.05;
+
+impl PongFluid for crate::vortex::fluid::Fluid {
+    fn apply_emit_force(&mut self, position: Vec2, velocity: Vec2) {
+        self.add_vortex_pair(position, velocity, EMIT_CIRCULATION * velocity.length(), EMIT_SEPARATION);
+    }
+    fn apply_paddle_force(&mut self, position: Vec2, velocity: Vec2) {
+        // A moving paddle sheds vorticity from its flat faces.
+        if velocity.y != 0.0 {
+            let circulation = velocity.y * PADDLE_CIRCULATION;
+            let offset = Vec2::new(PADDLE_HALF_WIDTH, 0.0);
+            self.add_blob(Blob { position: position + offset, circulation: -circulation });
+            self.add_blob(Blob { position: position - offset, circulation });
+        }
+    }
+    fn apply_ball_force(&mut self, _position: Vec2, _velocity: Vec2) {
+        // The ball is too small to shed vorticity the blobs can resolve, so it
+        // only rides the flow.
+    }
+    fn apply_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.set_obstacles(obstacles);
+    }
+    fn apply_vortex(&mut self, position: Vec2, strength: f32) {
+        self.add_blob(Blob { position, circulation: EMIT_CIRCULATION * strength });
+    }
+    fn get_fluid_force_at(&self, position: Vec2, _velocity: Vec2) -> Vec2 {
+        self.velocity_at_exact(position) * FIXED_TIMESTEP * FLUID_ON_BALL_VELOCITY
+    }
+}